@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
@@ -12,17 +13,21 @@ pub mod tag_list_screen;
 pub mod tag_edit_screen;
 pub mod configs_screen;
 pub mod file_action_screen;
+pub mod diagnostics_screen;
+pub mod duplicates_screen;
 
 use crate::log::notification::Notification;
-use crate::tagging::Tag;
+use crate::tagging::{id::TagID, Tag};
 use crate::widget::notification_card::NotificationCard;
-use crate::{configs, error, info, trace, ToPrettyString};
+use crate::{archive, configs, error, ignore_rules, info, trace, ToPrettyString};
 
 use main_screen::MainScreen;
 use tag_edit_screen::TagEditScreen;
 use tag_list_screen::TagListScreen;
 
 use self::configs_screen::ConfigsScreen;
+use self::diagnostics_screen::DiagnosticsScreen;
+use self::duplicates_screen::DuplicatesScreen;
 use self::file_action_screen::FileActionScreen;
 
 
@@ -74,12 +79,18 @@ pub enum Message {
     CloseNotification(usize),
     Notify(Notification),
     OpenPath(PathBuf),
+    /// Some tags failed to load, at the given paths
+    /// See [`crate::tagging::TagLoadResult::report_errors`]
+    TagsFailedToLoad(Vec<PathBuf>),
 
     SwitchToMainScreen,
     SwitchToTagListScreen,
     SwitchToTagEditScreen(Tag),
     SwitchToConfigScreen,
     SwitchToFileActionScreen(Vec<PathBuf>),
+    SwitchToDiagnosticsScreen,
+    /// Look for duplicates under the given tags right away, if any
+    SwitchToDuplicatesScreen(Vec<TagID>),
 }
 
 
@@ -90,18 +101,26 @@ pub struct KFiles {
     notifications: Vec<Notification>,
     has_focus: Option<Instant>,
     hovered_files: FileHoverState,
+    /// Tag paths that failed to load and were already shown in the [`DiagnosticsScreen`]
+    /// Avoids opening it over and over for the same errors
+    reported_load_failures: HashSet<PathBuf>,
 }
 
 impl Application for KFiles {
     type Executor = iced::executor::Default;
     type Message = Message;
-    type Flags = ();
+    /// Query to start with
+    type Flags = Option<String>;
     type Theme = Theme;
 
-    fn new(_flags: Self::Flags) -> (Self, Command<Self::Message>) {
+    fn new(initial_query: Self::Flags) -> (Self, Command<Self::Message>) {
         trace!("Program started");
     
-        let (main_screen, command) = MainScreen::new();
+        let (mut main_screen, mut command) = MainScreen::new();
+        if let Some(query) = initial_query {
+            main_screen.set_query_input(query);
+            command = Command::batch(vec![command, main_screen.restart_search()]);
+        }
 
         (
             KFiles {
@@ -109,6 +128,7 @@ impl Application for KFiles {
                 notifications: Vec::new(),
                 has_focus: None,
                 hovered_files: FileHoverState::None,
+                reported_load_failures: HashSet::new(),
             },
             Command::batch(vec![
                 iced::font::load(iced_aw::BOOTSTRAP_FONT_BYTES).map(Message::IconsFontLoaded),
@@ -154,6 +174,12 @@ impl Application for KFiles {
             }
 
             Message::SwitchToMainScreen => {
+                // The global ignore file may have been edited from the configs screen
+                if matches!(self.current_screen, Screen::Configs(_)) {
+                    if let Err(err) = ignore_rules::reload_global_patterns() {
+                        error!("Failed to reload global ignore file:\n {err:?}");
+                    }
+                }
                 let (main_screen, command) = MainScreen::new();
                 self.current_screen = Screen::Main(main_screen);
                 command
@@ -183,6 +209,32 @@ impl Application for KFiles {
                 command
             },
 
+            Message::SwitchToDiagnosticsScreen => {
+                let (diagnostics_screen, command) = DiagnosticsScreen::new();
+                self.current_screen = Screen::Diagnostics(diagnostics_screen);
+                command
+            }
+
+            Message::SwitchToDuplicatesScreen(tags) => {
+                let (duplicates_screen, command) = DuplicatesScreen::new(tags);
+                self.current_screen = Screen::Duplicates(duplicates_screen);
+                command
+            }
+
+            Message::TagsFailedToLoad(paths) => {
+                let mut is_new = false;
+                for path in paths {
+                    is_new |= self.reported_load_failures.insert(path);
+                }
+
+                if !is_new {
+                    return Command::none();
+                }
+                let (diagnostics_screen, command) = DiagnosticsScreen::new();
+                self.current_screen = Screen::Diagnostics(diagnostics_screen);
+                command
+            }
+
             Message::CloseNotification(index) => {
                 if index < self.notifications.len() {
                     self.notifications.remove(index);
@@ -393,6 +445,22 @@ impl KFiles {
             return Command::none();
         }
 
+        // Paths inside archives need to be extracted first
+        if archive::is_member_path(path) {
+            trace!("[KFiles::open_path()] Extracting path \"{}\"", path.display());
+            let path = path.to_path_buf();
+            return Command::perform(
+                async move { archive::extract_member(&path).map_err(|err| (path, err)) },
+                |res| match res {
+                    Ok(extracted) => Message::OpenPath(extracted),
+                    Err((path, err)) => Message::Notify(error!(
+                        notify;
+                        "Failed to extract \"{}\":\n{}", path.to_pretty_string(), err
+                    )),
+                }
+            );
+        }
+
         trace!("[KFiles::open_path()] Opening path \"{}\"", path.display());
         let Err(err) = opener::open(path) else {
             return Command::none();
@@ -457,6 +525,8 @@ pub enum ScreenMessage {
     TagEdit(tag_edit_screen::Message),
     Configs(configs_screen::Message),
     FileAction(file_action_screen::Message),
+    Diagnostics(diagnostics_screen::Message),
+    Duplicates(duplicates_screen::Message),
 }
 
 impl From<ScreenMessage> for Message {
@@ -473,12 +543,17 @@ enum Screen {
     TagEdit(TagEditScreen),
     Configs(ConfigsScreen),
     FileAction(FileActionScreen),
+    Diagnostics(DiagnosticsScreen),
+    Duplicates(DuplicatesScreen),
 }
 
 impl Screen {
     fn tick(&mut self) -> Command<Message> {
         match self {
             Self::Main(main) => main.tick(),
+            Self::TagList(tag_list) => tag_list.tick(),
+            Self::TagEdit(tag_edit) => tag_edit.tick(),
+            Self::Duplicates(duplicates) => duplicates.tick(),
             _ => Command::none(),
         }
     }
@@ -504,6 +579,14 @@ impl Screen {
             ScreenMessage::FileAction(message) => if let Screen::FileAction(file_action) = self {
                 return file_action.update(message);
             }
+
+            ScreenMessage::Diagnostics(message) => if let Screen::Diagnostics(diagnostics) = self {
+                return diagnostics.update(message);
+            }
+
+            ScreenMessage::Duplicates(message) => if let Screen::Duplicates(duplicates) = self {
+                return duplicates.update(message);
+            }
         }
 
         Command::none()
@@ -516,6 +599,8 @@ impl Screen {
             Screen::TagEdit(tag_edit) => tag_edit.view(),
             Screen::Configs(configs) => configs.view(),
             Screen::FileAction(file_action) => file_action.view(),
+            Screen::Diagnostics(diagnostics) => diagnostics.view(),
+            Screen::Duplicates(duplicates) => duplicates.view(),
         }
     }
 
@@ -526,6 +611,8 @@ impl Screen {
             Screen::TagEdit(tag_edit) => tag_edit.handle_event(event, status),
             Screen::Configs(configs) => configs.handle_event(event, status),
             Screen::FileAction(file_action) => file_action.handle_event(event, status),
+            Screen::Diagnostics(diagnostics) => diagnostics.handle_event(event, status),
+            Screen::Duplicates(duplicates) => duplicates.handle_event(event, status),
         }
     }
 }
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 
 use iced::event::Status;
 use iced::widget::{
-    button, column, container, row, scrollable, text, Column, Container, Row, Slider, Text
+    button, checkbox, column, container, pick_list, row, scrollable, text, Column, Container, Row, Slider, Text
 };
 use iced::{Color, Command, Element, Event, Length};
 
@@ -12,7 +12,8 @@ use iced_aw::widgets::NumberInput;
 
 use crate::app::Message as AppMessage;
 use crate::configs::{self, Configs};
-use crate::{ error, icon, info, log, send_message, simple_button, thumbnail, ToPrettyString, VERSION };
+use crate::strmatch::CaseMode;
+use crate::{ error, icon, ignore_rules, info, kind, log, send_message, simple_button, thumbnail, ToPrettyString, VERSION };
 
 // IDs
 // const THUMBNAIL_CACHE_INPUT_ID: fn() -> text_input::Id = || { text_input::Id::new("thumbnail_cache_size_input") };
@@ -117,6 +118,12 @@ pub enum Message {
     ThumbnailThreadCountInput(u8),
     ThumbnailUpdateProbInput(f32),
     ThumbnailCheckCountInput(u32),
+    RespectIgnoreFilesToggled(bool),
+    BrowseArchivesToggled(bool),
+    SearchThreadCountInput(u8),
+    MaxContentSearchSizeInput(u64),
+    CaseModeSelected(CaseMode),
+    OpenGlobalIgnoreFile,
     OpenConfigsDir,
     OpenLogsDir,
 }
@@ -216,6 +223,56 @@ impl ConfigsScreen {
                 self.configs.thumbnail_check_count = input;
             }
 
+            Message::RespectIgnoreFilesToggled(input) => {
+                self.is_dirty = true;
+                self.configs.respect_ignore_files = input;
+            }
+
+            Message::BrowseArchivesToggled(input) => {
+                self.is_dirty = true;
+                self.configs.browse_archives = input;
+            }
+
+            Message::SearchThreadCountInput(input) => {
+                self.is_dirty = true;
+                self.configs.search_thread_count = input;
+            }
+
+            Message::MaxContentSearchSizeInput(input) => {
+                self.is_dirty = true;
+                self.configs.max_content_search_size = input;
+            }
+
+            Message::CaseModeSelected(input) => {
+                self.is_dirty = true;
+                self.configs.case_mode = input;
+            }
+
+            Message::OpenGlobalIgnoreFile => {
+                // Make sure the file exists before opening it
+                if let Err(err) = ignore_rules::reload_global_patterns() {
+                    return send_message!(notif = error!(
+                        notify, log_context = "ConfigsScreen::update() => OpenGlobalIgnoreFile";
+                        "Failed to load global ignore file:\n{:?}", err
+                    ));
+                }
+
+                let path: PathBuf = match ignore_rules::get_global_ignore_path() {
+                    Ok(p) => p,
+                    Err(err) => return send_message!(notif = error!(
+                        notify, log_context = "ConfigsScreen::update() => OpenGlobalIgnoreFile";
+                        "Failed to get global ignore file path:\n{err:?}"
+                    )),
+                };
+
+                if let Err(err) = opener::open(&path) {
+                    return send_message!(notif = error!(
+                        notify, log_context = "ConfigsScreen::update() => OpenGlobalIgnoreFile";
+                        "Failed to open \"{}\":\n{:?}", path.to_pretty_string(), err
+                    ));
+                }
+            }
+
             Message::OpenConfigsDir => {
                 let path: PathBuf = match configs::get_save_path() {
                     Ok(p) => p,
@@ -327,6 +384,31 @@ impl ConfigsScreen {
                         .into()
                 ),
 
+                // SEARCH THREAD COUNT
+                config_entry(
+                    "Search thread count",
+                    desc_text("How many threads to use for searching through tagged folders.\nSet to 0 to use one thread per CPU core").into(),
+                    Some(default.search_thread_count.to_string()),
+                    number_input!(c.search_thread_count, u8, SearchThreadCountInput) .into()
+                ),
+
+                // MAX CONTENT SEARCH SIZE
+                config_entry(
+                    "Max content search size",
+                    desc_text("Files bigger than this many bytes are skipped when searching inside files with `content:`").into(),
+                    Some(format!("{} bytes", default.max_content_search_size)),
+                    number_input!(c.max_content_search_size, u64, MaxContentSearchSizeInput) .into()
+                ),
+
+                // CASE MODE
+                config_entry(
+                    "Case sensitivity",
+                    desc_text("Whether letter case matters when searching. `smart` only minds it if the query has uppercase letters.
+Queries can override this with `case:sensitive`, `case:insensitive` or `case:smart`").into(),
+                    Some(default.case_mode.to_string()),
+                    pick_list(CaseMode::ALL, Some(c.case_mode), |v| Message::CaseModeSelected(v).into()) .into()
+                ),
+
                 // THUMBNAIL CACHE SIZE
                 config_entry(
                     "Thumbnail cache size",
@@ -380,6 +462,46 @@ Setting this number higher may increase building speed, but could be wasteful if
                         .into()
                 ),
 
+                // IGNORE RULES
+                config_entry(
+                    "Ignore rules",
+                    column![
+                        button("Open global ignore file") .on_press(Message::OpenGlobalIgnoreFile.into()),
+                        desc_text("Gitignore-style patterns for paths to skip when searching through tagged folders.
+Tags can also have their own patterns. Add `--all` to a query to search through ignored paths anyway"),
+                    ]
+                    .spacing(4)
+                    .into(),
+                    Some(default.respect_ignore_files.to_string()),
+                    checkbox("Respect .gitignore and .ignore files", c.respect_ignore_files)
+                        .on_toggle(|v| Message::RespectIgnoreFilesToggled(v).into())
+                        .into()
+                ),
+
+                // BROWSE ARCHIVES
+                config_entry(
+                    "Browse archives",
+                    desc_text("Whether to also search through the files inside .zip, .tar, .tar.gz and .7z archives.
+Opening a file inside an archive extracts it to a temporary folder first").into(),
+                    Some(default.browse_archives.to_string()),
+                    checkbox("Search inside archives", c.browse_archives)
+                        .on_toggle(|v| Message::BrowseArchivesToggled(v).into())
+                        .into()
+                ),
+
+                // FILE KINDS
+                config_row(
+                    "File kinds",
+                    column![
+                        text(kind::table().kind_names().collect::<Vec<&str>>().join(", ")),
+                        desc_text("Kinds of files that can be searched for with `kind:`, e.g. `kind:image`.
+Add extensions to them, or new kinds, with `kind_extensions` in the configs file, e.g. `{ \"code\": [\"nim\"], \"3d\": [\"blend\"] }`.
+Changes apply after a restart"),
+                    ]
+                    .spacing(4)
+                    .into()
+                ),
+
                 // MISCELLANEOUS
                 // TODO use iced_aw::Grid
                 config_row(
@@ -408,6 +530,10 @@ Setting this number higher may increase building speed, but could be wasteful if
     fn save(&mut self) -> Command<AppMessage> {
         self.is_dirty = false;
 
+        if let Err(err) = ignore_rules::reload_global_patterns() {
+            error!("[ConfigsScreen::save()] Failed to reload global ignore file:\n {:?}", err);
+        }
+
         *configs::global() = self.configs.clone();
         if let Err(err) = self.configs.save() {
             return send_message!(notif = error!(
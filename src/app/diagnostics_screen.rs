@@ -0,0 +1,285 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use iced::event::Status;
+use iced::widget::container::Appearance;
+use iced::widget::{button, column, container, horizontal_space, row, scrollable, text, tooltip, Column, Container};
+use iced::{Alignment, Color, Command, Element, Event, Font, Length};
+
+use iced_aw::Bootstrap;
+
+use crate::app::Message as AppMessage;
+use crate::tagging::{self, fsck, LoadError, TagLoadResult};
+use crate::widget::tag_entry::ENTRY_COLOR;
+use crate::{ error, icon, info, send_message, simple_button, ToPrettyString };
+
+use super::theme::{ERROR_COLOR, WARNING_COLOR};
+
+
+const CONTAINER_APPEARANCE: fn() -> Appearance = || {
+    Appearance::default()
+        .with_background(Color::new(0.1, 0.1, 0.15, 1.0))
+};
+
+
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Reload,
+    OpenTagsDir,
+    RestoreBackup(usize),
+    Quarantine(usize),
+}
+
+impl From<Message> for AppMessage {
+    fn from(value: Message) -> AppMessage {
+        AppMessage::Screen(super::ScreenMessage::Diagnostics(value))
+    }
+}
+
+
+
+/// A tag file that failed to load
+#[derive(Debug)]
+struct Failure {
+    path: PathBuf,
+    error: LoadError,
+    /// For parse errors, the offending line in the file
+    line: Option<String>,
+    has_backup: bool,
+}
+
+impl Failure {
+    fn new(path: PathBuf, error: LoadError) -> Self {
+        let line = match &error {
+            LoadError::ParseError(err) => fs::read_to_string(&path).ok()
+                .and_then(|contents| contents.lines().nth(err.line).map(|l| l.to_string())),
+            _ => None,
+        };
+        let has_backup = tagging::get_backup_path(&path)
+            .is_some_and(|pb| pb.is_file());
+
+        Failure {
+            path,
+            error,
+            line,
+            has_backup,
+        }
+    }
+}
+
+
+
+/// Lists every tag that failed to load, and why
+#[derive(Debug)]
+pub struct DiagnosticsScreen {
+    /// Error while reading the tags dir itself, if any
+    dir_error: Option<String>,
+    failures: Vec<Failure>,
+}
+
+impl DiagnosticsScreen {
+    pub fn new() -> (Self, Command<AppMessage>) {
+        let mut screen = DiagnosticsScreen {
+            dir_error: None,
+            failures: Vec::new(),
+        };
+        screen.reload();
+        (screen, Command::none())
+    }
+
+    fn reload(&mut self) {
+        let load_res = tagging::load_tags();
+        if let Some(lines) = load_res.log_errors_verbose::<Vec<String>>() {
+            for line in lines {
+                error!("[DiagnosticsScreen::reload()] {}", line);
+            }
+        }
+
+        match load_res {
+            TagLoadResult::IO(err) => {
+                self.dir_error = Some(err.to_string());
+                self.failures.clear();
+            }
+
+            TagLoadResult::Ok(tags, errors) => {
+                tagging::set_tags_cache(tags);
+                self.dir_error = None;
+                self.failures = errors.into_iter()
+                    .map(|(path, err)| Failure::new(path, err))
+                    .collect();
+                self.failures.sort_by(|a, b| a.path.cmp(&b.path));
+            }
+        }
+    }
+
+    pub fn update(&mut self, message: Message) -> Command<AppMessage> {
+        match message {
+            Message::Reload => {
+                self.reload();
+            }
+
+            Message::OpenTagsDir => {
+                let path: PathBuf = tagging::get_save_dir();
+                if let Err(err) = opener::open(&path) {
+                    return send_message!(notif = error!(
+                        notify, log_context = "DiagnosticsScreen::update() => OpenTagsDir";
+                        "Failed to open {}:\n{}", path.to_pretty_string(), err
+                    ));
+                }
+            }
+
+            Message::RestoreBackup(index) => {
+                let Some(failure) = self.failures.get(index) else {
+                    return Command::none();
+                };
+                let path = failure.path.clone();
+
+                let notif = match tagging::restore_backup(&path) {
+                    Ok(()) => info!(
+                        notify, log_context = "DiagnosticsScreen::update() => RestoreBackup";
+                        "Restored \"{}\" from backup", path.to_pretty_string()
+                    ),
+                    Err(err) => error!(
+                        notify, log_context = "DiagnosticsScreen::update() => RestoreBackup";
+                        "Failed to restore \"{}\" from backup:\n{}", path.to_pretty_string(), err
+                    ),
+                };
+                self.reload();
+                return send_message!(notif = notif);
+            }
+
+            Message::Quarantine(index) => {
+                let Some(failure) = self.failures.get(index) else {
+                    return Command::none();
+                };
+                let path = failure.path.clone();
+
+                let notif = match fsck::quarantine(&path) {
+                    Ok(dest) => info!(
+                        notify, log_context = "DiagnosticsScreen::update() => Quarantine";
+                        "Moved \"{}\" to \"{}\"", path.to_pretty_string(), dest.to_pretty_string()
+                    ),
+                    Err(err) => error!(
+                        notify, log_context = "DiagnosticsScreen::update() => Quarantine";
+                        "Failed to quarantine \"{}\":\n{}", path.to_pretty_string(), err
+                    ),
+                };
+                self.reload();
+                return send_message!(notif = notif);
+            }
+        }
+
+        Command::none()
+    }
+
+    pub fn view(&self) -> Element<AppMessage> {
+        column![
+            row![
+                // Back arrow
+                simple_button!(icon = Bootstrap::ArrowLeft)
+                    .on_press(AppMessage::SwitchToMainScreen),
+                text("Tag diagnostics") .size(24),
+                horizontal_space(),
+                tooltip(
+                    simple_button!(icon = Bootstrap::ArrowClockwise) .on_press(Message::Reload.into()),
+                    "Reload tags",
+                    tooltip::Position::Bottom
+                ),
+                tooltip(
+                    simple_button!(icon = Bootstrap::Folder) .on_press(Message::OpenTagsDir.into()),
+                    "Open tags directory",
+                    tooltip::Position::Bottom
+                ),
+            ]
+            .align_items(Alignment::Center),
+
+            self.view_failures(),
+        ]
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .into()
+    }
+
+    fn view_failures(&self) -> Container<AppMessage> {
+        if let Some(err) = &self.dir_error {
+            return container(
+                text(format!("Failed to read the tags directory:\n{}", err)) .style(ERROR_COLOR)
+            )
+            .padding(12.0);
+        }
+
+        if self.failures.is_empty() {
+            return container(text("All tags loaded successfully"))
+                .padding(12.0);
+        }
+
+        container(scrollable(
+            column(self.failures.iter().enumerate().map(|(i, failure)|
+                view_failure(i, failure).into()
+            ))
+            .width(Length::Fill)
+            .padding(12.0)
+            .spacing(12.0)
+        ))
+    }
+
+    pub fn handle_event(&mut self, _event: Event, _status: Status) -> Command<AppMessage> {
+        Command::none()
+    }
+}
+
+
+fn view_failure(index: usize, failure: &Failure) -> Container<AppMessage> {
+    let path: &Path = &failure.path;
+
+    let details: Column<AppMessage> = match &failure.error {
+        LoadError::ParseError(err) => column![
+            text(format!("Failed to parse at line {}, column {}:", err.line + 1, err.col + 1)),
+            text(&err.msg) .style(ERROR_COLOR),
+        ]
+        .push_maybe(failure.line.as_ref().map(|line| column![
+            text(line) .font(Font::MONOSPACE) .style(ENTRY_COLOR),
+            // Caret under the offending column
+            text(format!("{}^", " ".repeat(err.col))) .font(Font::MONOSPACE) .style(WARNING_COLOR),
+        ])),
+
+        err => column![ text(err.to_string()) .style(ERROR_COLOR) ],
+    };
+
+    container(column![
+        row![
+            icon!(Bootstrap::ExclamationCircleFill, ERROR_COLOR),
+            text(path.to_pretty_string()),
+        ]
+        .spacing(8)
+        .align_items(Alignment::Center),
+
+        details.spacing(4),
+
+        row![
+            button("Open file")
+                .on_press(AppMessage::OpenPath(path.to_path_buf())),
+            tooltip(
+                button("Restore backup")
+                    .on_press_maybe(failure.has_backup.then_some(Message::RestoreBackup(index).into())),
+                if failure.has_backup {
+                    "Replace the file with its last working version"
+                } else {
+                    "No backup found"
+                },
+                tooltip::Position::Bottom
+            ),
+            tooltip(
+                button("Quarantine") .on_press(Message::Quarantine(index).into()),
+                "Move the file out of the tags directory, so that it stops being loaded",
+                tooltip::Position::Bottom
+            ),
+        ]
+        .spacing(8),
+    ]
+    .spacing(8))
+    .style( CONTAINER_APPEARANCE() )
+    .width(Length::Fill)
+    .padding(12.0)
+}
@@ -0,0 +1,339 @@
+use std::collections::HashSet;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use iced::event::Status;
+use iced::widget::container::Appearance;
+use iced::widget::{button, column, container, horizontal_space, row, scrollable, text, tooltip, Column, Container};
+use iced::{Alignment, Color, Command, Element, Event, Length};
+use iced_aw::{Bootstrap, Spinner, Wrap};
+
+use crate::app::Message as AppMessage;
+use crate::tagging::duplicates::{self, DuplicateFinder, DuplicateGroup};
+use crate::tagging::stats::format_size;
+use crate::tagging::{self, id::TagID, Tag};
+use crate::{configs, error, icon, info, send_message, simple_button, trash, ToPrettyString};
+
+
+const CONTAINER_APPEARANCE: fn() -> Appearance = || {
+    Appearance::default()
+        .with_background(Color::new(0.1, 0.1, 0.15, 1.0))
+};
+
+
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    ToggleTag(TagID),
+    Scan,
+    Cancel,
+    Reveal(PathBuf),
+    /// Move a path to the trash, given the indexes of its group and of itself in the group
+    Trash(usize, usize),
+    /// Keep only one path of a group and untag the rest, given the same indexes as [`Message::Trash`]
+    KeepOnly(usize, usize),
+}
+
+impl From<Message> for AppMessage {
+    fn from(value: Message) -> AppMessage {
+        AppMessage::Screen(super::ScreenMessage::Duplicates(value))
+    }
+}
+
+
+
+/// Finds files with identical contents under the selected tags
+/// See [`duplicates::find_duplicates`]
+#[derive(Debug)]
+pub struct DuplicatesScreen {
+    tags_cache: Vec<TagID>,
+    selected_tags: Vec<TagID>,
+    finder: DuplicateFinder,
+    /// `None` until the first scan finishes
+    groups: Option<Vec<DuplicateGroup>>,
+}
+
+impl DuplicatesScreen {
+    pub fn new(selected_tags: Vec<TagID>) -> (Self, Command<AppMessage>) {
+        let load_res = tagging::load_tags();
+        let command = load_res.report_errors();
+        tagging::set_tags_cache( load_res.get_tags().unwrap_or_default() );
+        let tags_cache: Vec<TagID> = tagging::tags_cache()
+            .iter()
+            .map(|t| t.id.clone())
+            .collect();
+
+        let mut screen = DuplicatesScreen {
+            tags_cache,
+            selected_tags,
+            finder: DuplicateFinder::new(),
+            groups: None,
+        };
+        if !screen.selected_tags.is_empty() {
+            screen.scan();
+        }
+        (screen, command)
+    }
+
+    pub fn tick(&mut self) -> Command<AppMessage> {
+        if let Some(groups) = self.finder.update() {
+            self.groups = Some(groups);
+        }
+        Command::none()
+    }
+
+    fn scan(&mut self) {
+        let tags: Vec<Tag> = tagging::tags_cache().iter()
+            .filter(|tag| self.selected_tags.contains(&tag.id))
+            .cloned()
+            .collect();
+        self.finder.find(&tags, configs::global().get_search_thread_count());
+    }
+
+    /// IDs of the selected tags and all their subtags, which the scan looks through
+    fn get_scanned_tag_ids(&self) -> HashSet<TagID> {
+        tagging::tags_cache().iter()
+            .filter(|tag| self.selected_tags.contains(&tag.id))
+            .flat_map(|tag| iter::once(tag.id.clone()).chain(tag.iter_all_subtags().map(|t| t.id)))
+            .collect()
+    }
+
+    pub fn update(&mut self, message: Message) -> Command<AppMessage> {
+        match message {
+            Message::ToggleTag(tag_id) => {
+                if let Some(index) = self.selected_tags.iter().position(|id| *id == tag_id) {
+                    self.selected_tags.remove(index);
+                } else {
+                    self.selected_tags.push(tag_id);
+                }
+            }
+
+            Message::Scan => {
+                self.scan();
+            }
+
+            Message::Cancel => {
+                self.finder.cancel();
+            }
+
+            Message::Reveal(path) => {
+                if let Err(err) = opener::reveal(&path) {
+                    return send_message!(notif = error!(
+                        notify, log_context = "DuplicatesScreen::update() => Reveal";
+                        "Failed to reveal {}:\n{}", path.to_pretty_string(), err
+                    ));
+                }
+            }
+
+            Message::Trash(group_index, index) => {
+                let Some(path) = self.get_path(group_index, index).map(Path::to_path_buf) else {
+                    return Command::none();
+                };
+
+                if let Err(err) = trash::move_to_trash(&path) {
+                    return send_message!(notif = error!(
+                        notify, log_context = "DuplicatesScreen::update() => Trash";
+                        "Failed to move \"{}\" to the trash:\n{}", path.to_pretty_string(), err
+                    ));
+                }
+
+                if let Some(groups) = &mut self.groups {
+                    groups[group_index].paths.remove(index);
+                    if groups[group_index].paths.len() < 2 {
+                        groups.remove(group_index);
+                    }
+                }
+
+                // Entries have to exist
+                let mut tags: Vec<Tag> = tagging::tags_cache().clone();
+                let res = duplicates::remove_entry_from_all(&path, &mut tags);
+                tagging::set_tags_cache(tags);
+                if let Err(err) = res {
+                    return send_message!(notif = error!(
+                        notify, log_context = "DuplicatesScreen::update() => Trash";
+                        "Moved \"{}\" to the trash, but failed to untag it:\n{}", path.to_pretty_string(), err
+                    ));
+                }
+
+                return send_message!(notif = info!(
+                    notify, log_context = "DuplicatesScreen::update() => Trash";
+                    "Moved \"{}\" to the trash", path.to_pretty_string()
+                ));
+            }
+
+            Message::KeepOnly(group_index, index) => {
+                if self.get_path(group_index, index).is_none() {
+                    return Command::none();
+                }
+                let Some(groups) = self.groups.as_mut() else {
+                    return Command::none();
+                };
+
+                let mut group = groups.remove(group_index);
+                let kept = group.paths.remove(index);
+
+                // Only untag the copies from the tags that were looked through
+                let scanned_tag_ids = self.get_scanned_tag_ids();
+                let mut all_tags: Vec<Tag> = tagging::tags_cache().clone();
+                let mut tags: Vec<Tag> = all_tags.iter()
+                    .filter(|tag| scanned_tag_ids.contains(&tag.id))
+                    .cloned()
+                    .collect();
+                let res = duplicates::untag_paths(&group.paths, &mut tags);
+                for tag in tags {
+                    if let Some(old) = all_tags.iter_mut().find(|t| t.id == tag.id) {
+                        *old = tag;
+                    }
+                }
+                tagging::set_tags_cache(all_tags);
+
+                let notif = match res {
+                    Ok(changed_count) => info!(
+                        notify, log_context = "DuplicatesScreen::update() => KeepOnly";
+                        "Kept \"{}\" and untagged {} copies from {} tags",
+                        kept.to_pretty_string(), group.paths.len(), changed_count
+                    ),
+                    Err(err) => error!(
+                        notify, log_context = "DuplicatesScreen::update() => KeepOnly";
+                        "Failed to untag the copies of \"{}\":\n{}", kept.to_pretty_string(), err
+                    ),
+                };
+                return send_message!(notif = notif);
+            }
+        }
+
+        Command::none()
+    }
+
+    fn get_path(&self, group_index: usize, index: usize) -> Option<&Path> {
+        self.groups.as_ref()?
+            .get(group_index)?
+            .paths.get(index)
+            .map(PathBuf::as_path)
+    }
+
+    pub fn view(&self) -> Element<'_, AppMessage> {
+        let is_busy: bool = self.finder.is_busy();
+
+        column![
+            row![
+                // Back arrow
+                simple_button!(icon = Bootstrap::ArrowLeft)
+                    .on_press(AppMessage::SwitchToTagListScreen),
+                text("Duplicates") .size(24),
+                horizontal_space(),
+            ]
+            .align_items(Alignment::Center),
+
+            // Tags to look through
+            Wrap::with_elements(self.tags_cache.iter().map(|id| {
+                let is_selected = self.selected_tags.contains(id);
+                button( text(id).size(14) )
+                    .on_press( Message::ToggleTag(id.clone()).into() )
+                    .style(if is_selected { iced::theme::Button::Primary } else { iced::theme::Button::Secondary })
+                    .padding([2, 8])
+                    .into()
+            }).collect())
+            .spacing(4.0)
+            .line_spacing(4.0),
+
+            if is_busy {
+                row![
+                    Spinner::new(),
+                    text("Looking for duplicates..."),
+                    button("Cancel") .on_press(Message::Cancel.into()),
+                ]
+            } else {
+                row![
+                    button("Find duplicates")
+                        .on_press_maybe((!self.selected_tags.is_empty()).then_some(Message::Scan.into())),
+                ]
+            }
+            .spacing(8)
+            .align_items(Alignment::Center),
+
+            self.view_groups(),
+        ]
+        .spacing(8)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .into()
+    }
+
+    fn view_groups(&self) -> Container<'_, AppMessage> {
+        let Some(groups) = &self.groups else {
+            return container(text("Pick the tags to look through"))
+                .padding(12.0);
+        };
+
+        if groups.is_empty() {
+            return container(text("No duplicates found"))
+                .padding(12.0);
+        }
+
+        let wasted_size: u64 = groups.iter().map(DuplicateGroup::wasted_size).sum();
+        container(column![
+            text(format!("{} groups of duplicates, {} could be freed", groups.len(), format_size(wasted_size))),
+
+            scrollable(
+                column(groups.iter().enumerate().map(|(i, group)|
+                    view_group(i, group).into()
+                ))
+                .width(Length::Fill)
+                .spacing(12.0)
+            ),
+        ]
+        .spacing(8.0))
+        .padding(12.0)
+    }
+
+    pub fn handle_event(&mut self, _event: Event, _status: Status) -> Command<AppMessage> {
+        Command::none()
+    }
+}
+
+
+fn view_group(group_index: usize, group: &DuplicateGroup) -> Container<'_, AppMessage> {
+    let paths = Column::with_children(group.paths.iter().enumerate().map(|(i, path)| {
+        row![
+            text(path.to_pretty_string()),
+            horizontal_space(),
+            tooltip(
+                simple_button!(icon = Bootstrap::Folder)
+                    .on_press(Message::Reveal(path.clone()).into()),
+                "Reveal in file explorer",
+                tooltip::Position::Left
+            ),
+            tooltip(
+                simple_button!(icon = Bootstrap::BookmarkCheck)
+                    .on_press(Message::KeepOnly(group_index, i).into()),
+                "Keep this one and untag the other copies",
+                tooltip::Position::Left
+            ),
+            tooltip(
+                simple_button!(icon = Bootstrap::Trash)
+                    .on_press(Message::Trash(group_index, i).into()),
+                "Move to trash",
+                tooltip::Position::Left
+            ),
+        ]
+        .spacing(8)
+        .align_items(Alignment::Center)
+        .into()
+    }));
+
+    container(column![
+        row![
+            icon!(Bootstrap::Files),
+            text(format!("{} copies, {} each", group.paths.len(), format_size(group.size))),
+        ]
+        .spacing(8)
+        .align_items(Alignment::Center),
+
+        paths.spacing(4),
+    ]
+    .spacing(8))
+    .style( CONTAINER_APPEARANCE() )
+    .width(Length::Fill)
+    .padding(12.0)
+}
@@ -9,9 +9,9 @@ use iced::{Color, Command, Element, Event, Length};
 use iced_aw::widgets::Grid;
 use iced_aw::{ grid_row, Bootstrap, Card, Wrap};
 
-use crate::log::notification::Notification;
 use crate::tagging::id::TagID;
 use crate::tagging::tag::{LoadError, SaveError};
+use crate::tagging::usage::TagUsage;
 use crate::tagging::{self, tags_cache, Tag};
 use crate::{error, icon, info, log, send_message, tag_list_menu, trace, ToPrettyString};
 use crate::{app::Message as AppMessage, simple_button};
@@ -50,16 +50,14 @@ pub struct FileActionScreen {
     hovered_path: Option<PathBuf>,
     popup: Option<Popup>,
     changes: Changes,
+    /// Used to list the most used tags first
+    tag_usage: TagUsage,
 }
 
 impl FileActionScreen {
     pub fn new(selected_paths: Vec<PathBuf>) -> (Self, Command<AppMessage>) {
         let load_res = tagging::load_tags();
-        let commands: Vec<Command<AppMessage>> = load_res.log_errors::<Vec<Notification>>()
-            .unwrap_or_default()
-            .into_iter()
-            .map(|n| send_message!(notif = n))
-            .collect();
+        let command = load_res.report_errors();
 
         tagging::set_tags_cache( load_res.get_tags().unwrap_or_default() );
 
@@ -70,8 +68,9 @@ impl FileActionScreen {
                 hovered_path: None,
                 popup: None,
                 changes: Changes::new(),
+                tag_usage: TagUsage::load(),
             },
-            Command::batch(commands),
+            command,
         )
     }
 
@@ -124,6 +123,12 @@ impl FileActionScreen {
                 let changes = self.changes.apply(&self.selected_paths);
                 log!("changes = {:#?}", &changes);
 
+                let used_tags = self.changes.add_tags.iter()
+                    .chain(self.changes.remove_tags.iter());
+                if let Err(err) = TagUsage::record_and_save(used_tags) {
+                    error!("[FileActionScreen::update() => ApplyChanges] Failed to save tag usage:\n {}", err);
+                }
+
                 let files_count = self.selected_paths.len();
                 let (added_tags_count, removed_tags_count) = changes.tags.iter()
                     .flat_map(|(_, res)| res)
@@ -185,6 +190,16 @@ impl FileActionScreen {
         .into()
     }
 
+    /// Get the index of each tag in the tags cache along with its id, most used first
+    fn get_ranked_tags(&self) -> Vec<(usize, TagID)> {
+        let mut tags: Vec<(usize, TagID)> = tags_cache().iter()
+            .map(|tag| tag.id.clone())
+            .enumerate()
+            .collect();
+        self.tag_usage.sort(&mut tags, |(_, id)| id);
+        tags
+    }
+
     // TODO UN-DUPLICATE CODE
     fn view_actions_tags(&self) -> Container<AppMessage> {
         let palette = iced::theme::Palette::CATPPUCCIN_MOCHA;
@@ -242,10 +257,10 @@ impl FileActionScreen {
                 // Add button
                 tag_list_menu!(
                     button("+").on_press(AppMessage::Empty),
-                    tags_cache().iter().enumerate()
-                        .filter(|(_, tag)| !self.changes.add_tags.iter().any(|id| **tag == *id))
-                        .map(|(i, tag)| {
-                            simple_button!(text(tag.id.to_string()))
+                    self.get_ranked_tags().into_iter()
+                        .filter(|(_, tag_id)| !self.changes.add_tags.contains(tag_id))
+                        .map(|(i, tag_id)| {
+                            simple_button!(text(tag_id.to_string()))
                                 .on_press( Message::AddTag(i).into() )
                                 .into()
                         })
@@ -254,10 +269,10 @@ impl FileActionScreen {
                 // Remove button
                 tag_list_menu!(
                     button("-").on_press(AppMessage::Empty),
-                    tags_cache().iter().enumerate()
-                        .filter(|(_, tag)| !self.changes.remove_tags.iter().any(|id| **tag == *id))
-                        .map(|(i, tag)| {
-                            simple_button!(text(tag.id.to_string()))
+                    self.get_ranked_tags().into_iter()
+                        .filter(|(_, tag_id)| !self.changes.remove_tags.contains(tag_id))
+                        .map(|(i, tag_id)| {
+                            simple_button!(text(tag_id.to_string()))
                                 .on_press( Message::RemoveTag(i).into() )
                                 .into()
                         })
@@ -1,27 +1,29 @@
 use std::collections::HashSet;
+use std::cmp::Ordering;
 use std::iter;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use iced::event::Status;
 use iced::keyboard::Key;
 use iced::widget::scrollable::Viewport;
-use iced::widget::{self, button, column, container, horizontal_space, row, scrollable, text, text_input, tooltip, Column, Container};
+use iced::widget::{self, button, column, container, horizontal_space, pick_list, row, scrollable, text, text_input, tooltip, Column, Container};
 use iced::{self, keyboard, Element, Event, Length, Rectangle};
 use iced::Command;
 use iced_aw::Bootstrap;
 use rand::Rng;
 
 use crate::configs::Configs;
-use crate::log::notification::Notification;
-use crate::search::Query;
-use crate::tagging::{self, tag::Tag, id::TagID};
+use crate::ignore_rules::IgnoreRules;
+use crate::search::{index::{Indexer, Meta}, ContentMatch, Query, Sort, SortKey};
+use crate::tagging::{self, annotation::Annotation, order::TagOrder, tag::Tag, id::TagID, usage::TagUsage};
 use crate::thumbnail::{self, get_thumbnail_cache_path, ThumbnailBuilder};
 use crate::widget::file_inspector::FileInspector;
 use crate::widget::fuzzy_input::FuzzyInput;
 use crate::widget::file_list::{self, FileList};
 use crate::app::{theme, Message as AppMessage};
-use crate::{configs, error, icon, send_message, warn, ToPrettyString};
+use crate::{archive, configs, error, icon, send_message, warn, ToPrettyString};
 
 
 /// Keys that focus the query input
@@ -38,6 +40,11 @@ const ITEM_SIZE: (f32, f32) = (80.0, 120.0);
 const ITEM_SPACING: (f32, f32) = (8.0, 8.0);
 const TOTAL_ITEM_SIZE: (f32, f32) = (ITEM_SIZE.0 + ITEM_SPACING.0, ITEM_SIZE.1 + ITEM_SPACING.1);
 
+const INSPECTOR_WIDTH: f32 = 260.0;
+
+/// How often tagged folders get re-indexed
+const INDEX_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 // Ids
 const QUERY_INPUT_ID: fn() -> text_input::Id = || { text_input::Id::new("query_input") };
 const MAIN_RESULTS_ID: fn() -> container::Id = || { container::Id::new("main_results") };
@@ -49,6 +56,8 @@ pub enum Message {
     QueryTextChanged(String),
     QuerySubmit,
     ToggleQueryTag(TagID),
+    /// Same as [`Message::ToggleQueryTag`], but keeps the query text
+    PinnedTagPressed(TagID),
     RemoveQueryTag(TagID),
     QueryTagPressed(TagID),
     FocusQuery,
@@ -57,6 +66,10 @@ pub enum Message {
     EntryHovered(usize),
     EntrySelected(usize),
     EntryRightClickSelected(usize),
+    AnnotationChanged(TagID, PathBuf, Annotation),
+    CloseInspector,
+    SortSelected(SortKey),
+    ToggleSortDirection,
 }
 
 impl From<Message> for AppMessage {
@@ -69,8 +82,11 @@ impl From<Message> for AppMessage {
 
 
 
+/// A search result: its score, path, the line that matched a `content:` constraint, if any, and
+/// its metadata, if the search came across it or the results are sorted by it (see
+/// [`SortKey::needs_metadata`])
 #[derive(Debug)]
-pub struct Item(pub isize, pub PathBuf);
+pub struct Item(pub isize, pub PathBuf, pub Option<ContentMatch>, pub Option<Meta>);
 
 impl AsRef<PathBuf> for Item {
     fn as_ref(&self) -> &PathBuf {
@@ -109,6 +125,21 @@ pub struct MainScreen {
     /// TODO select multiple paths
     selected_path: Option<PathBuf>,
     tags_cache: Vec<TagID>,
+    /// Shown as chips above the query input
+    pinned_tags: Vec<TagID>,
+    /// Used to rank tag suggestions
+    tag_usage: TagUsage,
+    /// Tags typed in the query as of the last search, whose use is already recorded
+    /// So a tag gets recorded once when typed, instead of on every search it stays in
+    typed_tags: HashSet<TagID>,
+    indexer: Indexer,
+    last_indexed: Instant,
+    /// Whether the search finished without hitting [`Configs::max_result_count`]
+    /// Only then can the results be refined. See [`MainScreen::refine_search`]
+    has_all_results: bool,
+    /// Picked in the header
+    /// A `sort:` in the query takes precedence. See [`MainScreen::current_sort`]
+    sort: Sort,
 }
 
 impl MainScreen {
@@ -119,20 +150,26 @@ impl MainScreen {
         ];
 
         let load_res = tagging::load_tags();
-        commands.extend(
-            load_res.log_errors::<Vec<Notification>>()
-                .unwrap_or_default()
-                .into_iter()
-                .map(|n| send_message!(notif = n))
-        );
+        commands.push(load_res.report_errors());
         tagging::set_tags_cache( load_res.get_tags().unwrap_or_default() );
         let tags_cache: Vec<TagID> = tagging::tags_cache()
             .iter()
             .map(|t| t.id.clone())
             .collect();
 
+        let pinned_tags: Vec<TagID> = TagOrder::load().get_pinned()
+            .iter()
+            .filter(|id| tags_cache.contains(id))
+            .cloned()
+            .collect();
+
+        // Before locking the configs, which these read too
+        let index_rules = IgnoreRules::global();
         let cfg = configs::global();
 
+        let mut indexer = Indexer::new(cfg.get_search_thread_count());
+        indexer.index_tags(&tagging::tags_cache(), &index_rules);
+
         (
             MainScreen {
                 query: Query::empty(),
@@ -144,6 +181,13 @@ impl MainScreen {
                 hovered_path: None,
                 selected_path: None,
                 tags_cache,
+                pinned_tags,
+                tag_usage: TagUsage::load(),
+                typed_tags: HashSet::new(),
+                indexer,
+                last_indexed: Instant::now(),
+                has_all_results: false,
+                sort: Sort::default(),
             },
             Command::batch(commands),
         )
@@ -161,6 +205,13 @@ impl MainScreen {
 
         self.try_receive_results();
 
+        // Keep the index up to date
+        self.indexer.update();
+        if !self.indexer.is_busy() && self.last_indexed.elapsed() >= INDEX_REFRESH_INTERVAL {
+            self.indexer.index_tags(&tagging::tags_cache(), &IgnoreRules::global());
+            self.last_indexed = Instant::now();
+        }
+
         Command::none()
     }
 
@@ -169,6 +220,7 @@ impl MainScreen {
         use std::sync::mpsc::TryRecvError;
 
         let has_search = !self.query.is_empty();
+        let sort = self.current_sort();
         let rx = self.query.receiver.as_mut()?;
 
         let (max_result_count, max_this_tick) = {
@@ -189,13 +241,14 @@ impl MainScreen {
             Err(TryRecvError::Empty) => return Some(RecvItemsResult::Empty),
             Err(TryRecvError::Disconnected) => {
                 self.query.receiver = None;
+                self.has_all_results = true;
                 return Some(RecvItemsResult::Disconnected);
             }
         };
 
         // If there is no query, just append normally
         // I didn't mean for it to rhyme, I'm just low on time
-        if !has_search {
+        if !has_search && sort.key == SortKey::Score {
             self.items.push(first);
             self.items.append(&mut rx.try_iter()
                 .take(max_this_tick)
@@ -206,10 +259,11 @@ impl MainScreen {
         }
 
         // Add new items in sorted order
+        // Each one goes after those it ties with, so the order doesn't shift as results come in
         let it = iter::once(first)
             .chain( rx.try_iter().take(max_this_tick) );
         for item in it {
-            let index = self.items.partition_point(|&Item(score, _)| score > item.0);
+            let index = self.items.partition_point(|other| sort.compare(other, &item) != Ordering::Greater);
             self.items.insert(index, item);
         }
 
@@ -230,11 +284,35 @@ impl MainScreen {
     }
 
     pub fn open_first_result(&self) -> Option<Command<AppMessage>> {
-        let Item(_, path) = self.items.first()?;
+        let Item(_, path, ..) = self.items.first()?;
         let path = path.to_path_buf();
         Some(send_message!( AppMessage::OpenPath(path) ))
     }
 
+    /// Add `tag_id` to the query, or remove it if it's already there
+    /// Returns a [`Command`] with the error notification if the tag fails to load
+    fn toggle_query_tag(&mut self, tag_id: TagID) -> Result<(), Command<AppMessage>> {
+        let removed: bool = self.query.remove_tag(&tag_id);
+        // If not removed, then add it
+        if !removed {
+            let tag: Tag = match tag_id.load() {
+                Ok(t) => t,
+                Err(err) => return Err(send_message!(notif = error!(
+                    notify, log_context = "MainScreen::toggle_query_tag()";
+                    "Failed to load tag `{}`:\n{}", tag_id, err
+                ))),
+            };
+
+            self.query.add_tag(tag);
+
+            self.tag_usage.record(&tag_id);
+            if let Err(err) = self.tag_usage.save() {
+                error!("[MainScreen::toggle_query_tag()] Failed to save tag usage:\n {}", err);
+            }
+        }
+        Ok(())
+    }
+
     pub fn update(&mut self, message: Message) -> Command<AppMessage> {
         match message {
             Message::FocusQuery => {
@@ -244,7 +322,7 @@ impl MainScreen {
             Message::QueryTextChanged(new_text) => {
                 let has_changed: bool = self.set_query_input(new_text);
                 if has_changed {
-                    return self.restart_search();
+                    return self.refine_search();
                 }
             }
 
@@ -253,24 +331,21 @@ impl MainScreen {
             }
 
             Message::ToggleQueryTag(tag_id) => {
-                let removed: bool = self.query.remove_tag(&tag_id);
-                // If not removed, then add it
-                if !removed {
-                    let tag: Tag = match tag_id.load() {
-                        Ok(t) => t,
-                        Err(err) => return send_message!(notif = error!(
-                            notify, log_context = "MainScreen::update() => ToggleQueryTag";
-                            "Failed to load tag `{}`:\n{}", tag_id, err
-                        )),
-                    };
-
-                    self.query.add_tag(tag);
+                if let Err(command) = self.toggle_query_tag(tag_id) {
+                    return command;
                 }
 
                 self.set_query_input(String::new());
                 return self.restart_search();
             }
 
+            Message::PinnedTagPressed(tag_id) => {
+                if let Err(command) = self.toggle_query_tag(tag_id) {
+                    return command;
+                }
+                return self.restart_search();
+            }
+
             Message::RemoveQueryTag(tag_id) => {
                 self.query.remove_tag(&tag_id);
                 return self.restart_search();
@@ -288,13 +363,51 @@ impl MainScreen {
             }
 
             Message::EntryHovered(index) => {
-                if let Some(Item(_, path)) = self.items.get(index) {
+                if let Some(Item(_, path, ..)) = self.items.get(index) {
                     self.hovered_path = Some(path.clone());
                 }
             }
 
             Message::EntrySelected(index) => {
-                self.selected_path = self.items.get(index).map(|Item(_, p)| p.clone());
+                self.selected_path = self.items.get(index).map(|Item(_, p, ..)| p.clone());
+                // Results container gets resized by the inspector
+                return MainScreen::fetch_results_bounds() .map(|m| m.into());
+            }
+
+            Message::CloseInspector => {
+                self.selected_path = None;
+                return MainScreen::fetch_results_bounds() .map(|m| m.into());
+            }
+
+            Message::AnnotationChanged(tag_id, path, annotation) => {
+                let mut tag = match tag_id.load() {
+                    Ok(tag) => tag,
+                    Err(err) => return send_message!(notif = error!(
+                        notify, log_context = "MainScreen::update() => AnnotationChanged";
+                        "Failed to load tag `{}`:\n{:?}", tag_id, err
+                    )),
+                };
+
+                if let Err(err) = tag.set_annotation(path, annotation) {
+                    return send_message!(notif = error!(
+                        notify, log_context = "MainScreen::update() => AnnotationChanged";
+                        "Failed to annotate path in tag `{}`:\n{}", tag_id, err
+                    ));
+                }
+
+                if let Err(err) = tag.save() {
+                    return send_message!(notif = error!(
+                        notify, log_context = "MainScreen::update() => AnnotationChanged";
+                        "Failed to save tag `{}`:\n{:?}", tag_id, err
+                    ));
+                }
+
+                // Update cache so the inspector shows the new annotation
+                let mut tags: Vec<Tag> = tagging::tags_cache().clone();
+                if let Some(t) = tags.iter_mut().find(|t| t.id == tag_id) {
+                    *t = tag;
+                }
+                tagging::set_tags_cache(tags);
             }
 
             Message::EntryRightClickSelected(_index) => {
@@ -308,6 +421,19 @@ impl MainScreen {
             Message::ResultsBoundsFetched(rect) => {
                 self.results_container_bounds = rect;
             }
+
+            Message::SortSelected(key) => {
+                self.sort.key = key;
+                if self.is_missing_sort_metadata() {
+                    return self.restart_search();
+                }
+                self.sort_items();
+            }
+
+            Message::ToggleSortDirection => {
+                self.sort.reversed = !self.sort.reversed;
+                self.sort_items();
+            }
         }
 
         Command::none()
@@ -320,6 +446,7 @@ impl MainScreen {
             column![
                 row![
                     horizontal_space(),
+                    self.view_sort(),
                     tooltip(
                         button( icon!(Bootstrap::BookmarkStar) ) .on_press(AppMessage::SwitchToTagListScreen),
                         "Tags",
@@ -338,25 +465,69 @@ impl MainScreen {
                 row![
                     self.view_results(),
                 ]
-                // .push_maybe(self.selected_path.as_ref()
-                //     .map(|path| FileInspector::new(path, &self.tags_cache)
-                // ))
+                .push_maybe(self.selected_path.as_ref().map(|path|
+                    FileInspector::new(path.clone(), &tagging::tags_cache())
+                        .width(INSPECTOR_WIDTH)
+                        .on_annotation_changed(|tag_id, path, annotation|
+                            Message::AnnotationChanged(tag_id, path, annotation).into()
+                        )
+                        .on_close(Message::CloseInspector.into())
+                ))
+                .spacing(8),
 
             ]
             // Add hovered path text, if any
-            .push_maybe(self.hovered_path.as_ref().map(|pb|
-                text(pb.to_pretty_string()) .size(12)
-            ))
+            .push_maybe(self.hovered_path.as_ref().map(|pb| {
+                let content_match: Option<&ContentMatch> = self.items.iter()
+                    .find(|item| &item.1 == pb)
+                    .and_then(|item| item.2.as_ref());
+                match content_match {
+                    Some(m) => text(format!("{}:{}  {}", pb.to_pretty_string(), m.line_number, m.line)),
+                    None => text(pb.to_pretty_string()),
+                }
+                .size(12)
+            }))
             .spacing(8),
         )
         .into()
     }
 
+    fn view_sort(&self) -> Element<'_, AppMessage> {
+        let sort = self.current_sort();
+        let direction_icon = if sort.reversed { Bootstrap::SortUp } else { Bootstrap::SortDown };
+
+        row![
+            pick_list(
+                &SortKey::ALL[..],
+                Some(sort.key),
+                |key| Message::SortSelected(key).into(),
+            ),
+            tooltip(
+                button( icon!(direction_icon) ) .on_press(Message::ToggleSortDirection.into()),
+                "Reverse order",
+                tooltip::Position::Bottom
+            ),
+        ]
+        .spacing(4)
+        .into()
+    }
+
     fn view_query_input(&self) -> Column<AppMessage> {
         let palette = iced::theme::Palette::CATPPUCCIN_MOCHA;
         let dark_text_col = palette.text.inverse();
 
         column![
+            // Pinned tags
+            row(self.pinned_tags.iter().map(|id| {
+                let is_active = self.query.tags.iter().any(|t| t.id == *id);
+                button( text(id).size(14) )
+                    .on_press( Message::PinnedTagPressed(id.clone()).into() )
+                    .style(if is_active { iced::theme::Button::Primary } else { iced::theme::Button::Secondary })
+                    .padding([2, 8])
+                    .into()
+            }))
+            .spacing(4),
+
             // Tags
             row(self.query.tags.iter().map(|tag| {
                 let id = &tag.id;
@@ -388,9 +559,21 @@ impl MainScreen {
                     .on_input(|text| Message::QueryTextChanged(text).into())
                     .on_submit(Message::QuerySubmit.into())
             })
+            .score_bonus(|tag_id| self.tag_usage.bonus(tag_id))
             .hide_on_empty( !self.query.tags.is_empty() )
             .style(theme::Simple),
         ]
+        // Parse errors, with the column they're at
+        .extend(self.query.constraints.errors.iter().map(|err| {
+            let column = err.span()
+                .and_then(|span| self.query_input.get(..span.start))
+                .map(|before| before.chars().count() + 1);
+            let message = match column {
+                Some(column) => format!("{column}: {err}"),
+                None => err.to_string(),
+            };
+            text(message) .size(12) .style(theme::ERROR_COLOR) .into()
+        }))
     }
 
     fn view_results(&self) -> Container<AppMessage> {
@@ -416,8 +599,66 @@ impl MainScreen {
     }
 
     pub fn restart_search(&mut self) -> Command<AppMessage> {
+        self.record_typed_tags();
         self.items.clear();
-        self.query.search();
+        self.query.search(self.current_sort());
+        self.has_all_results = false;
+        self.scroll_to_top()
+    }
+
+    /// Record the use of the existing tags typed in the query, e.g. `#art`, that weren't
+    /// there on the last search
+    fn record_typed_tags(&mut self) {
+        let typed_tags: HashSet<TagID> = self.query.constraints.tags.iter()
+            .flat_map(|filter| filter.any_of.iter())
+            .filter(|id| self.tags_cache.contains(id))
+            .cloned()
+            .collect();
+
+        let new_tags: Vec<&TagID> = typed_tags.difference(&self.typed_tags).collect();
+        if !new_tags.is_empty() {
+            for id in new_tags {
+                self.tag_usage.record(id);
+            }
+            if let Err(err) = self.tag_usage.save() {
+                error!("[MainScreen::record_typed_tags()] Failed to save tag usage:\n {}", err);
+            }
+        }
+        self.typed_tags = typed_tags;
+    }
+
+    /// Re-score the current results if the query only got narrower, or restart the search
+    /// otherwise
+    /// See [`Query::is_refinement`]
+    pub fn refine_search(&mut self) -> Command<AppMessage> {
+        if !self.has_all_results || !self.query.is_refinement() || self.is_missing_sort_metadata() {
+            return self.restart_search();
+        }
+
+        self.query.refine(&mut self.items);
+        self.sort_items();
+        self.scroll_to_top()
+    }
+
+    /// The `sort:` in the query, if any, otherwise the one picked in the header
+    fn current_sort(&self) -> Sort {
+        self.query.constraints.sort.unwrap_or(self.sort)
+    }
+
+    /// Returns whether the current results lack the metadata [`MainScreen::current_sort`] compares
+    /// Only searches started with such a sort read it, so they have to be restarted
+    fn is_missing_sort_metadata(&self) -> bool {
+        self.current_sort().key.needs_metadata() && !self.query.has_metadata()
+    }
+
+    /// Sort the current results with [`MainScreen::current_sort`]
+    fn sort_items(&mut self) {
+        let sort = self.current_sort();
+        // Stable, so ties keep their order
+        self.items.sort_by(|a, b| sort.compare(a, b));
+    }
+
+    fn scroll_to_top(&mut self) -> Command<AppMessage> {
         self.scroll = 0.0;
         self.hovered_path = None;
 
@@ -459,7 +700,7 @@ impl MainScreen {
                     // Open first item and close
                     /*
                     Key::Named(Named::Enter) if modifiers.command() => {
-                        if let Some(Item(_, path)) = self.items.first() {
+                        if let Some(Item(_, path, ..)) = self.items.first() {
                             return Command::batch(vec![
                                 KFiles::open_path(path),
                                 window::close(window::Id::MAIN),
@@ -541,7 +782,7 @@ impl ThumbnailHandler {
     ) -> Option<&'a PathBuf>
     {
         for _ in 0..self.max_check_depth {
-            let Some(Item(_, path)) = items.get(self.index) else {
+            let Some(Item(_, path, ..)) = items.get(self.index) else {
                 self.index = visible_items_range.start;
                 return None;
             };
@@ -553,7 +794,7 @@ impl ThumbnailHandler {
 
             self.index += 1;
 
-            if path.is_dir() || !thumbnail::is_file_supported(path) {
+            if path.is_dir() || !thumbnail::is_file_supported(path) || archive::is_member_path(path) {
                 continue;
             }
 
@@ -1,12 +1,12 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use iced::event::Status;
 use iced::keyboard::key::Named;
 use iced::widget::text_editor::{Action, Content};
 use iced::widget::{
-    self, button, column, container, horizontal_rule, horizontal_space, row, scrollable, text, text_editor, text_input, tooltip, vertical_space, Column, Row
+    self, button, checkbox, column, container, horizontal_rule, horizontal_space, pick_list, row, scrollable, text, text_editor, text_input, tooltip, vertical_space, Column, Row
 };
 use iced::widget::tooltip::Position as TooltipPosition;
 use iced::{Alignment, Color, Command, Element, Event, Length};
@@ -15,17 +15,48 @@ use iced_aw::{Bootstrap, Spinner};
 use rfd::FileDialog;
 
 use crate::app::Message as AppMessage;
+use crate::tagging::stats::{self, StatsBuilder, TagStats};
 use crate::tagging::tag::SelfReferringSubtag;
 use crate::tagging::tags_cache;
-use crate::tagging::{ self, entries::Entries, Tag, id::TagID };
+use crate::tagging::{ self, entries::{Entries, EntryOptions}, Tag, id::TagID };
+use crate::tagging::annotation::Annotation;
+use crate::tagging::order::TagOrder;
+use crate::tagging::usage::TagUsage;
 use crate::widget::context_menu::ContextMenu;
+use crate::widget::rating::rating_stars;
 use crate::widget::tag_entry;
-use crate::{ error, icon, info, send_message, simple_button, tag_list_menu, trace, warn, ToPrettyString };
+use crate::{ error, icon, ignore_rules, info, send_message, simple_button, tag_list_menu, trace, warn, ToPrettyString };
 
 use super::theme;
 
 
 pub const RENAME_INPUT_ID: fn() -> widget::text_input::Id = || { widget::text_input::Id::new("tag_rename_input") };
+/// [`EntryOptions::max_depth`] choices, for use in a [`pick_list`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DepthLimit(Option<usize>);
+
+impl DepthLimit {
+    const ALL: [DepthLimit; 6] = [
+        DepthLimit(None),
+        DepthLimit(Some(1)),
+        DepthLimit(Some(2)),
+        DepthLimit(Some(3)),
+        DepthLimit(Some(5)),
+        DepthLimit(Some(10)),
+    ];
+}
+
+impl std::fmt::Display for DepthLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            None => write!(f, "All levels"),
+            Some(1) => write!(f, "Direct children only"),
+            Some(d) => write!(f, "{d} levels"),
+        }
+    }
+}
+
+const ANNOTATION_INPUT_ID: fn() -> widget::text_input::Id = || { widget::text_input::Id::new("tag_annotation_input") };
 const DANGER_COLOR: Color = Color {
     r: 0.9,
     g: 0.1,
@@ -45,6 +76,10 @@ pub enum Message {
     EndEntriesEdit,
     CancelEntriesEdit,
     EntriesEditActionPerformed(Action),
+    StartIgnoreEdit,
+    EndIgnoreEdit,
+    CancelIgnoreEdit,
+    IgnoreEditActionPerformed(Action),
     AddFile,
     AddFolder,
 
@@ -60,6 +95,16 @@ pub enum Message {
     /// Add or remove a subtag from the current [`Tag`]
     SubtagToggled(TagID, bool),
     SubtagPressed(usize),
+
+    RefreshStats,
+
+    EntryOptionsChanged(PathBuf, EntryOptions),
+
+    StartAnnotationEdit(PathBuf),
+    EndAnnotationEdit,
+    CancelAnnotationEdit,
+    AnnotationNoteInput(String),
+    AnnotationRatingInput(Option<u8>),
 }
 
 impl From<Message> for AppMessage {
@@ -75,18 +120,33 @@ impl From<Message> for AppMessage {
 pub struct TagEditScreen {
     tag: Tag,
     entries_editing_content: Option<Content>,
+    ignore_editing_content: Option<Content>,
     renaming_content: Option<String>,
+    /// Path whose [`Annotation`] is being edited, and the edited annotation
+    annotation_editing: Option<(PathBuf, Annotation)>,
     is_loading: bool,
+    stats: Option<TagStats>,
+    stats_builder: StatsBuilder,
 }
 
 impl TagEditScreen {
     pub fn new(tag: Tag) -> (Self, Command<AppMessage>) {
+        let stats = TagStats::load_cached(&tag);
+        let mut stats_builder = StatsBuilder::new(1);
+        if stats.is_none() {
+            stats_builder.build(&tag);
+        }
+
         (
             TagEditScreen {
                 tag,
                 entries_editing_content: None,
+                ignore_editing_content: None,
                 renaming_content: None,
+                annotation_editing: None,
                 is_loading: false,
+                stats,
+                stats_builder,
             },
 
             scrollable::snap_to(
@@ -97,6 +157,15 @@ impl TagEditScreen {
         )
     }
 
+    pub fn tick(&mut self) -> Command<AppMessage> {
+        for (tag_id, stats) in self.stats_builder.update() {
+            if tag_id == self.tag.id {
+                self.stats = Some(stats);
+            }
+        }
+        Command::none()
+    }
+
     pub fn update(&mut self, message: Message) -> Command<AppMessage> {
         if self.is_loading {
             if matches!(message, Message::StopLoadingMate) {
@@ -144,8 +213,12 @@ impl TagEditScreen {
                     return Command::none();
                 };
 
-                let entries = &mut self.tag.entries;
-                *entries = Entries::from_string_list(&content.text());
+                let mut entries = Entries::from_string_list(&content.text());
+                entries.inherit_options(&self.tag.entries);
+                self.tag.entries = entries;
+                for pb in self.tag.retain_contained_annotations() {
+                    trace!("[TagEditScreen::update() => EndEntriesEdit] Removed annotation on {}", pb.display());
+                }
 
                 return Command::batch(vec![
                     self.filter_duplicate_entries(),
@@ -157,6 +230,31 @@ impl TagEditScreen {
                 self.entries_editing_content = None;
             }
 
+            Message::StartIgnoreEdit => {
+                let text: String = self.tag.ignore_patterns.join("\n");
+                self.ignore_editing_content = Some(Content::with_text(&text));
+            }
+
+            Message::IgnoreEditActionPerformed(action) => {
+                let Some(content) = &mut self.ignore_editing_content else {
+                    return Command::none();
+                };
+                content.perform(action);
+            }
+
+            Message::EndIgnoreEdit => {
+                let Some(content) = self.ignore_editing_content.take() else {
+                    return Command::none();
+                };
+
+                self.tag.ignore_patterns = ignore_rules::parse_patterns(&content.text());
+                return self.save();
+            }
+
+            Message::CancelIgnoreEdit => {
+                self.ignore_editing_content = None;
+            }
+
             Message::AddFile => {
                 let Some(picks) = FileDialog::new().pick_files() else {
                     return Command::none();
@@ -239,11 +337,104 @@ impl TagEditScreen {
 
                 return self.save();
             }
+
+            Message::RefreshStats => {
+                self.stats_builder.build(&self.tag);
+            }
+
+            Message::EntryOptionsChanged(path, options) => {
+                if !self.tag.entries.set_options(&path, options) {
+                    return Command::none();
+                }
+                return self.save();
+            }
+
+            Message::StartAnnotationEdit(path) => {
+                let annotation = self.tag.get_annotation(&path)
+                    .cloned()
+                    .unwrap_or_default();
+                self.annotation_editing = Some((path, annotation));
+                return widget::text_input::focus(ANNOTATION_INPUT_ID());
+            }
+
+            Message::AnnotationNoteInput(str) => {
+                if let Some((_, annotation)) = &mut self.annotation_editing {
+                    annotation.note = str;
+                }
+            }
+
+            Message::AnnotationRatingInput(rating) => {
+                if let Some((_, annotation)) = &mut self.annotation_editing {
+                    annotation.set_rating(rating);
+                }
+            }
+
+            Message::EndAnnotationEdit => {
+                let Some((path, annotation)) = self.annotation_editing.take() else {
+                    return Command::none();
+                };
+
+                if let Err(err) = self.tag.set_annotation(path.clone(), annotation) {
+                    let pathstr: String = path.to_pretty_string();
+                    return send_message!(notif = error!(
+                        notify, log_context = "TagEditScreen::update() => EndAnnotationEdit";
+                        "Failed to annotate \"{}\":\n{}", pathstr, err
+                    ));
+                }
+
+                return self.save();
+            }
+
+            Message::CancelAnnotationEdit => {
+                self.annotation_editing = None;
+            }
         }
 
         Command::none()
     }
 
+    fn view_stats(&self) -> Row<AppMessage> {
+        let is_building: bool = self.stats_builder.is_building(&self.tag.id);
+
+        let content: Element<AppMessage> = match &self.stats {
+            Some(s) => column![
+                text(format!(
+                    "{} files, {} folders. Total size: {}",
+                    s.file_count, s.folder_count, stats::format_size(s.total_size)
+                )),
+            ]
+            .push_maybe(s.newest_modified_string().map(|m|
+                text(format!("Last modified: {m}")) .style(tag_entry::ENTRY_COLOR)
+            ))
+            .push_maybe((s.broken_entries > 0).then(||
+                text(format!("{} broken entries (including subtags)", s.broken_entries))
+                    .style(theme::WARNING_COLOR)
+            ))
+            .into(),
+            None if is_building => text("Computing stats...") .style(tag_entry::ENTRY_COLOR) .into(),
+            None => text("No stats") .style(tag_entry::ENTRY_COLOR) .into(),
+        };
+
+        row![
+            content,
+            tooltip(
+                simple_button!(icon = Bootstrap::ArrowClockwise)
+                    .on_press_maybe((!is_building).then_some(Message::RefreshStats.into())),
+                "Refresh stats",
+                TooltipPosition::Right,
+            ),
+            tooltip(
+                simple_button!(icon = Bootstrap::Files)
+                    .on_press(AppMessage::SwitchToDuplicatesScreen(vec![ self.tag.id.clone() ])),
+                "Find duplicate files",
+                TooltipPosition::Right,
+            ),
+        ]
+        .spacing(16)
+        .padding([8, 24])
+        .align_items(Alignment::Center)
+    }
+
     fn view_entries(&self) -> Column<AppMessage> {
         let content = column![
             text("Entries:").size(24)
@@ -259,23 +450,24 @@ impl TagEditScreen {
             ],
             None => column(
                 self.tag.entries.as_ref().iter()
-                    .map(|pb| {
-                        let row = Row::new()
-                            .push( text(pb.to_pretty_string()).style(tag_entry::ENTRY_COLOR) )
-                            .spacing(8);
-                        if pb.exists() {
-                            return row.into();
-                        }
-                        return row.extend(vec![
-                            horizontal_space().width(64).into(),
-                            icon!(Bootstrap::ExclamationCircleFill, theme::ERROR_COLOR).into(),
-                            text("Path doesn't exist") .style(theme::ERROR_COLOR).into(),
-                        ])
-                        .into()
-                    })
-            ),
+                    .map(|pb| self.view_entry(pb).into())
+            )
+            .spacing(8.0),
         });
 
+        // Annotations on paths that aren't entries themselves (e.g. files inside tagged folders)
+        let mut other_annotated: Vec<&PathBuf> = self.tag.iter_annotations()
+            .map(|(pb, _)| pb)
+            .filter(|pb| !self.tag.entries.as_ref().contains(pb))
+            .collect();
+        other_annotated.sort();
+        let content = content.push_maybe((!other_annotated.is_empty()).then(|| column![
+            text("Other notes:").size(18),
+            column(other_annotated.into_iter().map(|pb| self.view_entry(pb).into()))
+                .spacing(8.0),
+        ]
+        .spacing(8.0)));
+
         // Bottom row
         let bottom_row = if self.entries_editing_content.is_some() {
             row![
@@ -304,10 +496,132 @@ impl TagEditScreen {
 
         content
             .push(bottom_row)
+            .push(self.view_ignore_patterns())
             .push(vertical_space().height(64))
 
     }
 
+    /// View the [`EntryOptions`] of the folder entry `pb`
+    fn view_entry_options(&self, pb: &Path) -> Row<AppMessage> {
+        let options = self.tag.entries.get_options(pb);
+        let path = pb.to_path_buf();
+        let path2 = pb.to_path_buf();
+
+        row![
+            pick_list(
+                DepthLimit::ALL,
+                Some(DepthLimit(options.max_depth)),
+                move |DepthLimit(max_depth)| Message::EntryOptionsChanged(
+                    path.clone(),
+                    options.with_max_depth(max_depth)
+                ).into(),
+            )
+            .text_size(12),
+            checkbox("Follow symlinks", options.follow_links)
+                .on_toggle(move |follow_links| Message::EntryOptionsChanged(
+                    path2.clone(),
+                    options.with_follow_links(follow_links)
+                ).into())
+                .text_size(12),
+        ]
+        .spacing(8)
+        .align_items(Alignment::Center)
+    }
+
+    fn view_ignore_patterns(&self) -> Column<AppMessage> {
+        let content = column![
+            text("Ignore patterns:").size(24),
+            text("Gitignore-style patterns for paths to skip inside this tag's folders, on top of the global ignore file")
+                .size(12)
+                .style(tag_entry::ENTRY_COLOR),
+        ]
+        .spacing(8.0);
+
+        match &self.ignore_editing_content {
+            Some(c) => content.extend(vec![
+                text_editor(c)
+                    .on_action(|a| Message::IgnoreEditActionPerformed(a).into())
+                    .into(),
+                row![
+                    button(icon!(Bootstrap::FloppyFill))
+                        .on_press(Message::EndIgnoreEdit.into()),
+                    simple_button!(icon = Bootstrap::X)
+                        .on_press(Message::CancelIgnoreEdit.into()),
+                ]
+                .into(),
+            ]),
+
+            None => content
+                .push(column(
+                    self.tag.ignore_patterns.iter()
+                        .map(|p| text(p) .style(tag_entry::ENTRY_COLOR) .into())
+                ))
+                .push(
+                    simple_button!(icon = Bootstrap::PencilSquare)
+                        .on_press(Message::StartIgnoreEdit.into())
+                ),
+        }
+    }
+
+    /// View a single path under this tag, along with its [`Annotation`], if any
+    fn view_entry(&self, pb: &PathBuf) -> Column<AppMessage> {
+        let row = Row::new()
+            .push( text(pb.to_pretty_string()).style(tag_entry::ENTRY_COLOR) )
+            .push(
+                simple_button!(icon = Bootstrap::ChatLeftText)
+                    .on_press_maybe(self.annotation_editing.is_none()
+                        .then(|| Message::StartAnnotationEdit(pb.clone()).into())
+                    )
+            )
+            .spacing(8)
+            .align_items(Alignment::Center);
+
+        let row = if pb.is_dir() && self.tag.entries.as_ref().contains(pb) {
+            row.push(self.view_entry_options(pb))
+        } else if pb.exists() {
+            row
+        } else {
+            row.extend(vec![
+                horizontal_space().width(64).into(),
+                icon!(Bootstrap::ExclamationCircleFill, theme::ERROR_COLOR).into(),
+                text("Path doesn't exist") .style(theme::ERROR_COLOR).into(),
+            ])
+        };
+
+        // Annotation being edited
+        if let Some((_, annotation)) = self.annotation_editing.as_ref().filter(|(p, _)| p == pb) {
+            return column![
+                row,
+                row![
+                    text_input("Note...", &annotation.note)
+                        .id(ANNOTATION_INPUT_ID())
+                        .on_input(|str| Message::AnnotationNoteInput(str).into())
+                        .on_submit(Message::EndAnnotationEdit.into()),
+                    rating_stars(annotation.rating, Some(|r| Message::AnnotationRatingInput(r).into())),
+                    button(icon!(Bootstrap::FloppyFill))
+                        .on_press(Message::EndAnnotationEdit.into()),
+                    simple_button!(icon = Bootstrap::X)
+                        .on_press(Message::CancelAnnotationEdit.into()),
+                ]
+                .spacing(8)
+                .padding([0, 0, 0, 24])
+                .align_items(Alignment::Center),
+            ];
+        }
+
+        // Annotation
+        column![ row ]
+            .push_maybe(self.tag.get_annotation(pb).map(|annotation|
+                row![
+                    rating_stars(annotation.rating, None::<fn(Option<u8>) -> AppMessage>),
+                    text(&annotation.note),
+                ]
+                .spacing(8)
+                .padding([0, 0, 0, 24])
+                .align_items(Alignment::Center)
+            ))
+    }
+
     fn view_label(&self) -> Row<AppMessage> {
         match &self.renaming_content {
             Some(content) => row![
@@ -421,6 +735,7 @@ impl TagEditScreen {
 
         // MAIN
         col.extend(vec![
+            self.view_stats().into(),
             horizontal_rule(1).into(),
             scrollable(self.view_entries())
             .id(MAIN_SCROLLABLE_ID())
@@ -450,6 +765,14 @@ impl TagEditScreen {
             if self.renaming_content.is_some() {
                 self.renaming_content = None;
             }
+            // Cancel editing annotation
+            else if self.annotation_editing.is_some() {
+                self.annotation_editing = None;
+            }
+            // Cancel editing ignore patterns
+            else if self.ignore_editing_content.is_some() {
+                self.ignore_editing_content = None;
+            }
             // Cancel editing entries
             else {
                 self.entries_editing_content = None;
@@ -471,6 +794,7 @@ impl TagEditScreen {
         use crate::tagging::RenameError;
 
         let old_path = self.tag.get_save_path();
+        let old_id = self.tag.id.clone();
         let new_path = new_id.get_path();
 
         match self.tag.rename(&new_id) {
@@ -478,6 +802,19 @@ impl TagEditScreen {
             Ok(true) => {
                 self.is_loading = true;
 
+                let mut order = TagOrder::load();
+                if order.rename(&old_id, &new_id) {
+                    if let Err(err) = order.save() {
+                        error!("[TagEditScreen::rename()] Failed to save tag order:\n {}", err);
+                    }
+                }
+                let mut usage = TagUsage::load();
+                if usage.rename(&old_id, &new_id) {
+                    if let Err(err) = usage.save() {
+                        error!("[TagEditScreen::rename()] Failed to save tag usage:\n {}", err);
+                    }
+                }
+
                 // We don't use `self.save()` because we don't want to wait if it fails
                 // (There's kinda no way to check whether it has failed just with iced::Command)
                 if let Err(err) = self.tag.save() {
@@ -538,8 +875,10 @@ impl TagEditScreen {
     }
 
     /// Save the current tag to disk and notify any errors via a [`Command`]
-    fn save(&self) -> Command<AppMessage> {
+    /// Also recomputes the tag's stats since its entries may have changed
+    fn save(&mut self) -> Command<AppMessage> {
         let Err(err) = self.tag.save() else {
+            self.stats_builder.build(&self.tag);
             return Command::none();
         };
         send_message!(notif = error!(
@@ -1,23 +1,36 @@
+use std::collections::HashMap;
+use std::fmt::Display;
 use std::path::PathBuf;
 
 use iced::event::Status;
-use iced::widget::{button, column, container, horizontal_space, row, scrollable, text, tooltip, Container};
-use iced::{Command, Element, Event, Length};
+use iced::widget::{button, column, container, horizontal_rule, horizontal_space, pick_list, row, scrollable, text, tooltip, Column, Container};
+use iced::{Alignment, Command, Element, Event, Length};
 
 use iced_aw::Bootstrap;
 
 use crate::app::Message as AppMessage;
 use crate::tagging::{ self, Tag, id::TagID };
-use crate::widget::tag_entry::TagEntry as TagEntryWidget;
-use crate::{ error, icon, send_message, simple_button, ToPrettyString };
+use crate::tagging::fsck::{self, ProblemKind, Report};
+use crate::tagging::order::TagOrder;
+use crate::tagging::stats::{StatsBuilder, TagStats};
+use crate::widget::tag_entry::{TagEntry as TagEntryWidget, ENTRY_COLOR};
+use crate::{ configs, error, icon, info, send_message, simple_button, ToPrettyString };
 
-use super::theme::ERROR_COLOR;
+use super::theme::{ERROR_COLOR, WARNING_COLOR};
 
 
 #[derive(Debug, Clone)]
 pub enum Message {
     OpenTagsDir,
     CreateTag,
+    SortSelected(TagSort),
+    RefreshStats,
+    RunHealthCheck,
+    FixProblems(ProblemKind),
+    CloseHealthCheck,
+    TogglePinned(TagID),
+    /// Move a tag up (negative) or down (positive) in the manual order
+    MoveTag(TagID, isize),
 }
 
 impl From<Message> for AppMessage {
@@ -28,28 +41,174 @@ impl From<Message> for AppMessage {
 
 
 
+/// How to order the tags in the [`TagListScreen`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TagSort {
+    /// Manual order, see [`TagOrder`]
+    #[default]
+    Default,
+    Name,
+    /// Biggest first
+    Size,
+    /// Most files first
+    FileCount,
+    /// Most folders first
+    FolderCount,
+    /// Most recently modified first
+    Modified,
+}
+
+impl TagSort {
+    const ALL: [TagSort; 6] = [
+        TagSort::Default,
+        TagSort::Name,
+        TagSort::Size,
+        TagSort::FileCount,
+        TagSort::FolderCount,
+        TagSort::Modified,
+    ];
+
+    /// Sort `tags` in place, pinned tags first
+    /// Tags without any stats are put last for stats-based sorts
+    fn sort(&self, tags: &mut [Tag], stats: &HashMap<TagID, TagStats>, order: &TagOrder) {
+        use std::cmp::Reverse;
+
+        match self {
+            TagSort::Default => {
+                order.sort(tags, |t| &t.id);
+                return;
+            }
+            TagSort::Name => tags.sort_by(|a, b| a.id.as_ref().cmp(b.id.as_ref())),
+            TagSort::Size => tags.sort_by_key(|t| Reverse( stats.get(&t.id).map(|s| s.total_size) )),
+            TagSort::FileCount => tags.sort_by_key(|t| Reverse( stats.get(&t.id).map(|s| s.file_count) )),
+            TagSort::FolderCount => tags.sort_by_key(|t| Reverse( stats.get(&t.id).map(|s| s.folder_count) )),
+            TagSort::Modified => tags.sort_by_key(|t| Reverse( stats.get(&t.id).and_then(|s| s.newest_modified) )),
+        }
+        order.pinned_first(tags, |t| &t.id);
+    }
+}
+
+impl Display for TagSort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagSort::Default => write!(f, "Default"),
+            TagSort::Name => write!(f, "Name"),
+            TagSort::Size => write!(f, "Size"),
+            TagSort::FileCount => write!(f, "File count"),
+            TagSort::FolderCount => write!(f, "Folder count"),
+            TagSort::Modified => write!(f, "Last modified"),
+        }
+    }
+}
+
+
+
 #[derive(Debug)]
 pub struct TagListScreen {
     error_message: Option<String>,
     loaded_tags: Vec<Tag>,
+    stats: HashMap<TagID, TagStats>,
+    stats_builder: StatsBuilder,
+    sort: TagSort,
+    /// Result of the last [`fsck::fsck`], if shown
+    health_report: Option<Report>,
+    order: TagOrder,
+    /// Last tag that was pinned or moved, which can then be moved with the keyboard
+    focused_tag: Option<TagID>,
 }
 
 impl TagListScreen {
     pub fn new() -> (Self, Command<AppMessage>) {
         let load_res = tagging::load_tags();
         let error_message = load_res.log_errors::<String>();
+        let command = load_res.report_errors();
         let tags_cache = load_res.get_tags().unwrap_or_default();
         tagging::set_tags_cache(tags_cache);
 
+        let loaded_tags: Vec<Tag> = tagging::tags_cache().clone();
+
+        // Use cached stats where possible, and compute the rest in the background
+        let mut stats: HashMap<TagID, TagStats> = HashMap::new();
+        let mut stats_builder = StatsBuilder::new(configs::global().get_search_thread_count());
+        for tag in loaded_tags.iter() {
+            match TagStats::load_cached(tag) {
+                Some(s) => { stats.insert(tag.id.clone(), s); },
+                None => { stats_builder.build(tag); },
+            }
+        }
+
         (
             TagListScreen {
                 error_message,
-                loaded_tags: tagging::tags_cache().clone(),
+                loaded_tags,
+                stats,
+                stats_builder,
+                sort: TagSort::default(),
+                health_report: None,
+                order: TagOrder::load(),
+                focused_tag: None,
             },
-            Command::none(),
+            command,
         )
     }
 
+    /// Reload all tags, e.g. after fixing problems in them
+    fn reload_tags(&mut self) {
+        let load_res = tagging::load_tags();
+        self.error_message = load_res.log_errors::<String>();
+        tagging::set_tags_cache(load_res.get_tags().unwrap_or_default());
+
+        self.loaded_tags = tagging::tags_cache().clone();
+        self.sort.sort(&mut self.loaded_tags, &self.stats, &self.order);
+    }
+
+    fn save_order(&mut self) -> Command<AppMessage> {
+        self.sort.sort(&mut self.loaded_tags, &self.stats, &self.order);
+        if let Err(err) = self.order.save() {
+            return send_message!(notif = error!(
+                notify, log_context = "TagListScreen::save_order()";
+                "Failed to save tag order:\n{}", err
+            ));
+        }
+        Command::none()
+    }
+
+    fn move_tag(&mut self, id: TagID, offset: isize) -> Command<AppMessage> {
+        let ids: Vec<TagID> = self.loaded_tags.iter()
+            .map(|t| t.id.clone())
+            .collect();
+        let has_moved = self.order.move_by(&ids, &id, offset);
+        self.focused_tag = Some(id);
+        if !has_moved {
+            return Command::none();
+        }
+        self.save_order()
+    }
+
+    fn run_health_check(&mut self) -> Command<AppMessage> {
+        match fsck::fsck() {
+            Ok(report) => {
+                self.health_report = Some(report);
+                Command::none()
+            }
+            Err(err) => send_message!(notif = error!(
+                notify, log_context = "TagListScreen::run_health_check()";
+                "Failed to check tags:\n{}", err
+            )),
+        }
+    }
+
+    pub fn tick(&mut self) -> Command<AppMessage> {
+        let results = self.stats_builder.update();
+        if results.is_empty() {
+            return Command::none();
+        }
+
+        self.stats.extend(results);
+        self.sort.sort(&mut self.loaded_tags, &self.stats, &self.order);
+        Command::none()
+    }
+
     pub fn update(&mut self, message: Message) -> Command<AppMessage> {
         match message {
             Message::OpenTagsDir => {
@@ -77,6 +236,61 @@ impl TagListScreen {
                 return send_message!(AppMessage::SwitchToTagEditScreen(tag))
             }
 
+            Message::SortSelected(sort) => {
+                self.sort = sort;
+                if sort == TagSort::Default {
+                    self.loaded_tags = tagging::tags_cache().clone();
+                }
+                sort.sort(&mut self.loaded_tags, &self.stats, &self.order);
+            }
+
+            Message::RefreshStats => {
+                for tag in self.loaded_tags.iter() {
+                    self.stats_builder.build(tag);
+                }
+            }
+
+            Message::RunHealthCheck => {
+                return self.run_health_check();
+            }
+
+            Message::FixProblems(kind) => {
+                let Some(report) = &self.health_report else {
+                    return Command::none();
+                };
+
+                let res = fsck::fix(report, kind);
+                self.reload_tags();
+                let command = self.run_health_check();
+
+                let notif = match res {
+                    Ok(count) => info!(
+                        notify, log_context = "TagListScreen::update() => FixProblems";
+                        "Fixed {} problem(s): {}", count, kind
+                    ),
+                    Err(err) => error!(
+                        notify, log_context = "TagListScreen::update() => FixProblems";
+                        "Failed to fix {}:\n{}", kind, err
+                    ),
+                };
+                return Command::batch([ command, send_message!(notif = notif) ]);
+            }
+
+            Message::CloseHealthCheck => {
+                self.health_report = None;
+            }
+
+            Message::TogglePinned(id) => {
+                let is_pinned = self.order.is_pinned(&id);
+                self.order.set_pinned(&id, !is_pinned);
+                self.focused_tag = Some(id);
+                return self.save_order();
+            }
+
+            Message::MoveTag(id, offset) => {
+                return self.move_tag(id, offset);
+            }
+
         }
         
         Command::none()
@@ -92,6 +306,23 @@ impl TagListScreen {
                     .on_press(AppMessage::SwitchToMainScreen),
                 text("Tags List") .size(24),
                 horizontal_space(),
+                tooltip(
+                    simple_button!(icon = Bootstrap::ExclamationTriangle)
+                        .on_press(AppMessage::SwitchToDiagnosticsScreen),
+                    "Tags that failed to load",
+                    tooltip::Position::Bottom
+                ),
+                tooltip(
+                    simple_button!(icon = Bootstrap::HeartPulse) .on_press(Message::RunHealthCheck.into()),
+                    "Check tags for problems",
+                    tooltip::Position::Bottom
+                ),
+                tooltip(
+                    simple_button!(icon = Bootstrap::Files)
+                        .on_press(AppMessage::SwitchToDuplicatesScreen(Vec::new())),
+                    "Find duplicate files",
+                    tooltip::Position::Bottom
+                ),
                 tooltip(
                     simple_button!(icon = Bootstrap::Folder) .on_press(Message::OpenTagsDir.into()),
                     "Open tags directory",
@@ -99,11 +330,28 @@ impl TagListScreen {
                 ),
             ],
 
-            tooltip(
-                button( icon!(Bootstrap::BookmarkPlus) ) .on_press(Message::CreateTag.into()),
-                "Create new tag",
-                tooltip::Position::Right
-            ),
+            row![
+                tooltip(
+                    button( icon!(Bootstrap::BookmarkPlus) ) .on_press(Message::CreateTag.into()),
+                    "Create new tag",
+                    tooltip::Position::Right
+                ),
+                horizontal_space(),
+                text("Sort by:"),
+                pick_list(
+                    &TagSort::ALL[..],
+                    Some(self.sort),
+                    |s| Message::SortSelected(s).into(),
+                ),
+                tooltip(
+                    simple_button!(icon = Bootstrap::ArrowClockwise)
+                        .on_press_maybe((!self.stats_builder.is_busy()).then_some(Message::RefreshStats.into())),
+                    "Refresh stats",
+                    tooltip::Position::Left
+                ),
+            ]
+            .spacing(8)
+            .align_items(Alignment::Center),
 
             list
         ]
@@ -124,9 +372,13 @@ impl TagListScreen {
 
         // Contents
         container(scrollable(
-            column(self.loaded_tags.iter().map(|t|
+            Column::new()
+            .push_maybe(self.health_report.as_ref().map(|report| self.view_health_report(report)))
+            .extend(self.loaded_tags.iter().map(|t| {
                 // aaa i dont like the cloning
-                TagEntryWidget::new(t)
+                let entry = TagEntryWidget::new(t)
+                    .stats(self.stats.get(&t.id))
+                    .pinned(self.order.is_pinned(&t.id), Message::TogglePinned(t.id.clone()).into())
                     .on_edit_pressed(AppMessage::SwitchToTagEditScreen(t.clone()))
                     .on_subtag_pressed(|id| match id.load() {
                         Ok(tag) => AppMessage::SwitchToTagEditScreen(tag),
@@ -134,9 +386,16 @@ impl TagListScreen {
                             notify;
                             "Failed to load tag \"{}\".\n{:?}", id, err
                         )),
-                    })
+                    });
+
+                // Manual ordering only makes sense in the default sort
+                if self.sort != TagSort::Default {
+                    return entry.into();
+                }
+                let id = t.id.clone();
+                entry.on_move(move |offset| Message::MoveTag(id.clone(), offset).into())
                     .into()
-            ))
+            }))
             .width(Length::Fill)
             .padding(12.0)
             .spacing(12.0)
@@ -144,7 +403,65 @@ impl TagListScreen {
 
     }
 
-    pub fn handle_event(&mut self, _event: Event, _status: Status) -> Command<AppMessage> {
+    fn view_health_report(&self, report: &Report) -> Column<AppMessage> {
+        let header = row![
+            text("Health check") .size(18),
+            horizontal_space(),
+            simple_button!(icon = Bootstrap::X) .on_press(Message::CloseHealthCheck.into()),
+        ]
+        .align_items(Alignment::Center);
+
+        if report.is_empty() {
+            return column![ header, text("No problems found") ]
+                .spacing(8);
+        }
+
+        column![ header ]
+            .extend(report.kinds().into_iter().map(|kind| column![
+                row![
+                    icon!(Bootstrap::ExclamationTriangleFill, WARNING_COLOR),
+                    text(format!("{} ({})", kind, report.iter_kind(kind).count())),
+                    horizontal_space(),
+                    tooltip(
+                        button("Fix") .on_press(Message::FixProblems(kind).into()),
+                        kind.fix_description(),
+                        tooltip::Position::Left
+                    ),
+                ]
+                .spacing(8)
+                .align_items(Alignment::Center),
+
+                column(report.iter_kind(kind).map(|problem|
+                    text(problem.to_string()) .size(12) .style(ENTRY_COLOR) .into()
+                ))
+                .spacing(4)
+                .padding([0, 24]),
+            ]
+            .spacing(8)
+            .into()))
+            .push(horizontal_rule(2))
+            .spacing(12)
+    }
+
+    pub fn handle_event(&mut self, event: Event, status: Status) -> Command<AppMessage> {
+        use iced::keyboard::{Event as KeyboardEvent, Key, key::Named};
+
+        // Alt + Up / Down to move the focused tag
+        if let Event::Keyboard(KeyboardEvent::KeyPressed { key, modifiers, .. }) = event {
+            if status == Status::Captured || !modifiers.alt() || self.sort != TagSort::Default {
+                return Command::none();
+            }
+            let Some(id) = self.focused_tag.clone() else {
+                return Command::none();
+            };
+
+            match key.as_ref() {
+                Key::Named(Named::ArrowUp) => return self.move_tag(id, -1),
+                Key::Named(Named::ArrowDown) => return self.move_tag(id, 1),
+                _ => {}
+            }
+        }
+
         Command::none()
     }
 }
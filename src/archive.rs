@@ -0,0 +1,467 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self, create_dir_all, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{Local, TimeZone};
+use flate2::read::GzDecoder;
+use thiserror::Error;
+
+use crate::get_temp_dir;
+use crate::search::CancelToken;
+
+
+/// Appended to an archive's file name to form the virtual paths of its members
+/// E.g. `assets.zip!/docs/readme.md`
+pub const MEMBER_SEPARATOR: char = '!';
+
+/// How many archives [`cached_members`] remembers the members of, before forgetting them all
+const MAX_CACHED_ARCHIVES: usize = 1024;
+
+/// Members of an archive, along with its modification time when it was listed
+type CachedMembers = (SystemTime, Arc<Vec<Member>>);
+
+/// Members of each archive listed by [`cached_members`]
+static MEMBERS_CACHE: Mutex<BTreeMap<PathBuf, CachedMembers>> = Mutex::new(BTreeMap::new());
+
+
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error(transparent)]
+    SevenZ(#[from] sevenz_rust::Error),
+    #[error("not a path inside an archive")]
+    NotAMember,
+    #[error("member not found in archive")]
+    MemberNotFound,
+    #[error("listing the archive was cancelled")]
+    Cancelled,
+}
+
+
+
+/// Supported archive formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    SevenZ,
+}
+
+impl ArchiveKind {
+    /// Get the archive format of `path` from its file name
+    pub fn from_path(path: &Path) -> Option<ArchiveKind> {
+        let name = path.file_name()?
+            .to_str()?
+            .to_lowercase();
+
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".7z") {
+            Some(ArchiveKind::SevenZ)
+        } else {
+            None
+        }
+    }
+}
+
+
+
+/// Returns whether `path` is an archive file that can be browsed
+pub fn is_archive(path: &Path) -> bool {
+    ArchiveKind::from_path(path).is_some() && path.is_file()
+}
+
+/// Create the virtual path of `member` inside `archive`
+/// E.g. `("assets.zip", "docs/readme.md")` => `"assets.zip!/docs/readme.md"`
+pub fn member_path(archive: &Path, member: &Path) -> PathBuf {
+    let mut name: OsString = archive.as_os_str().to_os_string();
+    name.push(MEMBER_SEPARATOR.to_string());
+    PathBuf::from(name).join(member)
+}
+
+/// Split a virtual path into its archive and the member path inside it
+/// Returns `None` if `path` is not inside an archive
+pub fn split_member_path(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let mut archive = PathBuf::new();
+    let mut components = path.components();
+
+    while let Some(c) = components.next() {
+        if let Component::Normal(name) = c {
+            if let Some(name) = name.to_str().and_then(|s| s.strip_suffix(MEMBER_SEPARATOR)) {
+                let candidate = archive.join(name);
+                let member = components.as_path();
+                if ArchiveKind::from_path(&candidate).is_some() && !member.as_os_str().is_empty() {
+                    return Some((candidate, member.to_path_buf()));
+                }
+            }
+        }
+        archive.push(c);
+    }
+
+    None
+}
+
+/// Returns whether `path` is a virtual path inside an archive
+/// See [`member_path`]
+#[inline]
+pub fn is_member_path(path: &Path) -> bool {
+    split_member_path(path).is_some()
+}
+
+/// Iterate over `path` and, if it is an archive, the virtual paths of all its members
+/// Each member comes with what the archive says about it. `path` itself comes with `None`
+/// Archives that fail to open, or whose listing gets cancelled by `token`, are skipped
+/// See [`cached_members`]
+pub fn with_members(path: PathBuf, token: &CancelToken) -> Box<dyn Iterator<Item = (PathBuf, Option<Member>)>> {
+    if !is_archive(&path) {
+        return Box::new(std::iter::once((path, None)));
+    }
+
+    let members: Vec<(PathBuf, Option<Member>)> = cached_members(&path, token)
+        .map(|members| members.iter()
+            .map(|m| (member_path(&path, &m.path), Some(m.clone())))
+            .collect())
+        .unwrap_or_default();
+    Box::new(std::iter::once((path, None)).chain(members))
+}
+
+
+
+/// A file inside an archive, as listed by [`list_members`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Member {
+    /// Relative to the archive
+    pub path: PathBuf,
+    /// Uncompressed size in bytes
+    pub size: u64,
+    /// As stored in the archive, if it was
+    pub modified: Option<SystemTime>,
+}
+
+/// Same as [`list_members`], but only lists `archive` again once it gets modified
+/// Searches list every archive they come across, which means decompressing all of a `.tar.gz`
+pub fn cached_members(archive: &Path, token: &CancelToken) -> Result<Arc<Vec<Member>>, ArchiveError> {
+    let modified = fs::metadata(archive)?.modified()?;
+    let cached = MEMBERS_CACHE.lock().ok()
+        .and_then(|cache| cache.get(archive).cloned());
+    if let Some((listed_modified, members)) = cached {
+        if listed_modified == modified {
+            return Ok(members);
+        }
+    }
+
+    let members = Arc::new(list_members(archive, token)?);
+    if let Ok(mut cache) = MEMBERS_CACHE.lock() {
+        if cache.len() >= MAX_CACHED_ARCHIVES {
+            cache.clear();
+        }
+        cache.insert(archive.to_path_buf(), (modified, members.clone()));
+    }
+    Ok(members)
+}
+
+/// List all files inside `archive`
+/// Folders are not included
+/// Tar archives are read member by member, so they stop with [`ArchiveError::Cancelled`] soon
+/// after `token` is cancelled
+pub fn list_members(archive: &Path, token: &CancelToken) -> Result<Vec<Member>, ArchiveError> {
+    let Some(kind) = ArchiveKind::from_path(archive) else {
+        return Ok(Vec::new());
+    };
+
+    let members: Vec<Member> = match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+            let mut members = Vec::new();
+            for i in 0..zip.len() {
+                let file = zip.by_index_raw(i)?;
+                if file.is_dir() {
+                    continue;
+                }
+                if let Some(path) = sanitize_member(file.name()) {
+                    members.push(Member {
+                        path,
+                        size: file.size(),
+                        modified: file.last_modified().and_then(zip_time),
+                    });
+                }
+            }
+            members
+        }
+
+        ArchiveKind::Tar => list_tar_members(File::open(archive)?, token)?,
+        ArchiveKind::TarGz => list_tar_members(GzDecoder::new(File::open(archive)?), token)?,
+
+        ArchiveKind::SevenZ => {
+            sevenz_rust::Archive::open(archive)?
+                .files
+                .iter()
+                .filter(|f| !f.is_directory())
+                .filter_map(|f| Some(Member {
+                    path: sanitize_member(f.name())?,
+                    size: f.size(),
+                    modified: f.has_last_modified_date
+                        .then(|| sevenz_time(f.last_modified_date().to_raw()))
+                        .flatten(),
+                }))
+                .collect()
+        }
+    };
+
+    Ok(members)
+}
+
+fn list_tar_members<R: Read>(reader: R, token: &CancelToken) -> Result<Vec<Member>, ArchiveError> {
+    let mut tar = tar::Archive::new(reader);
+    let mut members = Vec::new();
+
+    for entry in tar.entries()? {
+        if token.is_cancelled() {
+            return Err(ArchiveError::Cancelled);
+        }
+        let entry = entry?;
+        let header = entry.header();
+        if !header.entry_type().is_file() {
+            continue;
+        }
+        if let Some(path) = entry.path().ok().and_then(|p| sanitize_member(&p.to_string_lossy())) {
+            members.push(Member {
+                path,
+                size: header.size().unwrap_or(0),
+                modified: header.mtime().ok().map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            });
+        }
+    }
+
+    Ok(members)
+}
+
+/// Zip files store local times without a time zone
+fn zip_time(time: zip::DateTime) -> Option<SystemTime> {
+    Local.with_ymd_and_hms(
+        time.year().into(),
+        time.month().into(),
+        time.day().into(),
+        time.hour().into(),
+        time.minute().into(),
+        time.second().into(),
+    )
+    .earliest()
+    .map(SystemTime::from)
+}
+
+/// 7z files store times as 100 nanosecond ticks since 1601
+fn sevenz_time(ticks: u64) -> Option<SystemTime> {
+    const SECS_BEFORE_UNIX_EPOCH: u64 = 11_644_473_600;
+    let since_1601 = Duration::new(ticks / 10_000_000, (ticks % 10_000_000) as u32 * 100);
+    since_1601.checked_sub(Duration::from_secs(SECS_BEFORE_UNIX_EPOCH))
+        .map(|since_epoch| UNIX_EPOCH + since_epoch)
+}
+
+/// Turn a member name into a relative path
+/// Returns `None` for names that would escape the archive, e.g. `../../etc/passwd`
+fn sanitize_member(name: &str) -> Option<PathBuf> {
+    let pb = PathBuf::from(name);
+    let is_safe = pb.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    (is_safe && !name.is_empty()).then_some(pb)
+}
+
+
+
+/// Get the directory where members of `archive` get extracted to
+pub fn get_extract_dir(archive: &Path) -> PathBuf {
+    let mut s = DefaultHasher::new();
+    archive.hash(&mut s);
+    get_temp_dir().join(format!("archives/{}/", s.finish()))
+}
+
+/// Extract the member at the virtual path `path` into [`get_extract_dir`]
+/// Returns the path of the extracted file
+/// Members that were already extracted are reused, unless the archive has changed since
+pub fn extract_member(path: &Path) -> Result<PathBuf, ArchiveError> {
+    let (archive, member) = split_member_path(path)
+        .ok_or(ArchiveError::NotAMember)?;
+    let kind = ArchiveKind::from_path(&archive)
+        .ok_or(ArchiveError::NotAMember)?;
+    // Members that would escape the extract dir can't have been listed
+    let member = sanitize_member(&member.to_string_lossy())
+        .ok_or(ArchiveError::MemberNotFound)?;
+    let dest = get_extract_dir(&archive).join(&member);
+
+    // Already extracted
+    let archive_modified = fs::metadata(&archive)?.modified()?;
+    if fs::metadata(&dest).and_then(|m| m.modified()).is_ok_and(|t| t >= archive_modified) {
+        return Ok(dest);
+    }
+
+    if let Some(dir) = dest.parent() {
+        create_dir_all(dir)?;
+    }
+
+    let found: bool = match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(File::open(&archive)?)?;
+            let name: String = member.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let mut file = zip.by_name(&name).map_err(|err| match err {
+                zip::result::ZipError::FileNotFound => ArchiveError::MemberNotFound,
+                err => err.into(),
+            })?;
+            io::copy(&mut file, &mut File::create(&dest)?)?;
+            true
+        }
+
+        ArchiveKind::Tar => extract_tar_member(File::open(&archive)?, &member, &dest)?,
+        ArchiveKind::TarGz => extract_tar_member(GzDecoder::new(File::open(&archive)?), &member, &dest)?,
+
+        ArchiveKind::SevenZ => {
+            let mut found = false;
+            sevenz_rust::SevenZReader::open(&archive, sevenz_rust::Password::empty())?
+                .for_each_entries(|entry, reader| {
+                    if sanitize_member(entry.name()).as_ref() != Some(&member) {
+                        // Entries in the same block must be read through to get to the next
+                        io::copy(reader, &mut io::sink())?;
+                        return Ok(true);
+                    }
+                    io::copy(reader, &mut File::create(&dest)?)?;
+                    found = true;
+                    Ok(false)
+                })?;
+            found
+        }
+    };
+
+    if !found {
+        return Err(ArchiveError::MemberNotFound);
+    }
+    Ok(dest)
+}
+
+fn extract_tar_member<R: Read>(reader: R, member: &Path, dest: &Path) -> Result<bool, ArchiveError> {
+    let mut tar = tar::Archive::new(reader);
+
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let is_member = entry.path().ok()
+            .and_then(|p| sanitize_member(&p.to_string_lossy()))
+            .is_some_and(|p| p == member);
+        if is_member {
+            io::copy(&mut entry, &mut File::create(dest)?)?;
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    use crate::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn member_paths() {
+        let p = member_path(Path::new("/home/user/assets.zip"), Path::new("docs/readme.md"));
+        assert_eq!(p, PathBuf::from("/home/user/assets.zip!/docs/readme.md"));
+        assert_eq!(
+            split_member_path(&p),
+            Some((PathBuf::from("/home/user/assets.zip"), PathBuf::from("docs/readme.md")))
+        );
+
+        assert_eq!(
+            split_member_path(Path::new("/home/user/wow!/backup.tar.gz!/a.txt")),
+            Some((PathBuf::from("/home/user/wow!/backup.tar.gz"), PathBuf::from("a.txt")))
+        );
+        assert_eq!(split_member_path(Path::new("/home/user/assets.zip")), None);
+        assert_eq!(split_member_path(Path::new("/home/user/assets.zip!")), None);
+        assert_eq!(split_member_path(Path::new("/home/user/wow!/a.txt")), None);
+
+        assert_eq!(sanitize_member("../../etc/passwd"), None);
+        assert_eq!(sanitize_member("docs/readme.md"), Some(PathBuf::from("docs/readme.md")));
+    }
+
+    #[test]
+    fn zip_members() {
+        let dir = TestDir::new("archives");
+        let archive = dir.join("assets.zip");
+
+        let mut zip = zip::ZipWriter::new(File::create(&archive).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.add_directory("docs/", options).unwrap();
+        zip.start_file("docs/readme.md", options).unwrap();
+        zip.write_all(b"hello").unwrap();
+        zip.start_file("icon.png", options).unwrap();
+        zip.finish().unwrap();
+
+        let mut members = list_members(&archive, &CancelToken::new()).unwrap();
+        members.sort();
+        let listed: Vec<(PathBuf, u64)> = members.iter()
+            .map(|m| (m.path.clone(), m.size))
+            .collect();
+        assert_eq!(listed, vec![ (PathBuf::from("docs/readme.md"), 5), (PathBuf::from("icon.png"), 0) ]);
+        assert!(members.iter().all(|m| m.modified.is_some()));
+
+        let extracted = extract_member(&member_path(&archive, Path::new("docs/readme.md"))).unwrap();
+        assert_eq!(fs::read_to_string(&extracted).unwrap(), "hello");
+
+        assert!(matches!(
+            extract_member(&member_path(&archive, Path::new("nope.txt"))),
+            Err(ArchiveError::MemberNotFound)
+        ));
+        assert!(matches!(
+            extract_member(&member_path(&archive, Path::new("../../escaped.txt"))),
+            Err(ArchiveError::MemberNotFound)
+        ));
+
+        let _ = fs::remove_dir_all(get_extract_dir(&archive));
+    }
+
+    #[test]
+    fn tar_members() {
+        let dir = TestDir::new("tar-members");
+        let archive = dir.join("backup.tar");
+
+        let mut tar = tar::Builder::new(File::create(&archive).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_cksum();
+        tar.append_data(&mut header, "notes.txt", &b"hello"[..]).unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+
+        let cancelled = CancelToken::new();
+        cancelled.cancel();
+        assert!(matches!(list_members(&archive, &cancelled), Err(ArchiveError::Cancelled)));
+        assert!(cached_members(&archive, &cancelled).is_err());
+
+        let members = cached_members(&archive, &CancelToken::new()).unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].path, PathBuf::from("notes.txt"));
+        // Listed once, then served from the cache, even though this token is cancelled
+        assert!(Arc::ptr_eq(&members, &cached_members(&archive, &cancelled).unwrap()));
+    }
+}
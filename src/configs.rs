@@ -1,8 +1,10 @@
-use std::{fs::{create_dir_all, File}, io::{self, Read, Write}, path::PathBuf, sync::{Mutex, MutexGuard, OnceLock}};
+use std::{collections::HashMap, fs::{create_dir_all, File}, io::{self, Read, Write}, path::PathBuf, sync::{Mutex, MutexGuard, OnceLock}};
 
 use nanoserde::{DeJson, DeJsonErr, SerJson};
 use thiserror::Error;
 
+use crate::search::DEFAULT_MAX_CONTENT_SIZE;
+use crate::strmatch::CaseMode;
 use crate::{error, APP_NAME};
 
 
@@ -20,16 +22,22 @@ pub enum LoadError {
     ParseError(#[from] DeJsonErr),
 }
 
-/// Gets the save path of the configs file
-/// Returns `None` if no [`directories::BaseDirs`] was found
-pub fn get_save_path() -> io::Result<PathBuf> {
+/// Gets the path of the file `name` in the app's config directory
+/// Fails if no [`directories::BaseDirs`] was found
+pub fn get_config_file_path(name: &str) -> io::Result<PathBuf> {
     Ok(directories::BaseDirs::new()
         .ok_or_else(||
             io::Error::new(io::ErrorKind::NotFound, "Failed to get BaseDirs instance")
         )?
         .config_dir()
         .to_path_buf()
-        .join(format!("{}/configs.json", APP_NAME)))
+        .join(format!("{}/{}", APP_NAME, name)))
+}
+
+/// Gets the save path of the configs file
+#[inline]
+pub fn get_save_path() -> io::Result<PathBuf> {
+    get_config_file_path("configs.json")
 }
 
 
@@ -102,6 +110,26 @@ pub struct Configs {
     pub max_result_count: usize,
     pub max_results_per_tick: usize,
     pub update_rate_ms: u64,
+    /// Whether to honour `.gitignore` and `.ignore` files inside tagged folders
+    #[nserde(default = "true")]
+    pub respect_ignore_files: bool,
+    /// Whether to search through the contents of archive files (.zip, .tar, etc.)
+    #[nserde(default = "true")]
+    pub browse_archives: bool,
+    /// How many threads to search through tagged folders with
+    /// `0` means one thread per CPU core. See [`Configs::get_search_thread_count`]
+    #[nserde(default = "0")]
+    pub search_thread_count: u8,
+    /// Files bigger than this many bytes are skipped by `content:` searches
+    #[nserde(default = "10485760")]
+    pub max_content_search_size: u64,
+    /// Extensions to add to file kinds, e.g. `{ "code": ["nim"], "3d": ["blend", "fbx"] }`
+    /// Kinds that aren't built-in are created. See [`crate::kind::KindTable`]
+    #[nserde(default)]
+    pub kind_extensions: HashMap<String, Vec<String>>,
+    /// Whether letter case matters when searching, unless a query sets it with `case:`
+    #[nserde(default)]
+    pub case_mode: CaseMode,
 }
 
 impl Configs {
@@ -109,6 +137,14 @@ impl Configs {
     pub fn save(&self) -> io::Result<()> {
         save_configs(self)
     }
+
+    /// Get [`Configs::search_thread_count`], with `0` resolved to the number of CPU cores
+    pub fn get_search_thread_count(&self) -> usize {
+        match self.search_thread_count {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n as usize,
+        }
+    }
 }
 
 impl Default for Configs {
@@ -121,6 +157,12 @@ impl Default for Configs {
             max_results_per_tick: 10,
             max_result_count: 256,
             update_rate_ms: 100,
+            respect_ignore_files: true,
+            browse_archives: true,
+            search_thread_count: 0,
+            max_content_search_size: DEFAULT_MAX_CONTENT_SIZE,
+            kind_extensions: HashMap::new(),
+            case_mode: CaseMode::default(),
         }
     }
 }
@@ -0,0 +1,407 @@
+use std::borrow::Cow;
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{DirEntry, WalkBuilder, WalkState};
+
+use crate::tagging::entries::EntryOptions;
+use crate::tagging::Tag;
+use crate::{configs, error, info};
+
+
+/// Contents of the global ignore file when it is first created
+const DEFAULT_GLOBAL_IGNORE: &str = "\
+# Paths matching these patterns are skipped when searching through tagged folders
+# Uses the same syntax as .gitignore files
+# Prefix a pattern with `!` to include it again, e.g. `!.config/`
+.*
+node_modules/
+";
+
+/// Patterns of the global ignore file, loaded the first time they're needed
+/// See [`reload_global_patterns`]
+static GLOBAL_PATTERNS: RwLock<Option<Vec<String>>> = RwLock::new(None);
+
+
+
+/// Gets the path of the global ignore file
+#[inline]
+pub fn get_global_ignore_path() -> io::Result<PathBuf> {
+    configs::get_config_file_path("ignore")
+}
+
+/// Load the patterns in the global ignore file
+/// If it doesn't exist yet, it gets created with some default patterns
+pub fn load_global_patterns() -> io::Result<Vec<String>> {
+    let path = get_global_ignore_path()?;
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            info!("Initializing global ignore file.");
+            if let Some(dir) = path.parent() {
+                create_dir_all(dir)?;
+            }
+            File::create(&path)?
+                .write_all(DEFAULT_GLOBAL_IGNORE.as_bytes())?;
+            DEFAULT_GLOBAL_IGNORE.to_string()
+        }
+        Err(err) => return Err(err),
+    };
+
+    Ok(parse_patterns(&contents))
+}
+
+/// Read the global ignore file again, e.g. after it was edited
+/// If it fails to load, falls back to the default patterns
+pub fn reload_global_patterns() -> io::Result<()> {
+    let res = load_global_patterns();
+    let patterns = match &res {
+        Ok(patterns) => patterns.clone(),
+        Err(_) => parse_patterns(DEFAULT_GLOBAL_IGNORE),
+    };
+    if let Ok(mut cache) = GLOBAL_PATTERNS.write() {
+        *cache = Some(patterns);
+    }
+    res.map(|_| ())
+}
+
+/// Get the cached patterns of the global ignore file, loading them if needed
+fn get_global_patterns() -> Vec<String> {
+    if let Some(patterns) = GLOBAL_PATTERNS.read().ok().and_then(|cache| cache.clone()) {
+        return patterns;
+    }
+
+    if let Err(err) = reload_global_patterns() {
+        error!("[ignore_rules::get_global_patterns()] Failed to load global ignore file:\n {:?}", err);
+    }
+    GLOBAL_PATTERNS.read().ok()
+        .and_then(|cache| cache.clone())
+        .unwrap_or_else(|| parse_patterns(DEFAULT_GLOBAL_IGNORE))
+}
+
+/// Gitignore-style pattern that only matches `relative`, from the root of a walked folder
+pub fn anchored_pattern(relative: &Path) -> String {
+    let mut pattern = String::from("/");
+    for (i, component) in relative.components().enumerate() {
+        if i > 0 {
+            pattern.push('/');
+        }
+        for c in component.as_os_str().to_string_lossy().chars() {
+            if matches!(c, '\\' | '*' | '?' | '[' | ']' | '!' | '#') {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+    }
+    pattern
+}
+
+/// Split the contents of an ignore file into patterns, skipping comments and empty lines
+pub fn parse_patterns(str: &str) -> Vec<String> {
+    str.lines()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+
+
+/// Rules deciding which paths to skip when walking through tagged folders
+/// See [`IgnoreRules::walk`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct IgnoreRules {
+    /// Gitignore-style patterns, relative to each walked folder
+    /// Full paths are also accepted, and only match that path. See [`IgnoreRules::build_matcher`]
+    pub patterns: Vec<String>,
+    /// Whether to also honour `.gitignore` and `.ignore` files found in walked folders
+    pub respect_ignore_files: bool,
+}
+
+impl IgnoreRules {
+    /// Rules that don't ignore anything
+    pub fn none() -> Self {
+        IgnoreRules::default()
+    }
+
+    /// Rules from the global ignore file and [`configs::Configs`]
+    /// The file is only read once, see [`reload_global_patterns`]
+    /// If it fails to load, falls back to the default patterns
+    pub fn global() -> Self {
+        IgnoreRules {
+            patterns: get_global_patterns(),
+            respect_ignore_files: configs::global().respect_ignore_files,
+        }
+    }
+
+    /// Global rules, along with the ignore patterns of `tag` and all its subtags
+    pub fn for_tag(tag: &Tag) -> Self {
+        IgnoreRules::global()
+            .with_tag_patterns(tag)
+    }
+
+    /// Add the ignore patterns of `tag` and all its subtags
+    pub fn with_tag_patterns(mut self, tag: &Tag) -> Self {
+        self.extend_patterns(tag.ignore_patterns.iter().cloned());
+        for subtag in tag.iter_all_subtags() {
+            self.extend_patterns(subtag.ignore_patterns.iter().cloned());
+        }
+        self
+    }
+
+    /// Add `patterns`, skipping those already contained
+    pub fn extend_patterns<I>(&mut self, patterns: I)
+    where I: IntoIterator<Item = String>,
+    {
+        for p in patterns {
+            if !self.patterns.contains(&p) {
+                self.patterns.push(p);
+            }
+        }
+    }
+
+    /// Returns whether these rules skip everything `base` skips, e.g. the rules of a tag
+    /// compared to the global ones
+    /// If so, what a walk with `base` finds only has to be filtered with these rules
+    pub fn extends(&self, base: &IgnoreRules) -> bool {
+        self.respect_ignore_files == base.respect_ignore_files
+            && self.patterns.starts_with(&base.patterns)
+            // `!` patterns could bring back paths `base` skipped
+            && !self.patterns[base.patterns.len()..].iter().any(|p| p.starts_with('!'))
+    }
+
+    /// Recursively walk through `dir` according to `options`, skipping ignored paths
+    /// `dir` itself is always included
+    pub fn walk(&self, dir: &Path, options: EntryOptions) -> Box<dyn Iterator<Item = PathBuf>> {
+        Box::new(self.walk_entries(dir, options).map(|de| de.into_path()))
+    }
+
+    /// Same as [`IgnoreRules::walk`], but yields the [`DirEntry`]s, to get their metadata
+    pub fn walk_entries(&self, dir: &Path, options: EntryOptions) -> Box<dyn Iterator<Item = DirEntry>> {
+        let matcher = self.build_matcher(dir);
+
+        let walker = self.walk_builder(dir, options)
+            .filter_entry(move |de| {
+                let is_dir = de.file_type().is_some_and(|ft| ft.is_dir());
+                de.depth() == 0 || !matcher.matched(de.path(), is_dir).is_ignore()
+            })
+            .build()
+            .flatten();
+
+        Box::new(walker)
+    }
+
+    /// Same as [`IgnoreRules::walk`] through each of `dirs`, but spread over `thread_count` threads
+    /// `visit` gets called on every entry from any of the threads, and can return `false` to stop
+    /// the walk early
+    pub fn walk_parallel<F>(&self, dirs: &[(PathBuf, EntryOptions)], thread_count: usize, visit: F)
+    where F: Fn(DirEntry) -> bool + Sync,
+    {
+        // Options apply to a whole walk, so folders with the same options get walked together
+        let mut groups: Vec<(EntryOptions, Vec<&PathBuf>)> = Vec::new();
+        for (dir, options) in dirs {
+            match groups.iter_mut().find(|(o, _)| o == options) {
+                Some((_, group)) => group.push(dir),
+                None => groups.push((*options, vec![dir])),
+            }
+        }
+
+        let quit = AtomicBool::new(false);
+        for (options, group) in groups {
+            let Some((first, rest)) = group.split_first() else {
+                continue;
+            };
+            if quit.load(Ordering::Relaxed) {
+                return;
+            }
+
+            let matchers: Vec<(PathBuf, Gitignore)> = group.iter()
+                .map(|dir| (dir.to_path_buf(), self.build_matcher(dir)))
+                .collect();
+
+            let mut builder = self.walk_builder(first, options);
+            for dir in rest {
+                builder.add(dir);
+            }
+
+            builder.threads(thread_count)
+                .filter_entry(move |de| {
+                    if de.depth() == 0 {
+                        return true;
+                    }
+                    let is_dir = de.file_type().is_some_and(|ft| ft.is_dir());
+                    // Use the matcher of the innermost folder, in case they are nested
+                    matchers.iter()
+                        .filter(|(dir, _)| de.path().starts_with(dir))
+                        .max_by_key(|(dir, _)| dir.as_os_str().len())
+                        .is_none_or(|(_, matcher)| !matcher.matched(de.path(), is_dir).is_ignore())
+                })
+                .build_parallel()
+                .run(|| {
+                    let visit = &visit;
+                    let quit = &quit;
+                    Box::new(move |res| {
+                        let Ok(de) = res else {
+                            return WalkState::Continue;
+                        };
+                        if visit(de) {
+                            WalkState::Continue
+                        } else {
+                            quit.store(true, Ordering::Relaxed);
+                            WalkState::Quit
+                        }
+                    })
+                });
+        }
+    }
+
+    fn walk_builder(&self, dir: &Path, options: EntryOptions) -> WalkBuilder {
+        let mut builder = WalkBuilder::new(dir);
+        builder.follow_links(options.follow_links)
+            .max_depth(options.max_depth)
+            .standard_filters(false)
+            .git_ignore(self.respect_ignore_files)
+            .ignore(self.respect_ignore_files)
+            .parents(self.respect_ignore_files)
+            .require_git(false);
+        builder
+    }
+
+    /// Build a matcher for [`IgnoreRules::patterns`], rooted at `dir`
+    /// Patterns that are full paths inside `dir` are turned into [`anchored_pattern`]s, so that
+    /// they don't match the same relative path inside other folders
+    /// Invalid patterns get logged and skipped
+    pub fn build_matcher(&self, dir: &Path) -> Gitignore {
+        let mut builder = GitignoreBuilder::new(dir);
+        for pattern in self.patterns.iter() {
+            let pattern: Cow<str> = match Path::new(pattern).strip_prefix(dir) {
+                Ok(relative) if relative.as_os_str().is_empty() => continue,
+                Ok(relative) => Cow::Owned(anchored_pattern(relative)),
+                Err(_) => Cow::Borrowed(pattern),
+            };
+            if let Err(err) = builder.add_line(None, &pattern) {
+                error!("[IgnoreRules::build_matcher()] Invalid ignore pattern `{}`:\n {}", pattern, err);
+            }
+        }
+
+        builder.build().unwrap_or_else(|err| {
+            error!("[IgnoreRules::build_matcher()] Failed to build ignore matcher:\n {}", err);
+            Gitignore::empty()
+        })
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, create_dir_all, File};
+    use std::path::{Path, PathBuf};
+
+    use crate::tagging::entries::EntryOptions;
+    use crate::TestDir;
+
+    use super::{anchored_pattern, parse_patterns, IgnoreRules};
+
+    #[test]
+    fn pattern_parsing() {
+        let patterns = parse_patterns("# comment\n.*\n\n  \nnode_modules/\n!.config/\n");
+        assert_eq!(patterns, vec![ ".*", "node_modules/", "!.config/" ]);
+        assert_eq!(anchored_pattern(Path::new("a b/[1]*.png")), "/a b/\\[1\\]\\*.png");
+    }
+
+    #[test]
+    fn walking() {
+        let dir = TestDir::new("ignore-rules");
+        for d in [ ".git", ".config", "node_modules/pkg", "src" ] {
+            create_dir_all(dir.join(d)).unwrap();
+        }
+        for f in [ ".git/HEAD", ".config/settings", "node_modules/pkg/index.js", "src/main.rs", "debug.log" ] {
+            File::create(dir.join(f)).unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "*.log\n").unwrap();
+
+        let walk = |rules: &IgnoreRules| -> Vec<PathBuf> {
+            let mut v: Vec<PathBuf> = rules.walk(&dir, EntryOptions::default())
+                .map(|pb| pb.strip_prefix(&dir).unwrap().to_path_buf())
+                .collect();
+            v.sort();
+            v
+        };
+
+        let rules = IgnoreRules {
+            patterns: parse_patterns(".*\nnode_modules/\n!.config/"),
+            respect_ignore_files: true,
+        };
+        assert_eq!(walk(&rules), vec![
+            Path::new(""),
+            Path::new(".config"),
+            Path::new(".config/settings"),
+            Path::new("src"),
+            Path::new("src/main.rs"),
+        ]);
+
+        // Parallel walks find the same paths
+        let found = std::sync::Mutex::new(Vec::new());
+        rules.walk_parallel(&[ (dir.clone(), EntryOptions::default()) ], 4, |de| {
+            found.lock().unwrap().push(de.path().strip_prefix(&dir).unwrap().to_path_buf());
+            true
+        });
+        let mut found = found.into_inner().unwrap();
+        found.sort();
+        assert_eq!(found, walk(&rules));
+
+        // Not respecting .gitignore
+        let rules = IgnoreRules {
+            respect_ignore_files: false,
+            ..rules
+        };
+        assert!(walk(&rules).contains(&PathBuf::from("debug.log")));
+
+        // Nothing ignored
+        assert_eq!(walk(&IgnoreRules::none()).len(), 12);
+
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn walking_options() {
+        let dir = TestDir::new("entry-options");
+        create_dir_all(dir.join("a/b/c")).unwrap();
+        File::create(dir.join("a/b/c/deep.txt")).unwrap();
+        // Link back to the root, which would loop forever if followed blindly
+        std::os::unix::fs::symlink(&dir, dir.join("a/loop")).unwrap();
+
+        let walk = |options: EntryOptions| -> Vec<PathBuf> {
+            let mut v: Vec<PathBuf> = IgnoreRules::none().walk(&dir, options)
+                .map(|pb| pb.strip_prefix(&dir).unwrap().to_path_buf())
+                .collect();
+            v.sort();
+            v
+        };
+
+        assert_eq!(walk(EntryOptions::direct_children_only()), vec![
+            Path::new(""),
+            Path::new("a"),
+        ]);
+
+        assert_eq!(walk(EntryOptions::default().with_max_depth(Some(2))), vec![
+            Path::new(""),
+            Path::new("a"),
+            Path::new("a/b"),
+            Path::new("a/loop"),
+        ]);
+
+        // Loop gets detected and skipped
+        let followed = walk(EntryOptions::default().with_follow_links(true));
+        assert!(followed.contains(&PathBuf::from("a/b/c/deep.txt")));
+        assert!(!followed.iter().any(|pb| pb.starts_with("a/loop/a")));
+
+    }
+}
@@ -0,0 +1,248 @@
+//! Telling what kind of file a path is (image, video, code, etc.)
+//! Used by `kind:` constraints and for file icons
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::archive;
+
+
+static TABLE: OnceLock<KindTable> = OnceLock::new();
+
+/// Built-in kinds and their file extensions
+/// More can be added with [`crate::configs::Configs::kind_extensions`]
+pub const BUILTIN_KINDS: [(&str, &[&str]); 6] = [
+    ("image", &[
+        "png", "jpg", "jpeg", "gif", "bmp", "webp", "tif", "tiff", "ico", "svg", "heic", "heif",
+        "avif", "psd", "xcf", "kra", "ase", "aseprite", "raw", "cr2", "nef", "dng",
+    ]),
+    ("video", &[
+        "mp4", "mkv", "mov", "avi", "webm", "wmv", "flv", "m4v", "mpg", "mpeg", "3gp", "ogv",
+    ]),
+    ("audio", &[
+        "mp3", "wav", "ogg", "flac", "aac", "m4a", "opus", "wma", "aiff", "mid", "midi",
+    ]),
+    ("document", &[
+        "txt", "md", "pdf", "doc", "docx", "odt", "rtf", "xls", "xlsx", "ods", "ppt", "pptx", "odp",
+        "csv", "epub", "tex",
+    ]),
+    ("archive", &[
+        "zip", "tar", "gz", "tgz", "7z", "rar", "bz2", "xz", "zst", "iso",
+    ]),
+    ("code", &[
+        "rs", "py", "js", "mjs", "ts", "jsx", "tsx", "c", "h", "cpp", "hpp", "cc", "cs", "java", "kt",
+        "go", "rb", "php", "lua", "sh", "bash", "ps1", "bat", "html", "css", "scss", "json", "toml",
+        "yaml", "yml", "xml", "sql", "gd", "glsl", "wgsl", "hlsl", "zig", "swift", "dart", "vue",
+    ]),
+];
+
+/// How many bytes at the start of a file to read when sniffing its kind
+/// `tar` archives are only recognizable at byte 257
+const SNIFF_LEN: usize = 512;
+
+
+
+/// Maps file extensions to kinds
+/// See [`table`]
+#[derive(Debug, Clone, Default)]
+pub struct KindTable {
+    /// Kinds and their extensions, built-in ones first
+    kinds: Vec<(String, Vec<String>)>,
+    /// Extension => index in [`KindTable::kinds`]
+    extensions: HashMap<String, usize>,
+}
+
+impl KindTable {
+    /// Table with only the [`BUILTIN_KINDS`]
+    pub fn builtin() -> Self {
+        let mut table = KindTable::default();
+        for (kind, extensions) in BUILTIN_KINDS.iter() {
+            table.add(kind, extensions.iter().map(|ext| ext.to_string()));
+        }
+        table
+    }
+
+    /// Add `extensions` to `kind`, creating it if it doesn't exist
+    /// Extensions that already belong to another kind are moved to this one
+    pub fn add<I>(&mut self, kind: &str, extensions: I)
+    where I: IntoIterator<Item = String>,
+    {
+        let kind = kind.to_lowercase();
+        let index = match self.kinds.iter().position(|(k, _)| *k == kind) {
+            Some(index) => index,
+            None => {
+                self.kinds.push((kind, Vec::new()));
+                self.kinds.len() - 1
+            }
+        };
+
+        for ext in extensions {
+            let ext = ext.trim_start_matches('.').to_lowercase();
+            if let Some(previous) = self.extensions.insert(ext.clone(), index) {
+                self.kinds[previous].1.retain(|e| *e != ext);
+            }
+            self.kinds[index].1.push(ext);
+        }
+    }
+
+    /// Add the kinds from [`crate::configs::Configs::kind_extensions`]
+    pub fn with_custom_kinds(mut self, custom: &HashMap<String, Vec<String>>) -> Self {
+        // Sorted so that the order doesn't depend on the hash map
+        let mut custom: Vec<(&String, &Vec<String>)> = custom.iter().collect();
+        custom.sort();
+        for (kind, extensions) in custom {
+            self.add(kind, extensions.iter().cloned());
+        }
+        self
+    }
+
+    pub fn contains_kind(&self, kind: &str) -> bool {
+        self.kinds.iter().any(|(k, _)| k == kind)
+    }
+
+    /// Names of all kinds, built-in ones first
+    pub fn kind_names(&self) -> impl Iterator<Item = &str> {
+        self.kinds.iter().map(|(k, _)| k.as_str())
+    }
+
+    /// All extensions of `kind`
+    pub fn extensions_of(&self, kind: &str) -> &[String] {
+        self.kinds.iter()
+            .find(|(k, _)| k == kind)
+            .map_or(&[], |(_, extensions)| extensions.as_slice())
+    }
+
+    /// Kind of files with the extension `ext`, if known
+    pub fn kind_of_extension(&self, ext: &str) -> Option<&str> {
+        let index = *self.extensions.get(&ext.to_lowercase())?;
+        Some(self.kinds[index].0.as_str())
+    }
+
+    /// Kind of `path`, from its extension
+    /// Files without an extension are sniffed instead. See [`sniff`]
+    pub fn kind_of(&self, path: &Path) -> Option<&str> {
+        match path.extension() {
+            Some(ext) => self.kind_of_extension(&ext.to_string_lossy()),
+            None if path.is_file() && !archive::is_member_path(path) => sniff(path),
+            None => None,
+        }
+    }
+}
+
+
+
+/// Set the custom kinds of the global [`table`]
+/// This can only be done once, before it is first used
+pub fn set_custom_kinds(custom: &HashMap<String, Vec<String>>) {
+    if TABLE.set(KindTable::builtin().with_custom_kinds(custom)).is_err() {
+        crate::error!("[kind::set_custom_kinds()] Kind table was already initialized");
+    }
+}
+
+/// The global [`KindTable`]
+/// Only has the [`BUILTIN_KINDS`] unless [`set_custom_kinds`] was called first
+pub fn table() -> &'static KindTable {
+    TABLE.get_or_init(KindTable::builtin)
+}
+
+/// Tell the kind of the file at `path` from its first few bytes
+/// Only knows about the [`BUILTIN_KINDS`]
+pub fn sniff(path: &Path) -> Option<&'static str> {
+    let mut buf = [0u8; SNIFF_LEN];
+    let mut file = File::open(path).ok()?;
+    let mut len: usize = 0;
+    while len < SNIFF_LEN {
+        match file.read(&mut buf[len..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
+        }
+    }
+    sniff_bytes(&buf[..len])
+}
+
+/// See [`sniff`]
+pub fn sniff_bytes(bytes: &[u8]) -> Option<&'static str> {
+    let starts_with = |magic: &[u8]| bytes.starts_with(magic);
+    let riff_type = bytes.get(8..12).filter(|_| starts_with(b"RIFF"));
+    let ftyp_brand = bytes.get(8..12).filter(|_| bytes.get(4..8) == Some(b"ftyp"));
+
+    if starts_with(b"\x89PNG") || starts_with(b"\xFF\xD8\xFF") || starts_with(b"GIF8")
+        || starts_with(b"II*\0") || starts_with(b"MM\0*") || riff_type == Some(b"WEBP")
+    {
+        return Some("image");
+    }
+
+    if starts_with(b"ID3") || starts_with(b"fLaC") || starts_with(b"OggS")
+        || starts_with(b"\xFF\xFB") || starts_with(b"\xFF\xF3") || starts_with(b"\xFF\xF2")
+        || riff_type == Some(b"WAVE") || ftyp_brand == Some(b"M4A ")
+    {
+        return Some("audio");
+    }
+
+    if ftyp_brand.is_some() || starts_with(b"\x1A\x45\xDF\xA3") || riff_type == Some(b"AVI ") {
+        return Some("video");
+    }
+
+    if starts_with(b"%PDF") {
+        return Some("document");
+    }
+
+    if starts_with(b"PK\x03\x04") || starts_with(b"\x1F\x8B") || starts_with(b"7z\xBC\xAF\x27\x1C")
+        || starts_with(b"Rar!") || bytes.get(257..262) == Some(b"ustar")
+    {
+        return Some("archive");
+    }
+
+    if starts_with(b"#!") {
+        return Some("code");
+    }
+
+    None
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use super::{sniff_bytes, KindTable};
+
+    #[test]
+    fn kinds() {
+        let table = KindTable::builtin();
+        assert_eq!(table.kind_of(Path::new("C:/Pictures/cat.PNG")), Some("image"));
+        assert_eq!(table.kind_of(Path::new("C:/Projects/main.rs")), Some("code"));
+        assert_eq!(table.kind_of(Path::new("C:/Downloads/assets.tar.gz")), Some("archive"));
+        assert_eq!(table.kind_of(Path::new("C:/Downloads/setup.exe")), None);
+
+        let custom = HashMap::from([
+            ("code".to_string(), vec![ "nim".to_string() ]),
+            ("3D".to_string(), vec![ ".blend".to_string(), "fbx".to_string(), "svg".to_string() ]),
+        ]);
+        let table = KindTable::builtin().with_custom_kinds(&custom);
+        assert_eq!(table.kind_of_extension("nim"), Some("code"));
+        assert_eq!(table.kind_of_extension("BLEND"), Some("3d"));
+        // Moved out of images
+        assert_eq!(table.kind_of_extension("svg"), Some("3d"));
+        assert!(!table.extensions_of("image").contains(&"svg".to_string()));
+        assert!(table.contains_kind("3d"));
+    }
+
+    #[test]
+    fn sniffing() {
+        assert_eq!(sniff_bytes(b"\x89PNG\r\n\x1A\n"), Some("image"));
+        assert_eq!(sniff_bytes(b"RIFF\0\0\0\0WAVEfmt "), Some("audio"));
+        assert_eq!(sniff_bytes(b"\0\0\0\x20ftypisom"), Some("video"));
+        assert_eq!(sniff_bytes(b"\0\0\0\x20ftypM4A "), Some("audio"));
+        assert_eq!(sniff_bytes(b"%PDF-1.7"), Some("document"));
+        assert_eq!(sniff_bytes(b"PK\x03\x04"), Some("archive"));
+        assert_eq!(sniff_bytes(b"#!/bin/sh\necho hi"), Some("code"));
+        assert_eq!(sniff_bytes(b"hello"), None);
+        assert_eq!(sniff_bytes(b""), None);
+    }
+}
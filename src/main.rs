@@ -14,6 +14,10 @@ pub mod widget;
 pub mod strmatch;
 pub mod configs;
 pub mod log;
+pub mod ignore_rules;
+pub mod archive;
+pub mod kind;
+pub mod trash;
 
 use app::KFiles;
 use log::Log;
@@ -37,14 +41,20 @@ fn main() -> iced::Result {
         init_default_tags();
     }
 
+    kind::set_custom_kinds(&configs.kind_extensions);
     configs::set_global(configs) .expect("global Configs instance shouldn't be set before this");
 
+    // Query to start with, e.g. `kfiles "#pictures cat .png"`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let initial_query: Option<String> = (!args.is_empty()).then(|| args.join(" "));
+
     // Run program...
     let res = KFiles::run(Settings {
         window: iced::window::Settings {
             size: iced::Size::new(800.0, 400.0),
             ..Default::default()
         },
+        flags: initial_query,
         ..Default::default()
     });
 
@@ -252,6 +262,52 @@ mod fs {
 
 
 
+#[cfg(test)]
+mod test_dir {
+    use std::fs;
+    use std::ops::Deref;
+    use std::path::{Path, PathBuf};
+
+    /// Empty scratch directory for a test, removed when dropped, even if the test panics
+    #[derive(Debug)]
+    pub struct TestDir(PathBuf);
+
+    impl TestDir {
+        /// Creates `kfiles-test-{name}` in the temp directory, replacing whatever a previous
+        /// run left there
+        /// `name` has to be unique across tests, since they run in parallel
+        pub fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("kfiles-test-{name}"));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+    }
+
+    impl Deref for TestDir {
+        type Target = PathBuf;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for TestDir {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+}
+#[cfg(test)]
+use test_dir::TestDir;
+
+
 
 #[test]
 fn test_log() {
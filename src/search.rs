@@ -1,71 +1,185 @@
+use std::fs;
+use std::iter;
 use std::path::PathBuf;
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 
-use walkdir::{DirEntry, WalkDir};
+use chrono::Local;
 
 use crate::app::main_screen::Item;
-use crate::error;
-use crate::tagging::{ entries::Entries, Tag };
+use crate::ignore_rules::IgnoreRules;
+use crate::{archive, configs, error};
+use crate::tagging::{ self, annotation::AnnotationIndex, entries::{Entries, EntryOptions}, id::TagID, Tag };
 
-use self::constraint::ConstraintList;
+use self::constraint::{ConstraintList, ParseError};
+pub use self::constraint::{ContentMatch, Sort, SortKey, DEFAULT_MAX_CONTENT_SIZE};
+use self::index::{Meta, RootIndex};
 
+pub mod index;
+mod syntax;
 
-fn is_direntry_hidden(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| s.starts_with('.'))
-        .unwrap_or(false)
+
+/// How many indexed paths each search thread takes at a time
+const SCORE_BATCH_SIZE: usize = 256;
+
+
+/// Shared flag telling a running search to stop
+/// Searches check it before every path, so they stop soon after [`CancelToken::cancel`] even
+/// if nothing matches
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    #[inline]
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
 }
 
 
 
-/// Iterates through all paths in the filesystem within an [`Entries`]
+
+/// Iterates through all paths in the filesystem within an [`Entries`], skipping those
+/// ignored by `rules`
+/// Files directly in `entries` are never ignored
+/// Folders are looked up in their [`index::RootIndex`] if it is built, and walked otherwise
 /// See also [`Searcher`]
-pub fn iter_entries(entries: Entries) -> Box<dyn Iterator<Item = PathBuf>> {
-    // Files and folders merged with subtags
-    let (files, folders) = entries.into_iter()
-        .partition::<Vec<PathBuf>, _>(|pb| pb.is_file());
-    let mut iter: Box<dyn Iterator<Item = PathBuf>> = Box::new(files.into_iter());
+pub fn iter_entries(entries: Entries, rules: &IgnoreRules) -> Box<dyn Iterator<Item = PathBuf>> {
+    Box::new(iter_entries_with_meta(entries, rules, false).map(|(pb, _)| pb))
+}
 
-    for dir in folders {
-        let walker = WalkDir::new(dir).into_iter()
-            .filter_entry(|de| !is_direntry_hidden(de))
-            .flatten()
-            .map(|de| de.into_path());
-        iter = Box::new(iter.chain(walker));
+/// Same as [`iter_entries`], along with the [`Meta`] of each path if it's known
+/// Indexed paths always come with theirs, other paths only if `with_metadata` is set
+pub fn iter_entries_with_meta(
+    entries: Entries,
+    rules: &IgnoreRules,
+    with_metadata: bool,
+) -> Box<dyn Iterator<Item = (PathBuf, Option<Meta>)>> {
+    // Files and folders merged with subtags
+    let (files, folders) = entries.into_iter_with_options()
+        .partition::<Vec<(PathBuf, EntryOptions)>, _>(|(pb, _)| pb.is_file());
+    let mut iter: Box<dyn Iterator<Item = (PathBuf, Option<Meta>)>> = Box::new(files.into_iter()
+        .map(move |(pb, _)| {
+            let meta = read_meta(with_metadata, || fs::metadata(&pb).ok());
+            (pb, meta)
+        })
+    );
+
+    for (dir, options) in folders {
+        let paths: Box<dyn Iterator<Item = (PathBuf, Option<Meta>)>> = match index::get(&dir, options, rules) {
+            Some(index) => Box::new(index.iter_paths(rules).map(|(pb, meta)| (pb, Some(meta)))),
+            None => Box::new(rules.walk_entries(&dir, options).map(move |de| {
+                let meta = read_meta(with_metadata, || de.metadata().ok());
+                (de.into_path(), meta)
+            })),
+        };
+        iter = Box::new(iter.chain(paths));
     }
 
     iter
 }
 
+/// Get a [`Meta`] out of `read`, only if `with_metadata` is set
+#[inline]
+fn read_meta(with_metadata: bool, read: impl FnOnce() -> Option<fs::Metadata>) -> Option<Meta> {
+    with_metadata.then(read)
+        .flatten()
+        .map(|meta| Meta::from(&meta))
+}
+
+/// Same as [`archive::with_members`], with the [`Meta`] of each member taken from the archive
+/// `path` itself keeps `meta`
+fn with_members((path, meta): (PathBuf, Option<Meta>), token: &CancelToken) -> Box<dyn Iterator<Item = (PathBuf, Option<Meta>)>> {
+    Box::new(archive::with_members(path, token).map(move |(pb, member)| match member {
+        Some(member) => (pb, Some(Meta {
+            is_dir: false,
+            size: member.size,
+            modified: member.modified,
+            created: None,
+        })),
+        None => (pb, meta),
+    }))
+}
+
 
 
 
 /// Iteratively searches through some [`Entries`]
+/// Can also search inside archives (see [`Searcher::with_archives`])
 /// See also [`iter_entries`]
 pub struct Searcher {
-    iter: Box<dyn Iterator<Item = PathBuf>>,
+    iter: Box<dyn Iterator<Item = (PathBuf, Option<Meta>)>>,
     constraints: ConstraintList,
+    annotations: AnnotationIndex,
+    /// Whether to read the [`Meta`] of results that weren't found with theirs
+    with_metadata: bool,
+    /// See [`Searcher::with_cancel_token`]
+    cancel_token: CancelToken,
 }
 
 impl Searcher {
-    pub fn new(entries: Entries, constraints: ConstraintList) -> Self {
+    /// Metadata is read while walking if `constraints` need it
+    pub fn new(entries: Entries, constraints: ConstraintList, rules: &IgnoreRules) -> Self {
         Searcher {
-            iter: iter_entries(entries),
+            iter: iter_entries_with_meta(entries, rules, constraints.needs_metadata()),
             constraints,
+            annotations: AnnotationIndex::default(),
+            with_metadata: false,
+            cancel_token: CancelToken::new(),
         }
     }
+
+    /// Annotations to look up for `note:` constraints
+    pub fn with_annotations(mut self, annotations: AnnotationIndex) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    /// Make sure every result comes with its [`Meta`], e.g. to sort them by date
+    pub fn with_metadata(mut self) -> Self {
+        self.with_metadata = true;
+        self
+    }
+
+    /// Also search through the members of archive files, as virtual paths
+    /// Listing an archive stops once the token given to [`Searcher::with_cancel_token`] before
+    /// this is cancelled
+    /// See [`archive::member_path`]
+    pub fn with_archives(mut self) -> Self {
+        let token = self.cancel_token.clone();
+        self.iter = Box::new(self.iter.flat_map(move |entry| with_members(entry, &token)));
+        self
+    }
+
+    /// Stop searching once `token` is cancelled
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel_token = token.clone();
+        self.iter = Box::new(self.iter.take_while(move |_| !token.is_cancelled()));
+        self
+    }
 }
 
 impl Iterator for Searcher {
     type Item = Item;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.iter.find_map(|pb| {
-            self.constraints.score(&pb)
-                .map(|s| Item(s, pb))
+        self.iter.find_map(|(pb, mut meta)| {
+            let (score, content_match) = self.constraints.match_with_meta(&pb, &mut meta, &self.annotations.get(&pb))?;
+            if self.with_metadata && meta.is_none() {
+                meta = read_meta(true, || fs::metadata(&pb).ok());
+            }
+            Some(Item(score, pb, content_match, meta))
         })
     }
 }
@@ -81,6 +195,16 @@ pub struct Query {
     pub constraints: ConstraintList,
     pub receiver: Option< Receiver<Item> >,
     search_handle: Option<JoinHandle<()>>,
+    cancel_token: CancelToken,
+    /// Cancelled searches that may still be winding down
+    /// They are never waited on, only cleaned up once finished
+    stale_handles: Vec<JoinHandle<()>>,
+    /// Constraints of the last search
+    /// See [`Query::is_refinement`]
+    searched_constraints: Option<ConstraintList>,
+    /// Whether the results of the last search come with their [`Meta`]
+    /// See [`Query::search`]
+    has_metadata: bool,
 }
 
 impl Query {
@@ -91,15 +215,19 @@ impl Query {
     pub fn parse(query: &str) -> Query {
         Query {
             tags: Vec::new(),
-            constraints: ConstraintList::parse(query),
+            constraints: Query::parse_constraints(query),
             receiver: None,
             search_handle: None,
+            cancel_token: CancelToken::new(),
+            stale_handles: Vec::new(),
+            searched_constraints: None,
+            has_metadata: false,
         }
     }
 
     /// Returns whether this query has changed
     pub fn parse_query(&mut self, query: &str) -> bool {
-        let new_constraints = ConstraintList::parse(query);
+        let new_constraints = Query::parse_constraints(query);
         if new_constraints == self.constraints {
             return false;
         }
@@ -134,17 +262,104 @@ impl Query {
         self.constraints.is_empty()
     }
 
-    /// Begins the search.
-    pub fn search(&mut self) {
+    /// Parse `query` into a [`ConstraintList`], reporting tags that don't exist as errors
+    fn parse_constraints(query: &str) -> ConstraintList {
+        let mut constraints = ConstraintList::parse(query);
+
+        let tags_cache = tagging::tags_cache();
+        let unknown_tags = constraints.tags.iter()
+            .flat_map(|filter| filter.any_of.iter())
+            .filter(|id| !tags_cache.iter().any(|tag| tag.id == **id))
+            .map(|id| ParseError::UnknownTag(id.clone()))
+            .collect::<Vec<ParseError>>();
+        constraints.errors.extend(unknown_tags);
+
+        constraints
+    }
+
+    /// Returns whether the current constraints only narrow down those of the last search
+    /// If so, its results can be re-scored with [`Query::refine`] instead of searching again
+    /// Changing the tags doesn't count as a refinement
+    pub fn is_refinement(&self) -> bool {
+        self.searched_constraints.as_ref()
+            .is_some_and(|previous| self.get_cased_constraints().narrows(previous))
+    }
+
+    /// Re-score `items`, the complete results of the last search, with the current constraints
+    /// Items that don't match anymore are removed
+    /// Only use this if [`Query::is_refinement`], otherwise some results would be missing
+    /// The remaining items keep their order, so they have to be sorted again
+    pub fn refine(&mut self, items: &mut Vec<Item>) {
+        let annotations = if self.constraints.notes.is_empty() {
+            AnnotationIndex::default()
+        } else {
+            AnnotationIndex::from_tags(&self.get_included_tags())
+        };
+
+        let constraints = self.get_search_constraints();
+        items.retain_mut(|item| {
+            match constraints.match_with_meta(&item.1, &mut item.3, &annotations.get(&item.1)) {
+                Some((score, content_match)) => {
+                    item.0 = score;
+                    item.2 = content_match;
+                    true
+                }
+                None => false,
+            }
+        });
+
+        self.searched_constraints = Some(self.get_cased_constraints());
+    }
+
+    /// Stop the current search, if any
+    /// This never blocks: the search thread is left to finish on its own
+    pub fn cancel(&mut self) {
+        self.cancel_token.cancel();
+        self.receiver = None;
+        if let Some(handle) = self.search_handle.take() {
+            self.stale_handles.push(handle);
+        }
+
+        // Clean up searches that have finished
+        let (finished, running) = std::mem::take(&mut self.stale_handles)
+            .into_iter()
+            .partition::<Vec<_>, _>(|handle| handle.is_finished());
+        self.stale_handles = running;
+        for handle in finished {
+            if let Err(err) = handle.join() {
+                error!("[Query::cancel()] Search thread panicked:\n {err:?}");
+            }
+        }
+    }
+
+    /// Returns whether the results of the last search come with their [`Meta`]
+    #[inline]
+    pub fn has_metadata(&self) -> bool {
+        self.has_metadata
+    }
+
+    /// Begins the search, cancelling the previous one
+    /// `sort` is how the results will be ordered. If it compares metadata, the results are
+    /// sent along with their [`Meta`]
+    pub fn search(&mut self, sort: Sort) {
+        self.cancel();
+        self.cancel_token = CancelToken::new();
+        let token = self.cancel_token.clone();
+
         let (tx, rx) = mpsc::channel::<Item>();
 
-        let constraints = self.constraints.clone();
+        self.searched_constraints = Some(self.get_cased_constraints());
+        self.has_metadata = sort.key.needs_metadata();
+        let with_metadata: bool = self.has_metadata;
+        let constraints = self.get_search_constraints();
 
-        let it = self.tags.iter()
-            .map(|tag| tag.get_all_entries());
+        let tag_groups = self.get_tag_groups();
+        let group_count: usize = tag_groups.len();
+        let it = tag_groups.into_iter()
+            .map(|group| Entries::union_of(group.iter().map(Tag::get_all_entries)));
 
         let handle = if constraints.is_empty() {
-            let entries = if self.tags.len() > 1 {
+            let entries = if group_count > 1 {
                 Entries::intersection_of(it)
             } else {
                 Entries::from( it.flatten().collect::<Vec<PathBuf>>() )
@@ -152,43 +367,125 @@ impl Query {
             };
 
             thread::spawn(move ||
-                send_entries(tx, entries)
+                send_entries(tx, entries, with_metadata, token)
             )
         } else {
             let entries = Entries::intersection_of(it);
+            let annotations = if constraints.notes.is_empty() {
+                AnnotationIndex::default()
+            } else {
+                AnnotationIndex::from_tags(&self.get_included_tags())
+            };
+            let rules = self.get_ignore_rules();
+            let (browse_archives, thread_count) = {
+                let cfg = configs::global();
+                (cfg.browse_archives, cfg.get_search_thread_count())
+            };
             thread::spawn(move ||
-                search_entries(tx, entries, constraints)
+                search_entries(tx, entries, constraints, annotations, rules, browse_archives, thread_count, with_metadata, token)
             )
         };
 
         self.search_handle = Some(handle);
         self.receiver = Some(rx);
     }
+
+    /// Get the current constraints with the [`crate::strmatch::CaseMode`] they get searched with:
+    /// that of `case:` if any, otherwise [`configs::Configs::case_mode`]
+    /// Refinements are checked with these, since the same text may match differently
+    fn get_cased_constraints(&self) -> ConstraintList {
+        let mut constraints = self.constraints.clone();
+        if constraints.case_mode.is_none() {
+            constraints.set_case_mode(configs::global().case_mode);
+        }
+        constraints
+    }
+
+    /// Get the constraints to search with, with settings from [`configs::Configs`] applied
+    fn get_search_constraints(&self) -> ConstraintList {
+        let mut constraints = self.get_cased_constraints();
+        constraints.set_max_content_size(configs::global().max_content_search_size);
+        constraints.set_now(Local::now());
+
+        let excluded: Vec<Tag> = self.constraints.tags.iter()
+            .filter(|filter| filter.inverted)
+            .flat_map(|filter| find_tags(&filter.any_of))
+            .collect();
+        if !excluded.is_empty() {
+            constraints.excluded_entries = Some(Entries::union_of(excluded.iter().map(Tag::get_all_entries)));
+        }
+
+        constraints
+    }
+
+    /// Get the groups of tags to search through
+    /// Paths must be in at least one tag of every group
+    /// Each tag in [`Query::tags`] is its own group, followed by the tags in the query string
+    fn get_tag_groups(&self) -> Vec<Vec<Tag>> {
+        self.tags.iter()
+            .map(|tag| vec![tag.clone()])
+            .chain(self.constraints.tags.iter()
+                .filter(|filter| !filter.inverted)
+                .map(|filter| find_tags(&filter.any_of))
+            )
+            .collect()
+    }
+
+    /// Get all the tags searched through, both from [`Query::tags`] and the query string
+    fn get_included_tags(&self) -> Vec<Tag> {
+        self.get_tag_groups()
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// Get the [`IgnoreRules`] for this query's tags
+    /// If the query has the `--all` flag, nothing is ignored
+    fn get_ignore_rules(&self) -> IgnoreRules {
+        if self.constraints.show_ignored {
+            return IgnoreRules::none();
+        }
+
+        self.get_included_tags().iter()
+            .fold(IgnoreRules::global(), |rules, tag| rules.with_tag_patterns(tag))
+    }
 }
 
 impl Drop for Query {
     fn drop(&mut self) {
-        self.receiver = None;
-
-        // Join threads
-        if let Some(handle) = self.search_handle.take() {
-            if let Err(err) = handle.join() {
-                error!("[Query::drop()] Failed to join search handle:\n {err:?}");
-            }
-        }
+        // Not joined, since that could block the UI
+        // Search threads check the token often, so they won't outlive the query for long
+        self.cancel();
     }
 
 }
 
 
-/// Send entries in `entries` over `sender`
+/// Get the tags with the given `ids` from the tags cache
+/// Tags that don't exist are skipped
+fn find_tags(ids: &[TagID]) -> Vec<Tag> {
+    let tags_cache = tagging::tags_cache();
+    ids.iter()
+        .filter_map(|id| tags_cache.iter().find(|tag| tag.id == *id))
+        .cloned()
+        .collect()
+}
+
+
+/// Send entries in `entries` over `sender`, along with their [`Meta`] if `with_metadata` is set
 fn send_entries(
     sender: Sender<Item>,
     entries: Entries,
+    with_metadata: bool,
+    token: CancelToken,
 ) {
     let it = entries.into_iter()
+        .take_while(|_| !token.is_cancelled())
         .filter(|pb| pb.exists())
-        .map(|pb| Item(0, pb));
+        .map(|pb| {
+            let meta = read_meta(with_metadata, || fs::metadata(&pb).ok());
+            Item(0, pb, None, meta)
+        });
 
     for item in it {
         if sender.send(item).is_err() {
@@ -199,48 +496,189 @@ fn send_entries(
 
 /// Searches through all paths in in `entries` with the given [`ConstraintList`], and sends it over
 /// a `sender`
-/// Since the search can potentially take a while, also specify a break switch that will stop the
-/// searching if set to `true`
+/// The search is spread over `thread_count` threads, so results are sent in no particular order
+/// If `with_metadata` is set, every result is sent along with its [`Meta`]
+/// Stops as soon as `token` is cancelled or the receiver is dropped
+#[allow(clippy::too_many_arguments)]
 fn search_entries(
     sender: Sender<Item>,
     entries: Entries,
     constraints: ConstraintList,
+    annotations: AnnotationIndex,
+    rules: IgnoreRules,
+    browse_archives: bool,
+    thread_count: usize,
+    with_metadata: bool,
+    token: CancelToken,
 ) {
-    for item in Searcher::new(entries, constraints) {
-        if sender.send(item).is_err() {
+    if thread_count <= 1 {
+        let mut searcher = Searcher::new(entries, constraints, &rules) .with_annotations(annotations);
+        if with_metadata {
+            searcher = searcher.with_metadata();
+        }
+        searcher = searcher.with_cancel_token(token);
+        if browse_archives {
+            searcher = searcher.with_archives();
+        }
+
+        for item in searcher {
+            if sender.send(item).is_err() {
+                return;
+            }
+        }
+        return;
+    }
+
+    // Metadata is read while walking if scoring needs it, otherwise only for results
+    let walk_metadata: bool = constraints.needs_metadata();
+
+    // Score and send `pb`, and the members of `pb` if it's an archive
+    // Returns whether the search should go on
+    let visit = |entry: (PathBuf, Option<Meta>)| -> bool {
+        if token.is_cancelled() {
+            return false;
+        }
+
+        let paths: Box<dyn Iterator<Item = (PathBuf, Option<Meta>)>> = if browse_archives {
+            with_members(entry, &token)
+        } else {
+            Box::new(iter::once(entry))
+        };
+
+        for (pb, mut meta) in paths {
+            if token.is_cancelled() {
+                return false;
+            }
+            let Some((score, content_match)) = constraints.match_with_meta(&pb, &mut meta, &annotations.get(&pb)) else {
+                continue;
+            };
+            if with_metadata && meta.is_none() {
+                meta = read_meta(true, || fs::metadata(&pb).ok());
+            }
+            if sender.send(Item(score, pb, content_match, meta)).is_err() {
+                return false;
+            }
+        }
+        true
+    };
+
+    let (files, folders) = entries.into_iter_with_options()
+        .partition::<Vec<(PathBuf, EntryOptions)>, _>(|(pb, _)| pb.is_file());
+    for (pb, _) in files {
+        let meta = read_meta(walk_metadata, || fs::metadata(&pb).ok());
+        if !visit((pb, meta)) {
             return;
         }
     }
-}
 
+    let mut indexes: Vec<Arc<RootIndex>> = Vec::new();
+    let mut unindexed: Vec<(PathBuf, EntryOptions)> = Vec::new();
+    for (dir, options) in folders {
+        match index::get(&dir, options, &rules) {
+            Some(index) => indexes.push(index),
+            None => unindexed.push((dir, options)),
+        }
+    }
+
+    // Indexed folders are already listed, so only scoring needs to be spread out
+    let paths = Mutex::new(indexes.into_iter()
+        .flat_map(|index| index.iter_paths(&rules))
+        .map(|(pb, meta)| (pb, Some(meta)))
+    );
+    let has_quit = thread::scope(|s| {
+        let workers: Vec<_> = (0..thread_count)
+            .map(|_| s.spawn(|| loop {
+                let batch: Vec<(PathBuf, Option<Meta>)> = match paths.lock() {
+                    Ok(mut paths) => paths.by_ref().take(SCORE_BATCH_SIZE).collect(),
+                    Err(_) => return false,
+                };
+                if batch.is_empty() {
+                    return true;
+                }
+                if !batch.into_iter().all(visit) {
+                    return false;
+                }
+            }))
+            .collect();
+
+        !workers.into_iter().all(|w| w.join().unwrap_or(false))
+    });
+    if has_quit {
+        return;
+    }
+
+    rules.walk_parallel(&unindexed, thread_count, |de| {
+        let meta = read_meta(walk_metadata, || de.metadata().ok());
+        visit((de.into_path(), meta))
+    });
+}
 
 
 
 
 
 mod constraint {
-    use std::{ffi::{OsStr, OsString}, path::Path, sync::OnceLock};
+    use std::{borrow::Cow, cmp::Ordering, ffi::OsString, fmt::Display, fs, ops::Range, path::Path, time::SystemTime};
+    use chrono::{DateTime, Days, Local, Months, NaiveDate, TimeDelta, TimeZone};
     use regex::Regex;
+    use thiserror::Error;
+
+    use crate::{archive, kind, strmatch::{self, CaseMode, StringMatcher, Sublime}, ToPrettyString};
+    use crate::app::main_screen::Item;
+    use crate::tagging::{annotation::Annotation, entries::Entries, id::TagID};
+
+    use super::index::Meta;
 
-    use crate::{strmatch::{StringMatcher, Sublime}, ToPrettyString};
+
+    /// Default for [`ConstraintList::max_content_size`], in bytes
+    pub const DEFAULT_MAX_CONTENT_SIZE: u64 = 10 * 1024 * 1024;
+
+    /// How many bytes at the start of a file to check for NUL bytes, to tell binary files apart
+    const BINARY_CHECK_LEN: usize = 8 * 1024;
+
+    /// Matched lines longer than this many chars get cut off
+    const MAX_MATCHED_LINE_LEN: usize = 200;
+
+    /// Added to the score of [`Field::Auto`] fuzzy constraints that match within the file name
+    const NAME_MATCH_BONUS: isize = 100;
 
 
     /// Constraint list for file searching, given a query (see [`ConstraintList::parse()`] )
-    /// - Parts enclosed in quotes `"` will be matched in their entirety (simple case insensitive
-    /// `contains()` check)
+    /// - `name:`, `stem:`, `parent:`, `dir:` or `path:` only match part of the path. See [`Field`]
+    /// - Parts enclosed in quotes `"` will be matched in their entirety (simple `contains()` check)
     ///     If no closing quotes are found, the rest of the string is included
     /// - Parts that match `.ext` will filter files with the extension `ext`
+    /// - Parts with `*` or `?` wildcards will filter file names. See [`Glob`]
+    /// - `kind:image`, `kind:code`, etc. will filter files by kind. See [`Kind`]
+    /// - `#tag`, `!#tag` or `#a|#b` will filter paths by the tags they're in. See [`TagFilter`]
     /// - `--file` or `-f` will constrain the search to files only; while `--dir` or `-d`,
     /// directories (folders) only
+    /// - `note:"text"` or `note:text` will filter paths whose [`Annotation`] note contains `text`
+    /// - `/regex/` or `re:regex` will filter paths that match the regex. See [`Pattern`]
+    /// - `content:"text"`, `content:text` or `content:/regex/` will filter text files with a line
+    /// that matches. See [`Content`]
+    /// - `size:>10MB`, `size:<=4k` or `size:1M..50M` will filter files by size. See [`Size`]
+    /// - `modified:<7d`, `created:2024-03` or `accessed:today` will filter paths by date. See [`Date`]
+    /// - `sort:mtime` or `sort:-size` will order the results. See [`Sort`]
+    /// - `case:sensitive`, `case:insensitive` or `case:smart` will set whether letter case matters
+    /// for the whole query. See [`CaseMode`]
+    /// - `--all` or `-a` will also search through paths that would otherwise be ignored (see
+    /// [`crate::ignore_rules::IgnoreRules`])
     /// - Everything else will be scored via the [`Sublime`] string matcher
     ///
+    /// Text is matched with smart-case by default: case sensitive only if it has uppercase letters
+    /// Regexes are not affected, they have their own `i` flag
+    ///
     /// Any of the above fields can be negated by adding a `!` before them
+    /// They can also be combined with `|` (or), `( )` (grouping) and `!( )` (not). See [`Expr`]
+    /// A `\` before a special character makes it part of the text, e.g. `\-f` or `\"`
     /// E.g.
     /// ```
     /// "Hello \"World\"" // Score paths with "Hello", but only those that contain "World"
     /// "!.import \"all the rest" // Only files that contain "all the rest" that aren't .import files
     /// "-d !foo" // Search only directories, and exclude those that score "foo"
     /// "dino .png .ase" // Score paths with "dino", but only .png or .ase files
+    /// "dino (\"art\" | .ase) !(-d)" // Files with "dino", that either contain "art" or are .ase files
     /// ```
     #[derive(Debug, Clone, PartialEq, Eq, Default)]
     pub struct ConstraintList {
@@ -250,66 +688,165 @@ mod constraint {
         /// All AND-ed together
         pub exact: Vec<Exact>,
         /// Look for specific file extensions
-        /// All OR-ed together
+        /// All OR-ed together, along with [`ConstraintList::globs`]
         pub extensions: Vec<Extension>,
+        /// Match file names against wildcard patterns
+        /// All OR-ed together, along with [`ConstraintList::extensions`]
+        pub globs: Vec<Glob>,
+        /// Filter files by kind
+        /// All OR-ed together, or AND-ed if inverted
+        pub kinds: Vec<Kind>,
         /// Filter files or folders
         pub filetype: Option<FileType>,
+        /// Look for specific strings in annotation notes
+        /// All AND-ed together
+        pub notes: Vec<Note>,
+        /// Match regexes against the target path
+        /// All AND-ed together
+        pub patterns: Vec<Pattern>,
+        /// Look for lines inside text files
+        /// All AND-ed together
+        pub content: Vec<Content>,
+        /// Filter files by size
+        /// All AND-ed together
+        pub sizes: Vec<Size>,
+        /// Filter paths by modification, creation or access date
+        /// All AND-ed together
+        pub dates: Vec<Date>,
+        /// Files bigger than this many bytes are never searched through for [`ConstraintList::content`]
+        /// `None` means [`DEFAULT_MAX_CONTENT_SIZE`]
+        pub max_content_size: Option<u64>,
+        /// Whether to bypass ignore rules while walking through folders
+        /// This doesn't affect scoring
+        pub show_ignored: bool,
+        /// How to order the results, overriding the one picked in the UI
+        /// This doesn't affect scoring
+        pub sort: Option<Sort>,
+        /// The time that relative dates count back from, e.g. `modified:<7d`
+        /// Only set on the constraints a search runs with, so that it's the same for every path
+        /// `None` means the time each path gets matched
+        pub now: Option<DateTime<Local>>,
+        /// Set by `case:`, overriding [`crate::configs::Configs::case_mode`]
+        /// Already applied to every constraint. See [`ConstraintList::set_case_mode`]
+        pub case_mode: Option<CaseMode>,
+        /// Tags to search through, on top of [`super::Query::tags`]
+        /// All AND-ed together
+        pub tags: Vec<TagFilter>,
+        /// Constraint groups, all AND-ed together
+        /// E.g. `.png | .jpg`, `(dino art)` or `!(dino art)`
+        pub groups: Vec<Expr>,
+        /// Everything the tags of inverted [`ConstraintList::tags`] contain
+        /// Only set on the constraints a search runs with, since it depends on the tags' entries
+        pub excluded_entries: Option<Entries>,
+        /// Parts of the query that failed to parse, e.g. invalid regexes
+        /// They are left out of the search
+        pub errors: Vec<ParseError>,
     }
 
     impl ConstraintList {
         /// Parses a string into a [`ConstraintList`]
+        /// Parts that fail to parse are left out, and reported in [`ConstraintList::errors`]
+        #[inline]
         pub fn parse(str: &str) -> ConstraintList {
-            let mut constraints = ConstraintList::default();
-            if str.is_empty() {
-                return constraints;
-            }
-
-            let mut str: String = str.to_string();
-            // Exact constraint
-            constraints.exact = Exact::parse(&mut str);
-
-            for arg in str.split(' ')
-                .map(|str| str.trim())
-                .filter(|str| !str.is_empty())
-            {
-                // File type constraint
-                if let Some(c) = FileType::parse(arg) {
-                    constraints.filetype = Some(c);
-                    continue;
-                }
+            super::syntax::parse(str)
+        }
 
-                // File extension constraint
-                if let Some(c) = Extension::parse(arg) {
-                    constraints.extensions.push(c);
-                    continue;
-                }
+        /// Set [`ConstraintList::max_content_size`], including that of every group
+        pub fn set_max_content_size(&mut self, size: u64) {
+            self.max_content_size = Some(size);
+            for group in self.groups.iter_mut() {
+                group.set_max_content_size(size);
+            }
+        }
 
-                // Everything else -> Score constraint (aka fuzzy search)
-                constraints.fuzzy.push(Fuzzy::parse(arg));
+        /// Set [`ConstraintList::now`], including that of every group
+        pub fn set_now(&mut self, now: DateTime<Local>) {
+            self.now = Some(now);
+            for group in self.groups.iter_mut() {
+                group.set_now(now);
             }
+        }
 
-            constraints
+        /// Set the [`CaseMode`] of every text constraint, including those of every group
+        /// This doesn't set [`ConstraintList::case_mode`], which is only for `case:`
+        pub fn set_case_mode(&mut self, case: CaseMode) {
+            self.fuzzy.iter_mut().for_each(|c| c.matcher.set_case(case));
+            self.exact.iter_mut().for_each(|c| c.case = case);
+            self.extensions.iter_mut().for_each(|c| c.case = case);
+            self.globs.iter_mut().for_each(|c| c.case = case);
+            self.notes.iter_mut().for_each(|c| c.case = case);
+            self.content.iter_mut().for_each(|c| c.case = case);
+            for group in self.groups.iter_mut() {
+                group.set_case_mode(case);
+            }
         }
 
+        /// Score `path` without any annotations
+        /// See [`ConstraintList::score_annotated`]
+        #[inline]
         pub fn score(&self, path: &Path) -> Option<isize> {
+            self.score_annotated(path, &[])
+        }
+
+        /// Score `path`, given all the [`Annotation`]s that apply to it
+        /// See [`ConstraintList::match_annotated`]
+        #[inline]
+        pub fn score_annotated(&self, path: &Path, annotations: &[&Annotation]) -> Option<isize> {
+            self.match_annotated(path, annotations)
+                .map(|(score, _)| score)
+        }
+
+        /// Score `path`, given all the [`Annotation`]s that apply to it
+        /// Also returns the line that matched [`ConstraintList::content`], if any
+        #[inline]
+        pub fn match_annotated(&self, path: &Path, annotations: &[&Annotation]) -> Option<(isize, Option<ContentMatch>)> {
+            self.match_with_meta(path, &mut None, annotations)
+        }
+
+        /// Same as [`ConstraintList::match_annotated`], with the [`Meta`] of `path` if it's already known
+        /// If it isn't and it's needed, it gets read and stored into `meta`
+        pub fn match_with_meta(
+            &self,
+            path: &Path,
+            meta: &mut Option<Meta>,
+            annotations: &[&Annotation],
+        ) -> Option<(isize, Option<ContentMatch>)> {
+            // 0. Excluded tags
+            if self.excluded_entries.as_ref().is_some_and(|entries| entries.contains(path)) {
+                return None;
+            }
+
+            // 0. AND notes
+            if !self.notes.iter().all(|c| c.matches(annotations)) {
+                return None;
+            }
+
             // 1. Filter file type
             match &self.filetype {
-                Some(FileType::File) => if !path.is_file() { return None; },
+                Some(FileType::File) => if !path.is_file() && !archive::is_member_path(path) { return None; },
                 Some(FileType::Dir) => if !path.is_dir() { return None; },
                 None => {},
             }
 
-            // 2. OR extensions, or AND if inverted
-            if !self.extensions.is_empty() {
-                let ext = path.extension()?;
+            // 2. OR extensions and globs, or AND if inverted
+            if !self.extensions.is_empty() || !self.globs.is_empty() {
+                let name = path.file_name()?.to_string_lossy();
+                // Paths without an extension never match extension constraints, even inverted ones
+                let extensions = self.extensions.iter()
+                    .filter(|_| path.extension().is_some())
+                    .map(|c| (c.matches(&name), c.inverted));
+                let constraints = extensions
+                    .chain(self.globs.iter() .map(|c| (c.matches(&name), c.inverted)))
+                    // The `!=` basically negates it
+                    .map(|(matches, inverted)| (matches != inverted, inverted));
 
                 let mut any_match: bool = false;
-                for constraint in self.extensions.iter() {
+                for (matches, inverted) in constraints {
                     // .png .png => (true, false) => any match = true; break
                     // .png .jpg => (false, false) => no match; continue
                     // !.png .png => (false, true) => exclude; return
                     // !.png .jpg => (true, true) => any match = true; continue
-                    match (constraint.matches(ext), constraint.inverted) {
+                    match (matches, inverted) {
                         // Match!
                         (true, false) => {
                             any_match = true;
@@ -330,37 +867,305 @@ mod constraint {
                 }
             }
 
-            // 3. AND Exacts
+            // 3. AND Exacts and patterns
             let pathstr = path.to_pretty_string();
-            if !self.exact.is_empty() && !self.exact.iter() .all(|c| c.matches(&pathstr)) {
+            if !self.exact.is_empty() && !self.exact.iter() .all(|c| c.matches(path, &pathstr)) {
+                return None;
+            }
+            if !self.patterns.iter().all(|c| c.matches(&pathstr)) {
                 return None;
             }
 
             let length_penalty: isize = pathstr.len() as isize;
             
             // 4. Score fuzzy
-            if self.fuzzy.is_empty() {
-                return Some(-length_penalty);
+            let score: isize = if self.fuzzy.is_empty() {
+                -length_penalty
+            } else {
+                self.fuzzy.iter()
+                    .filter_map(|f| f.score(path, &pathstr))
+                    .reduce(|acc, s| acc + s)?
+                    - length_penalty
+            };
+
+            // 5. AND sizes and dates
+            if !self.sizes.is_empty() || !self.dates.is_empty() {
+                let meta = get_meta(path, meta)?;
+                if !self.sizes.is_empty() && (meta.is_dir || !self.sizes.iter().all(|c| c.matches(meta.size))) {
+                    return None;
+                }
+
+                let now = self.now.unwrap_or_else(Local::now);
+                if !self.dates.iter().all(|c| c.matches(path, &meta, now)) {
+                    return None;
+                }
+            }
+
+            // 5. OR kinds, or AND if inverted
+            // Files without an extension may have to be read to tell their kind, so this comes after
+            // the cheaper filters
+            if !self.kinds.is_empty() {
+                let kind: Option<&str> = kind::table().kind_of(path);
+                let mut has_included: bool = false;
+                let mut any_match: bool = false;
+                for c in self.kinds.iter() {
+                    let matches: bool = kind == Some(c.kind.as_str());
+                    match (matches, c.inverted) {
+                        (true, true) => return None,
+                        (false, true) => {},
+                        (matches, false) => {
+                            has_included = true;
+                            any_match |= matches;
+                        },
+                    }
+                }
+
+                if has_included && !any_match {
+                    return None;
+                }
+            }
+
+            // 6. AND content
+            // This has to read the file
+            let mut content_match = self.match_content(path)?;
+
+            // 7. AND groups
+            // Their scores include the length penalty too, so it's only counted once
+            let mut score = score;
+            for group in self.groups.iter() {
+                let (group_score, group_match) = group.match_with_meta(path, meta, annotations)?;
+                score += group_score + length_penalty;
+                content_match = content_match.or(group_match);
+            }
+
+            Some((score, content_match))
+        }
+
+        /// Check the contents of `path` against [`ConstraintList::content`]
+        /// Returns `None` if it doesn't match, otherwise the first line that matched the first
+        /// non-inverted constraint
+        fn match_content(&self, path: &Path) -> Option<Option<ContentMatch>> {
+            if self.content.is_empty() {
+                return Some(None);
+            }
+
+            let text = read_text(path, self.max_content_size.unwrap_or(DEFAULT_MAX_CONTENT_SIZE))?;
+            let mut content_match: Option<ContentMatch> = None;
+            for constraint in self.content.iter() {
+                let found = text.lines()
+                    .enumerate()
+                    .find(|(_, line)| constraint.matches_line(line));
+
+                match (found, constraint.inverted) {
+                    (Some(_), true) | (None, false) => return None,
+                    (None, true) => {},
+                    (Some((index, line)), false) => if content_match.is_none() {
+                        content_match = Some(ContentMatch::new(index + 1, line));
+                    },
+                }
             }
 
-            self.fuzzy.iter()
-                .filter_map(|f| f.score(&pathstr))
-                .reduce(|acc, s| acc + s)
-                .map(|s| s - length_penalty)
+            Some(content_match)
+        }
+
+        /// Returns whether matching needs the [`Meta`] of paths, including for any group
+        pub fn needs_metadata(&self) -> bool {
+            !self.sizes.is_empty()
+                || !self.dates.is_empty()
+                || self.groups.iter().any(Expr::needs_metadata)
         }
 
         pub fn is_empty(&self) -> bool {
             self.fuzzy.is_empty()
                 && self.exact.is_empty()
                 && self.extensions.is_empty()
+                && self.globs.is_empty()
+                && self.kinds.is_empty()
                 && self.filetype.is_none()
+                && self.notes.is_empty()
+                && self.patterns.is_empty()
+                && self.content.is_empty()
+                && self.sizes.is_empty()
+                && self.dates.is_empty()
+                && self.groups.is_empty()
+                // Included tags only change which entries are searched through
+                && self.tags.iter().all(|t| !t.inverted)
+        }
+
+        /// Returns whether every path matched by these constraints is also matched by `previous`
+        /// If so, the results of `previous` can be re-scored instead of searching again
+        /// This is conservative, so some narrower constraints may still return `false`
+        pub fn narrows(&self, previous: &ConstraintList) -> bool {
+            // Without constraints, searches only list the entries themselves
+            if previous.is_empty() || self.show_ignored != previous.show_ignored {
+                return false;
+            }
+            // Tags change which entries are searched through
+            if self.tags != previous.tags {
+                return false;
+            }
+
+            // Paths that match none of the fuzzy constraints get filtered out, so adding more
+            // would let more paths through
+            let fuzzy_narrows: bool = previous.fuzzy.is_empty()
+                || self.fuzzy.len() == previous.fuzzy.len()
+                    && self.fuzzy.iter().zip(previous.fuzzy.iter()).all(|(f, p)| f.narrows(p));
+            let filetype_narrows: bool = previous.filetype.is_none() || self.filetype == previous.filetype;
+            let extensions_narrow: bool = previous.extensions.is_empty() && previous.globs.is_empty()
+                || self.extensions == previous.extensions && self.globs == previous.globs;
+            let kinds_narrow: bool = previous.kinds.is_empty() || self.kinds == previous.kinds;
+
+            fuzzy_narrows
+                && filetype_narrows
+                && extensions_narrow
+                && kinds_narrow
+                && previous.exact.iter().all(|p| self.exact.iter().any(|e| e.narrows(p)))
+                && previous.notes.iter().all(|p| self.notes.iter().any(|n| n.narrows(p)))
+                && previous.patterns.iter().all(|p| self.patterns.contains(p))
+                && previous.groups.iter().all(|p| self.groups.contains(p))
+                && previous.content.iter().all(|p| self.content.iter().any(|c| c.narrows(p)))
+                && previous.sizes.iter().all(|p| self.sizes.iter().any(|c| c.narrows(p)))
+                // Relative dates move with time, so only identical ones are safe
+                && previous.dates.iter().all(|p| self.dates.contains(p))
+                && self.max_content_size == previous.max_content_size
         }
 
         pub fn clear(&mut self) {
             self.fuzzy.clear();
             self.exact.clear();
             self.extensions.clear();
+            self.globs.clear();
+            self.kinds.clear();
             self.filetype = None;
+            self.notes.clear();
+            self.patterns.clear();
+            self.content.clear();
+            self.sizes.clear();
+            self.dates.clear();
+            self.groups.clear();
+            self.tags.clear();
+            self.excluded_entries = None;
+            self.now = None;
+            self.show_ignored = false;
+            self.sort = None;
+            self.case_mode = None;
+            self.errors.clear();
+        }
+    }
+
+    /// Constraints combined with `|`, `( )` or `!( )`
+    /// See [`ConstraintList::groups`]
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Expr {
+        /// All constraints must match, e.g. `(dino .png)`
+        And(Box<ConstraintList>),
+        /// At least one must match, e.g. `dino | .png`
+        /// The best score is kept
+        Or(Vec<Expr>),
+        /// Must not match, e.g. `!(dino .png)`
+        Not(Box<Expr>),
+    }
+
+    impl Expr {
+        /// Same as [`ConstraintList::match_with_meta`]
+        fn match_with_meta(
+            &self,
+            path: &Path,
+            meta: &mut Option<Meta>,
+            annotations: &[&Annotation],
+        ) -> Option<(isize, Option<ContentMatch>)> {
+            match self {
+                Expr::And(constraints) => constraints.match_with_meta(path, meta, annotations),
+                Expr::Or(exprs) => exprs.iter()
+                    .filter_map(|expr| expr.match_with_meta(path, meta, annotations))
+                    .max_by_key(|(score, _)| *score),
+                Expr::Not(expr) => match expr.match_with_meta(path, meta, annotations) {
+                    Some(_) => None,
+                    None => Some((-(path.to_pretty_string().len() as isize), None)),
+                },
+            }
+        }
+
+        fn needs_metadata(&self) -> bool {
+            match self {
+                Expr::And(constraints) => constraints.needs_metadata(),
+                Expr::Or(exprs) => exprs.iter().any(Expr::needs_metadata),
+                Expr::Not(expr) => expr.needs_metadata(),
+            }
+        }
+
+        fn set_now(&mut self, now: DateTime<Local>) {
+            match self {
+                Expr::And(constraints) => constraints.set_now(now),
+                Expr::Or(exprs) => exprs.iter_mut().for_each(|expr| expr.set_now(now)),
+                Expr::Not(expr) => expr.set_now(now),
+            }
+        }
+
+        fn set_max_content_size(&mut self, size: u64) {
+            match self {
+                Expr::And(constraints) => constraints.set_max_content_size(size),
+                Expr::Or(exprs) => exprs.iter_mut().for_each(|expr| expr.set_max_content_size(size)),
+                Expr::Not(expr) => expr.set_max_content_size(size),
+            }
+        }
+
+        fn set_case_mode(&mut self, case: CaseMode) {
+            match self {
+                Expr::And(constraints) => constraints.set_case_mode(case),
+                Expr::Or(exprs) => exprs.iter_mut().for_each(|expr| expr.set_case_mode(case)),
+                Expr::Not(expr) => expr.set_case_mode(case),
+            }
+        }
+    }
+
+    /// Which part of a path a [`Fuzzy`] or [`Exact`] constraint looks at
+    /// Set with a prefix, e.g. `name:cat` or `parent:"my docs"`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum Field {
+        /// The whole path, but fuzzy matches within the file name score higher
+        #[default]
+        Auto,
+        /// `path:`, the whole path
+        Path,
+        /// `name:`, the file name
+        Name,
+        /// `stem:`, the file name without its extension
+        Stem,
+        /// `parent:`, the name of the folder it's in
+        Parent,
+        /// `dir:`, the path of the folder it's in
+        Dir,
+    }
+
+    impl Field {
+        /// Split a field prefix off `str`
+        /// E.g. `name:cat` => `Some((Field::Name, "cat"))`
+        pub fn parse_prefix(str: &str) -> Option<(Field, &str)> {
+            let (prefix, rest) = str.split_once(':')?;
+            let field = match prefix {
+                "path" => Field::Path,
+                "name" => Field::Name,
+                "stem" => Field::Stem,
+                "parent" => Field::Parent,
+                "dir" => Field::Dir,
+                _ => return None,
+            };
+            Some((field, rest))
+        }
+
+        /// Get the part of `path` this field looks at
+        /// `pathstr` is the pretty `path`, for [`Field::Auto`] and [`Field::Path`]
+        fn get<'a>(self, path: &'a Path, pathstr: &'a str) -> Cow<'a, str> {
+            match self {
+                Field::Auto | Field::Path => Cow::Borrowed(pathstr),
+                Field::Name => path.file_name().map_or(Cow::Borrowed(""), |name| name.to_string_lossy()),
+                Field::Stem => path.file_stem().map_or(Cow::Borrowed(""), |stem| stem.to_string_lossy()),
+                Field::Parent => path.parent()
+                    .and_then(Path::file_name)
+                    .map_or(Cow::Borrowed(""), |name| name.to_string_lossy()),
+                Field::Dir => path.parent().map_or(Cow::Borrowed(""), |dir| Cow::Owned(dir.to_pretty_string())),
+            }
         }
     }
 
@@ -369,6 +1174,7 @@ mod constraint {
     #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct Fuzzy {
         pub matcher: Sublime,
+        pub field: Field,
         pub inverted: bool,
     }
 
@@ -376,15 +1182,53 @@ mod constraint {
         pub fn parse(str: &str) -> Fuzzy {
             let (str, inverted) = str.strip_prefix('!')
                 .map_or((str, false), |s| (s, true));
+            let (field, str) = Field::parse_prefix(str)
+                .filter(|(_, query)| !query.is_empty())
+                .unwrap_or((Field::Auto, str));
 
             Fuzzy {
                 matcher: Sublime::default() .with_query(str),
+                field,
                 inverted,
             }
         }
 
-        fn score(&self, str: &str) -> Option<isize> {
-            match (self.matcher.score(&str), self.inverted) {
+        /// Returns whether this matches a subset of what `previous` matches
+        /// Inverted fuzzy constraints never filter anything out
+        fn narrows(&self, previous: &Fuzzy) -> bool {
+            if self.inverted != previous.inverted || self.field != previous.field {
+                return false;
+            }
+            if self.inverted {
+                return true;
+            }
+
+            // A case insensitive query can match more than the same one with case
+            let previous_sensitive: bool = previous.matcher.is_case_sensitive();
+            if previous_sensitive && !self.matcher.is_case_sensitive() {
+                return false;
+            }
+
+            // Sublime matches are subsequences, so the previous query must be one of the new one
+            let mut chars = self.matcher.get_query().iter();
+            previous.matcher.get_query().iter()
+                .all(|pch| chars.any(|ch| strmatch::chars_match(*ch, *pch, previous_sensitive)))
+        }
+
+        /// `pathstr` is the pretty `path`
+        fn score(&self, path: &Path, pathstr: &str) -> Option<isize> {
+            let score = match self.field {
+                // A match within the file name is worth more than one across folders
+                Field::Auto => {
+                    let name_score = path.file_name()
+                        .and_then(|name| self.matcher.score(&name.to_string_lossy()))
+                        .map(|score| score + NAME_MATCH_BONUS);
+                    self.matcher.score(&pathstr).max(name_score)
+                },
+                field => self.matcher.score(&field.get(path, pathstr)),
+            };
+
+            match (score, self.inverted) {
                 (None, false) => None,
                 (Some(s), false) => Some(s),
                 (None, true) => Some(0),
@@ -393,79 +1237,380 @@ mod constraint {
         }
     }
 
-    /// Do a simple case insensitive `contains` check
+    /// Do a simple `contains` check
     #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct Exact {
         pub query: String,
+        pub field: Field,
         pub inverted: bool,
+        pub case: CaseMode,
     }
 
     impl Exact {
-        /// Drains the parsed sections from the string
-        /// This makes it easier to deal with in [`ConstraintList::parse`]
-        pub fn parse(str: &mut String) -> Vec<Exact> {
-            static REGEX: OnceLock<Regex> = OnceLock::new();
-
-            #[allow(clippy::unwrap_used)]
-            let re: &Regex = REGEX.get_or_init(||
-                Regex::new(r#" ?(?<invert>!)?"(?<inner>[^"]+)("( |$)|$)"#)
-                    .unwrap() // Will never fail
-               // (?<invert>!)?     Optional `!` to invert
-               // "(?<inner>[^"]+)  Inner query
-               // ("( |$)|$)        Closing quote or EOL
-            );
-
-            let mut parsed: Vec<Exact> = Vec::new();
-            let mut drain_ranges = Vec::new();
-            // Parse
-            for cap in re.captures_iter(str) {
-                #[allow(clippy::unwrap_used)]
-                let inner_match = cap.name("inner").unwrap();
-                let inner: &str = inner_match.as_str();
-                let mut range = inner_match.range();
-
-                let inverted: bool = cap.name("invert").is_some();
-                if inverted {
-                    range.start -= 1;
-                }
-                drain_ranges.push(range);
-
-                parsed.push(Exact {
-                    query: inner.to_lowercase(),
-                    inverted,
-                });
+        /// Returns `None` if `query` is empty
+        pub fn parse(query: &str, inverted: bool) -> Option<Exact> {
+            if query.is_empty() {
+                return None;
             }
 
-            // Drain captured ranges
-            for r in drain_ranges.into_iter().rev() {
-                let start = r.start - 1;
-                let end = (r.end + 2).min(str.len());
-                str.drain(start..end);
-            }
+            Some(Exact {
+                query: query.to_string(),
+                field: Field::Auto,
+                inverted,
+                case: CaseMode::default(),
+            })
+        }
+
+        pub fn with_field(mut self, field: Field) -> Self {
+            self.field = field;
+            self
+        }
 
-            parsed
+        /// Returns whether this matches a subset of what `previous` matches
+        fn narrows(&self, previous: &Exact) -> bool {
+            self.field == previous.field
+                && narrows_contains(
+                    (&self.query, self.case, self.inverted),
+                    (&previous.query, previous.case, previous.inverted),
+                )
         }
 
+        /// `pathstr` is the pretty `path`
         #[inline]
-        fn matches(&self, str: &str) -> bool {
+        fn matches(&self, path: &Path, pathstr: &str) -> bool {
+            let str = self.field.get(path, pathstr);
             // The `!=` basically negates it
             // t != t = f
             // f != t = t
             // t != f = t
             // f != f = f
-            str.to_lowercase() .contains(&self.query) != self.inverted
+            self.case.contains(&str, &self.query) != self.inverted
         }
     }
 
-    /// Filter file extensions
+    /// Do a simple `contains` check on the notes of [`Annotation`]s
     #[derive(Debug, Clone, PartialEq, Eq)]
-    pub struct Extension {
-        pub extension: OsString,
-        inverted: bool,
+    pub struct Note {
+        pub query: String,
+        pub inverted: bool,
+        pub case: CaseMode,
     }
 
-    impl Extension {
-        pub fn parse(str: &str) -> Option<Extension> {
+    impl Note {
+        /// Parse the query after `note:`, which can be quoted or a single word
+        /// Returns `None` if `query` is empty
+        pub fn parse(query: &str, inverted: bool) -> Option<Note> {
+            if query.is_empty() {
+                return None;
+            }
+
+            Some(Note {
+                query: query.to_string(),
+                inverted,
+                case: CaseMode::default(),
+            })
+        }
+
+        /// Returns whether this matches a subset of what `previous` matches
+        fn narrows(&self, previous: &Note) -> bool {
+            narrows_contains(
+                (&self.query, self.case, self.inverted),
+                (&previous.query, previous.case, previous.inverted),
+            )
+        }
+
+        /// Returns whether any of the `annotations` contain the query
+        /// If inverted, returns whether none of them do
+        #[inline]
+        fn matches(&self, annotations: &[&Annotation]) -> bool {
+            annotations.iter()
+                .any(|a| self.case.contains(&a.note, &self.query))
+                != self.inverted
+        }
+    }
+
+    /// Searches for lines inside text files
+    /// Accepts `content:"some text"`, `content:word` and `content:/regex/`, with optional flags
+    /// after the regex (see [`build_regex`])
+    /// Binary files, and files bigger than [`ConstraintList::max_content_size`] never match
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Content {
+        pub query: ContentQuery,
+        pub inverted: bool,
+        /// Only applies to [`ContentQuery::Text`]
+        pub case: CaseMode,
+    }
+
+    #[derive(Debug, Clone)]
+    pub enum ContentQuery {
+        /// `contains` check, following [`Content::case`]
+        Text(String),
+        Regex(Regex),
+    }
+
+    impl PartialEq for ContentQuery {
+        fn eq(&self, other: &Self) -> bool {
+            match (self, other) {
+                (ContentQuery::Text(a), ContentQuery::Text(b)) => a == b,
+                (ContentQuery::Regex(a), ContentQuery::Regex(b)) => a.as_str() == b.as_str(),
+                _ => false,
+            }
+        }
+    }
+
+    impl Eq for ContentQuery {}
+
+    impl Content {
+        /// Returns `None` if `query` is empty
+        pub fn text(query: &str, inverted: bool) -> Option<Content> {
+            if query.is_empty() {
+                return None;
+            }
+
+            Some(Content {
+                query: ContentQuery::Text(query.to_string()),
+                inverted,
+                case: CaseMode::default(),
+            })
+        }
+
+        pub fn regex(regex: Regex, inverted: bool) -> Content {
+            Content {
+                query: ContentQuery::Regex(regex),
+                inverted,
+                case: CaseMode::default(),
+            }
+        }
+
+        fn matches_line(&self, line: &str) -> bool {
+            match &self.query {
+                ContentQuery::Text(query) => self.case.contains(line, query),
+                ContentQuery::Regex(re) => re.is_match(line),
+            }
+        }
+
+        /// Returns whether this matches a subset of what `previous` matches
+        fn narrows(&self, previous: &Content) -> bool {
+            match (&self.query, &previous.query) {
+                (ContentQuery::Text(query), ContentQuery::Text(prev)) => narrows_contains(
+                    (query, self.case, self.inverted),
+                    (prev, previous.case, previous.inverted),
+                ),
+                _ => self == previous,
+            }
+        }
+    }
+
+    /// Match a regex against the target path
+    /// Accepts `/regex/` with optional flags after it (see [`build_regex`]), `re:"regex"`, and
+    /// `re:regex` without spaces, where escapes are part of the regex
+    #[derive(Debug, Clone)]
+    pub struct Pattern {
+        pub regex: Regex,
+        pub inverted: bool,
+    }
+
+    impl PartialEq for Pattern {
+        fn eq(&self, other: &Self) -> bool {
+            // Flags are part of the pattern, see `build_regex`
+            self.regex.as_str() == other.regex.as_str() && self.inverted == other.inverted
+        }
+    }
+
+    impl Eq for Pattern {}
+
+    impl Pattern {
+        #[inline]
+        fn matches(&self, str: &str) -> bool {
+            self.regex.is_match(str) != self.inverted
+        }
+    }
+
+    /// Compile `pattern` with `flags`, which can be any of
+    /// - `i`: case insensitive
+    /// - `m`: `^` and `$` match at line boundaries
+    /// - `s`: `.` also matches new lines
+    /// - `x`: ignore whitespace and allow `#` comments
+    ///
+    /// Flags are added to the pattern itself, so that [`Regex::as_str`] tells them apart
+    /// `span` is where the regex is in the query, in case it's invalid
+    pub fn build_regex(pattern: &str, flags: &str, span: Range<usize>) -> Result<Regex, ParseError> {
+        let full_pattern = if flags.is_empty() {
+            pattern.to_string()
+        } else {
+            format!("(?{flags}){pattern}")
+        };
+
+        Regex::new(&full_pattern).map_err(|err| ParseError::InvalidRegex {
+            pattern: pattern.to_string(),
+            message: match err {
+                regex::Error::Syntax(msg) => msg.lines().last().unwrap_or_default().to_string(),
+                err => err.to_string(),
+            },
+            span,
+        })
+    }
+
+    /// Part of a query that failed to parse
+    /// Spans are byte ranges in the query
+    #[derive(Debug, Clone, PartialEq, Eq, Error)]
+    pub enum ParseError {
+        #[error("Invalid regex `{pattern}`: {message}")]
+        InvalidRegex {
+            pattern: String,
+            message: String,
+            span: Range<usize>,
+        },
+        #[error("Unknown flag `{flag}`. Use `\\{flag}` to search for it")]
+        UnknownFlag {
+            flag: String,
+            span: Range<usize>,
+        },
+        #[error("`(` is never closed")]
+        UnclosedGroup {
+            span: Range<usize>,
+        },
+        #[error("Unexpected `)`")]
+        UnexpectedClose {
+            span: Range<usize>,
+        },
+        #[error("Expected something to search for")]
+        ExpectedTerm {
+            span: Range<usize>,
+        },
+        #[error("Tags can't be used inside `( )` or next to `|`")]
+        TagInGroup {
+            span: Range<usize>,
+        },
+        #[error("Unknown kind `{kind}`")]
+        UnknownKind {
+            kind: String,
+            span: Range<usize>,
+        },
+        #[error("Unknown tag `{0}`")]
+        UnknownTag(TagID),
+    }
+
+    impl ParseError {
+        /// Where the error is in the query, if anywhere in particular
+        pub fn span(&self) -> Option<Range<usize>> {
+            match self {
+                ParseError::InvalidRegex { span, .. }
+                | ParseError::UnknownFlag { span, .. }
+                | ParseError::UnclosedGroup { span }
+                | ParseError::UnexpectedClose { span }
+                | ParseError::ExpectedTerm { span }
+                | ParseError::TagInGroup { span }
+                | ParseError::UnknownKind { span, .. } => Some(span.clone()),
+                ParseError::UnknownTag(_) => None,
+            }
+        }
+    }
+
+    /// A line inside a file that matched a [`Content`] constraint
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ContentMatch {
+        /// Starting from 1
+        pub line_number: usize,
+        /// Trimmed, and cut off if too long
+        pub line: String,
+    }
+
+    impl ContentMatch {
+        fn new(line_number: usize, line: &str) -> Self {
+            let line = line.trim();
+            let line: String = match line.char_indices().nth(MAX_MATCHED_LINE_LEN) {
+                Some((end, _)) => format!("{}...", &line[..end]),
+                None => line.to_string(),
+            };
+            ContentMatch { line_number, line }
+        }
+    }
+
+    /// Get the [`Meta`] of `path` out of `meta`, reading it if it isn't known yet
+    fn get_meta(path: &Path, meta: &mut Option<Meta>) -> Option<Meta> {
+        if meta.is_none() {
+            *meta = Some(Meta::from(&fs::metadata(path).ok()?));
+        }
+        *meta
+    }
+
+    /// Read `path` as text, if it is a text file no bigger than `max_size` bytes
+    /// Files with NUL bytes near the start are considered binary
+    fn read_text(path: &Path, max_size: u64) -> Option<String> {
+        let meta = fs::metadata(path).ok()?;
+        if !meta.is_file() || meta.len() > max_size {
+            return None;
+        }
+
+        let bytes = fs::read(path).ok()?;
+        if bytes[..bytes.len().min(BINARY_CHECK_LEN)].contains(&0) {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Returns whether a `contains` check for `query` matches a subset of what one for `previous`
+    /// matches, given each as `(query, case, inverted)`
+    /// A longer query matches less, unless inverted, in which case it excludes less
+    /// Likewise, a case sensitive query matches less than an insensitive one
+    fn narrows_contains(
+        (query, case, inverted): (&str, CaseMode, bool),
+        (previous, previous_case, previous_inverted): (&str, CaseMode, bool),
+    ) -> bool {
+        // `looser` is the query that has to match everything the other one does
+        let is_contained = |looser: &str, looser_case: CaseMode, stricter: &str, stricter_case: CaseMode| {
+            if looser_case.is_sensitive(looser) {
+                stricter_case.is_sensitive(stricter) && stricter.contains(looser)
+            } else {
+                stricter.to_lowercase().contains(&looser.to_lowercase())
+            }
+        };
+
+        match (inverted, previous_inverted) {
+            (false, false) => is_contained(previous, previous_case, query, case),
+            (true, true) => is_contained(query, case, previous, previous_case),
+            _ => false,
+        }
+    }
+
+    /// Filter paths by the tags they're in
+    /// Accepts `#tag`, and `#a|#b|...` for paths in any of the tags
+    /// When inverted, e.g. `!#tag`, paths in any of the tags are left out
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TagFilter {
+        pub any_of: Vec<TagID>,
+        pub inverted: bool,
+    }
+
+    impl TagFilter {
+        pub fn parse(str: &str) -> Option<TagFilter> {
+            let (str, inverted) = str.strip_prefix('!')
+                .map_or((str, false), |s| (s, true));
+
+            let any_of = str.split('|')
+                .map(|part| part.strip_prefix('#')
+                    .filter(|id| !id.is_empty())
+                    .map(TagID::parse)
+                )
+                .collect::<Option<Vec<TagID>>>()?;
+
+            Some(TagFilter { any_of, inverted })
+        }
+    }
+
+    /// Filter file extensions
+    /// Extensions can have multiple parts, like `.tar.gz` or `.d.ts`
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Extension {
+        /// Without the leading `.`
+        pub extension: OsString,
+        inverted: bool,
+        pub case: CaseMode,
+    }
+
+    impl Extension {
+        pub fn parse(str: &str) -> Option<Extension> {
             let (str, inverted) = str.strip_prefix('!')
                 .map_or((str, false), |s| (s, true));
 
@@ -473,16 +1618,433 @@ mod constraint {
             if ext.is_empty() { return None; }
 
             Some(Extension {
-                extension: ext.to_lowercase().into(),
+                extension: ext.into(),
+                inverted,
+                case: CaseMode::default(),
+            })
+        }
+
+        /// Whether the file `name` ends with this extension
+        /// Returns `true` regardless of [`Extension::inverted`]
+        fn matches(&self, name: &str) -> bool {
+            let ext = self.extension.to_string_lossy();
+            let (name, ext) = if self.case.is_sensitive(&ext) {
+                (Cow::Borrowed(name), ext)
+            } else {
+                (Cow::Owned(name.to_lowercase()), Cow::Owned(ext.to_lowercase()))
+            };
+            // The name must have a stem, so `.gitignore` has no extension
+            name.len() > ext.len() + 1
+                && name.ends_with(ext.as_ref())
+                && name[..name.len() - ext.len()].ends_with('.')
+        }
+    }
+
+    /// Filter files by kind, e.g. `kind:image` or `!kind:code`
+    /// See [`crate::kind::KindTable`]
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Kind {
+        /// Lowercase
+        pub kind: String,
+        pub inverted: bool,
+    }
+
+    impl Kind {
+        /// Parses `kind:(kind)`, whether or not the kind exists
+        pub fn parse(str: &str) -> Option<Kind> {
+            let (str, inverted) = str.strip_prefix('!')
+                .map_or((str, false), |s| (s, true));
+            let kind = str.strip_prefix("kind:")?;
+            if kind.is_empty() { return None; }
+
+            Some(Kind {
+                kind: kind.to_lowercase(),
                 inverted,
             })
         }
+    }
+
+    /// Match file names against a wildcard pattern, e.g. `*.tar.gz` or `IMG_????.jpg`
+    /// `*` matches any number of characters, and `?` matches exactly one
+    /// The whole file name must match
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Glob {
+        pub pattern: Vec<char>,
+        pub inverted: bool,
+        pub case: CaseMode,
+    }
+
+    impl Glob {
+        pub fn parse(str: &str) -> Option<Glob> {
+            let (str, inverted) = str.strip_prefix('!')
+                .map_or((str, false), |s| (s, true));
+
+            if !str.contains(['*', '?']) {
+                return None;
+            }
+
+            Some(Glob {
+                pattern: str.chars().collect(),
+                inverted,
+                case: CaseMode::default(),
+            })
+        }
+
+        /// Whether the file `name` matches this pattern
+        /// Returns `true` regardless of [`Glob::inverted`]
+        fn matches(&self, name: &str) -> bool {
+            let case_sensitive: bool = self.case.is_sensitive_chars(self.pattern.iter().copied());
+            let name: Vec<char> = name.chars().collect();
+            let (mut p, mut n) = (0, 0);
+            // Where to resume from when a `*` needs to swallow one more character
+            let mut backtrack: Option<(usize, usize)> = None;
+
+            while n < name.len() {
+                match self.pattern.get(p) {
+                    Some('*') => {
+                        backtrack = Some((p, n));
+                        p += 1;
+                    },
+                    Some(&c) if c == '?' || strmatch::chars_match(c, name[n], case_sensitive) => {
+                        p += 1;
+                        n += 1;
+                    },
+                    _ => match backtrack {
+                        Some((star, matched)) => {
+                            p = star + 1;
+                            n = matched + 1;
+                            backtrack = Some((star, matched + 1));
+                        },
+                        None => return false,
+                    },
+                }
+            }
+
+            self.pattern[p..].iter().all(|c| *c == '*')
+        }
+    }
+
+    /// Filter files by size, in bytes
+    /// Accepts `size:>N`, `size:>=N`, `size:<N`, `size:<=N`, `size:N` (exactly), and inclusive ranges
+    /// `size:A..B`, `size:A..` and `size:..B`
+    /// Sizes can have units, e.g. `4k`, `1.5MB` or `2GiB`. See [`parse_size`]
+    /// Folders never match
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Size {
+        /// Inclusive
+        pub min: Option<u64>,
+        /// Inclusive
+        pub max: Option<u64>,
+        pub inverted: bool,
+    }
+
+    impl Size {
+        pub fn parse(str: &str) -> Option<Size> {
+            let (str, inverted) = str.strip_prefix('!')
+                .map_or((str, false), |s| (s, true));
+            let str = str.strip_prefix("size:")?;
+
+            let (min, max) = if let Some((a, b)) = str.split_once("..") {
+                let min = if a.is_empty() { None } else { Some(parse_size(a)?) };
+                let max = if b.is_empty() { None } else { Some(parse_size(b)?) };
+                if min.is_none() && max.is_none() {
+                    return None;
+                }
+                (min, max)
+            } else if let Some(s) = str.strip_prefix(">=") {
+                (Some(parse_size(s)?), None)
+            } else if let Some(s) = str.strip_prefix("<=") {
+                (None, Some(parse_size(s)?))
+            } else if let Some(s) = str.strip_prefix('>') {
+                (Some(parse_size(s)?.saturating_add(1)), None)
+            } else if let Some(s) = str.strip_prefix('<') {
+                (None, Some(parse_size(s)?.checked_sub(1)?))
+            } else {
+                let size = parse_size(str)?;
+                (Some(size), Some(size))
+            };
+
+            Some(Size { min, max, inverted })
+        }
 
         #[inline]
-        fn matches(&self, extension:  &OsStr) -> bool {
-            // The `!=` basically negates it
-            self.extension.eq_ignore_ascii_case(extension) != self.inverted
-            // (self.extension == extension.to_ascii_lowercase()) != self.inverted
+        fn contains(&self, size: u64) -> bool {
+            self.min.is_none_or(|min| size >= min)
+                && self.max.is_none_or(|max| size <= max)
+        }
+
+        #[inline]
+        fn matches(&self, size: u64) -> bool {
+            self.contains(size) != self.inverted
+        }
+
+        /// Returns whether this matches a subset of what `previous` matches
+        fn narrows(&self, previous: &Size) -> bool {
+            // Whether the range of `a` is within that of `b`
+            let within = |a: &Size, b: &Size| {
+                b.min.is_none_or(|bmin| a.min.is_some_and(|amin| amin >= bmin))
+                    && b.max.is_none_or(|bmax| a.max.is_some_and(|amax| amax <= bmax))
+            };
+
+            match (self.inverted, previous.inverted) {
+                (false, false) => within(self, previous),
+                // Excluding a bigger range matches less
+                (true, true) => within(previous, self),
+                _ => false,
+            }
+        }
+    }
+
+    /// Parse a human readable size into bytes
+    /// Units are case insensitive, and powers of 1024 like in [`crate::tagging::stats::format_size`]
+    /// E.g. `"512"` => 512, `"4k"` => 4096, `"1.5MB"` => 1572864
+    pub fn parse_size(str: &str) -> Option<u64> {
+        let split = str.find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(str.len());
+        let (number, unit) = str.split_at(split);
+        let number: f64 = number.parse().ok()?;
+
+        let exponent: i32 = match unit.to_lowercase().as_str() {
+            "" | "b" => 0,
+            "k" | "kb" | "kib" => 1,
+            "m" | "mb" | "mib" => 2,
+            "g" | "gb" | "gib" => 3,
+            "t" | "tb" | "tib" => 4,
+            _ => return None,
+        };
+
+        Some((number * 1024f64.powi(exponent)).round() as u64)
+    }
+
+    /// Which date of a path to filter by
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DateField {
+        Modified,
+        Created,
+        Accessed,
+    }
+
+    /// A point or span in time, as written in a query
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DateValue {
+        /// Some time ago, e.g. `7d`
+        Ago(TimeDelta),
+        Today,
+        Yesterday,
+        /// E.g. `2024`
+        Year(i32),
+        /// E.g. `2024-03`
+        Month(i32, u32),
+        /// E.g. `2024-03-15`
+        Day(NaiveDate),
+    }
+
+    impl DateValue {
+        /// Parse a relative time (`30h`, `7d`, `2w`, `6mo`, `1y`), `today`, `yesterday`, or an
+        /// absolute date (`2024`, `2024-03`, `2024-03-15`)
+        pub fn parse(str: &str) -> Option<DateValue> {
+            match str {
+                "today" => return Some(DateValue::Today),
+                "yesterday" => return Some(DateValue::Yesterday),
+                _ => {},
+            }
+
+            let split = str.find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(str.len());
+            let (number, rest) = str.split_at(split);
+            let n: i64 = number.parse().ok()?;
+
+            let days: i64 = match rest {
+                "h" => return TimeDelta::try_hours(n).map(DateValue::Ago),
+                "d" => 1,
+                "w" => 7,
+                "mo" => 30,
+                "y" => 365,
+                "" if number.len() == 4 => return Some(DateValue::Year(n as i32)),
+                _ => {
+                    let mut parts = str.split('-');
+                    let year: i32 = parts.next()?.parse().ok()?;
+                    let month: u32 = parts.next()?.parse().ok()?;
+                    let day: Option<u32> = match parts.next() {
+                        Some(day) => Some(day.parse().ok()?),
+                        None => None,
+                    };
+                    if parts.next().is_some() {
+                        return None;
+                    }
+
+                    // Make sure it's a valid date
+                    let date = NaiveDate::from_ymd_opt(year, month, day.unwrap_or(1))?;
+                    return Some(match day {
+                        Some(_) => DateValue::Day(date),
+                        None => DateValue::Month(year, month),
+                    });
+                }
+            };
+
+            TimeDelta::try_days(n.checked_mul(days)?).map(DateValue::Ago)
+        }
+
+        /// Get the span `[start, end)` that this value covers
+        /// Relative times are a single point, so `start == end`
+        fn span(&self, now: DateTime<Local>) -> Option<(DateTime<Local>, DateTime<Local>)> {
+            let start_of = |date: NaiveDate| -> Option<DateTime<Local>> {
+                Local.from_local_datetime(&date.and_hms_opt(0, 0, 0)?).earliest()
+            };
+            let today = now.date_naive();
+
+            let (start, end): (NaiveDate, NaiveDate) = match *self {
+                DateValue::Ago(delta) => {
+                    let t = now.checked_sub_signed(delta)?;
+                    return Some((t, t));
+                }
+                DateValue::Today => (today, today.checked_add_days(Days::new(1))?),
+                DateValue::Yesterday => (today.checked_sub_days(Days::new(1))?, today),
+                DateValue::Year(year) => (
+                    NaiveDate::from_ymd_opt(year, 1, 1)?,
+                    NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+                ),
+                DateValue::Month(year, month) => {
+                    let start = NaiveDate::from_ymd_opt(year, month, 1)?;
+                    (start, start.checked_add_months(Months::new(1))?)
+                }
+                DateValue::Day(date) => (date, date.checked_add_days(Days::new(1))?),
+            };
+
+            Some((start_of(start)?, start_of(end)?))
+        }
+
+        #[inline]
+        fn is_relative(&self) -> bool {
+            matches!(self, DateValue::Ago(_))
+        }
+    }
+
+    /// The times `[min, max)` a [`DateBound`] lets through, where `None` means unbounded
+    type Interval = (Option<DateTime<Local>>, Option<DateTime<Local>>);
+
+    /// Which dates a [`Date`] constraint lets through
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum DateBound {
+        /// `modified:2024-03`
+        /// For relative times, within that time, e.g. `modified:7d` is the same as `modified:<7d`
+        During(DateValue),
+        /// `modified:>2024-03` or `modified:>=2024-03`, with whether it is inclusive
+        /// For relative times, longer ago than that, e.g. `modified:>7d`
+        Greater(DateValue, bool),
+        /// `modified:<2024-03` or `modified:<=2024-03`, with whether it is inclusive
+        /// For relative times, more recently than that, e.g. `modified:<7d`
+        Less(DateValue, bool),
+        /// `modified:2023..2024-03`, `modified:7d..30d`, or open ended `modified:2024..`
+        Range(Option<DateValue>, Option<DateValue>),
+    }
+
+    impl DateBound {
+        pub fn parse(str: &str) -> Option<DateBound> {
+            if let Some((a, b)) = str.split_once("..") {
+                let a = if a.is_empty() { None } else { Some(DateValue::parse(a)?) };
+                let b = if b.is_empty() { None } else { Some(DateValue::parse(b)?) };
+                if a.is_none() && b.is_none() {
+                    return None;
+                }
+                return Some(DateBound::Range(a, b));
+            }
+
+            Some(if let Some(s) = str.strip_prefix(">=") {
+                DateBound::Greater(DateValue::parse(s)?, true)
+            } else if let Some(s) = str.strip_prefix("<=") {
+                DateBound::Less(DateValue::parse(s)?, true)
+            } else if let Some(s) = str.strip_prefix('>') {
+                DateBound::Greater(DateValue::parse(s)?, false)
+            } else if let Some(s) = str.strip_prefix('<') {
+                DateBound::Less(DateValue::parse(s)?, false)
+            } else {
+                DateBound::During(DateValue::parse(str)?)
+            })
+        }
+
+        /// Get the [`Interval`] of times this lets through, relative to `now`
+        fn interval(&self, now: DateTime<Local>) -> Option<Interval> {
+            Some(match *self {
+                // Relative times count backwards from now
+                DateBound::During(v) | DateBound::Less(v, _) if v.is_relative() => (Some(v.span(now)?.0), None),
+                DateBound::Greater(v, _) if v.is_relative() => (None, Some(v.span(now)?.0)),
+
+                DateBound::During(v) => {
+                    let (start, end) = v.span(now)?;
+                    (Some(start), Some(end))
+                }
+                DateBound::Greater(v, inclusive) => {
+                    let (start, end) = v.span(now)?;
+                    (Some(if inclusive { start } else { end }), None)
+                }
+                DateBound::Less(v, inclusive) => {
+                    let (start, end) = v.span(now)?;
+                    (None, Some(if inclusive { end } else { start }))
+                }
+
+                DateBound::Range(a, b) => {
+                    let a = a.map(|v| v.span(now)).map_or(Some(None), |span| span.map(Some))?;
+                    let b = b.map(|v| v.span(now)).map_or(Some(None), |span| span.map(Some))?;
+                    match (a, b) {
+                        (Some(a), Some(b)) => (Some(a.0.min(b.0)), Some(a.1.max(b.1))),
+                        (Some(a), None) => (Some(a.0), None),
+                        (None, Some(b)) => (None, Some(b.1)),
+                        (None, None) => (None, None),
+                    }
+                }
+            })
+        }
+    }
+
+    /// Filter paths by their modification, creation or access date
+    /// E.g. `modified:<7d`, `modified:2024-03`, `created:>2023-01-01`, `accessed:today`
+    /// See [`DateBound`] and [`DateValue`] for all accepted forms
+    /// Paths whose date can't be read never match, even if inverted
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Date {
+        pub field: DateField,
+        pub bound: DateBound,
+        pub inverted: bool,
+    }
+
+    impl Date {
+        pub fn parse(str: &str) -> Option<Date> {
+            let (str, inverted) = str.strip_prefix('!')
+                .map_or((str, false), |s| (s, true));
+            let (field, str) = str.split_once(':')?;
+            let field = match field {
+                "modified" => DateField::Modified,
+                "created" => DateField::Created,
+                "accessed" => DateField::Accessed,
+                _ => return None,
+            };
+
+            Some(Date {
+                field,
+                bound: DateBound::parse(str)?,
+                inverted,
+            })
+        }
+
+        /// Access dates aren't kept in [`Meta`], so they are read from `path`
+        fn matches(&self, path: &Path, meta: &Meta, now: DateTime<Local>) -> bool {
+            let time = match self.field {
+                DateField::Modified => meta.modified,
+                DateField::Created => meta.created,
+                DateField::Accessed => fs::metadata(path).and_then(|m| m.accessed()).ok(),
+            };
+            time.is_some_and(|time| self.matches_time(time, now))
+        }
+
+        fn matches_time(&self, time: SystemTime, now: DateTime<Local>) -> bool {
+            let Some((min, max)) = self.bound.interval(now) else {
+                return false;
+            };
+            let time: DateTime<Local> = time.into();
+            let contains: bool = min.is_none_or(|min| time >= min)
+                && max.is_none_or(|max| time < max);
+            contains != self.inverted
         }
     }
 
@@ -503,19 +2065,145 @@ mod constraint {
         }
     }
 
+    /// What to order search results by. See [`Sort`]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum SortKey {
+        /// Best match first
+        #[default]
+        Score,
+        Name,
+        Extension,
+        Modified,
+        Created,
+        Size,
+    }
+
+    impl SortKey {
+        pub const ALL: [SortKey; 6] = [
+            SortKey::Score,
+            SortKey::Name,
+            SortKey::Extension,
+            SortKey::Modified,
+            SortKey::Created,
+            SortKey::Size,
+        ];
+
+        fn parse(str: &str) -> Option<SortKey> {
+            match str {
+                "score" => Some(SortKey::Score),
+                "name" => Some(SortKey::Name),
+                "ext" | "extension" => Some(SortKey::Extension),
+                "mtime" | "modified" => Some(SortKey::Modified),
+                "ctime" | "created" => Some(SortKey::Created),
+                "size" => Some(SortKey::Size),
+                _ => None,
+            }
+        }
+
+        /// Whether results need their [`Meta`] to be compared by this key
+        pub fn needs_metadata(&self) -> bool {
+            matches!(self, SortKey::Modified | SortKey::Created | SortKey::Size)
+        }
+    }
+
+    impl Display for SortKey {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                SortKey::Score => write!(f, "Score"),
+                SortKey::Name => write!(f, "Name"),
+                SortKey::Extension => write!(f, "Extension"),
+                SortKey::Modified => write!(f, "Modified"),
+                SortKey::Created => write!(f, "Created"),
+                SortKey::Size => write!(f, "Size"),
+            }
+        }
+    }
+
+    /// How to order search results, e.g. `sort:mtime` or `sort:-size`
+    /// Results go from A to Z, or oldest or smallest first, except for [`SortKey::Score`], which
+    /// puts the best matches first. A `-` reverses the order
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Sort {
+        pub key: SortKey,
+        pub reversed: bool,
+    }
+
+    impl Sort {
+        pub fn parse(str: &str) -> Option<Sort> {
+            let str = str.strip_prefix("sort:")?;
+            let (str, reversed) = str.strip_prefix('-')
+                .map_or((str, false), |s| (s, true));
+
+            Some(Sort {
+                key: SortKey::parse(str)?,
+                reversed,
+            })
+        }
+
+        /// Compare two results
+        /// Results missing what's compared (e.g. an extension or a date) always go last
+        /// Ties are [`Ordering::Equal`], so stable sorts keep them in the order they came in
+        pub fn compare(&self, a: &Item, b: &Item) -> Ordering {
+            fn name(item: &Item) -> Option<String> {
+                item.1.file_name().map(|name| name.to_string_lossy().to_lowercase())
+            }
+            fn extension(item: &Item) -> Option<String> {
+                item.1.extension().map(|ext| ext.to_string_lossy().to_lowercase())
+            }
+
+            match self.key {
+                SortKey::Score => self.directed(b.0.cmp(&a.0)),
+                SortKey::Name => self.compare_by(name(a), name(b)),
+                SortKey::Extension => self.compare_by(extension(a), extension(b))
+                    .then_with(|| self.compare_by(name(a), name(b))),
+                SortKey::Modified => self.compare_by(
+                    a.3.and_then(|meta| meta.modified),
+                    b.3.and_then(|meta| meta.modified),
+                ),
+                SortKey::Created => self.compare_by(
+                    a.3.and_then(|meta| meta.created),
+                    b.3.and_then(|meta| meta.created),
+                ),
+                SortKey::Size => self.compare_by(
+                    a.3.map(|meta| meta.size),
+                    b.3.map(|meta| meta.size),
+                ),
+            }
+        }
+
+        fn compare_by<T: Ord>(&self, a: Option<T>, b: Option<T>) -> Ordering {
+            match (a, b) {
+                (Some(a), Some(b)) => self.directed(a.cmp(&b)),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        }
+
+        fn directed(&self, ordering: Ordering) -> Ordering {
+            if self.reversed {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        }
+    }
+
 
 
 
     #[cfg(test)]
     mod tests {
         use std::ffi::OsString;
-        use std::path::Path;
+        use std::path::{Path, PathBuf};
 
+        use crate::app::main_screen::Item;
         use crate::search::constraint::Fuzzy;
-        use crate::strmatch::Sublime;
+        use crate::strmatch::{CaseMode, Sublime};
+        use crate::TestDir;
 
         use super::ConstraintList;
-        use super::{ Exact, Extension, FileType };
+        use super::{ Exact, Extension, Field, FileType, Kind, Note, ParseError, Sort, SortKey };
 
 
         #[test]
@@ -523,14 +2211,15 @@ mod constraint {
             let c = ConstraintList::parse("score .rs .png");
             assert_eq!(c.fuzzy, vec![
                 Fuzzy {
+                    field: Field::Auto,
                     matcher: Sublime::default() .with_query("score"),
                     inverted: false,
                 }
             ]);
             assert_eq!(c.exact, vec![]);
             assert_eq!(c.extensions, vec![
-                Extension { extension: OsString::from("rs"), inverted: false },
-                Extension { extension: OsString::from("png"), inverted: false } 
+                Extension { extension: OsString::from("rs"), inverted: false, case: CaseMode::Smart },
+                Extension { extension: OsString::from("png"), inverted: false, case: CaseMode::Smart } 
             ]);
             assert_eq!(c.filetype, None);
 
@@ -538,19 +2227,18 @@ mod constraint {
             let c = ConstraintList::parse("score \"exact\" .txt -f --wot");
             assert_eq!(c.fuzzy, vec![
                 Fuzzy {
+                    field: Field::Auto,
                     matcher: Sublime::default() .with_query("score"),
                     inverted: false,
-                },
-                Fuzzy {
-                    matcher: Sublime::default() .with_query("--wot"),
-                    inverted: false,
                 }
             ]);
+            // Unknown flags are errors
+            assert_eq!(c.errors.len(), 1);
             assert_eq!(c.exact, vec![
-                Exact { query: "exact".to_string(), inverted: false }
+                Exact { query: "exact".to_string(), field: Field::Auto, inverted: false, case: CaseMode::Smart }
             ]);
             assert_eq!(c.extensions, vec![
-                Extension { extension: OsString::from("txt"), inverted: false } 
+                Extension { extension: OsString::from("txt"), inverted: false, case: CaseMode::Smart } 
             ]);
             assert_eq!(c.filetype, Some(FileType::File));
 
@@ -560,6 +2248,7 @@ mod constraint {
             dbg!(&c.fuzzy);
             assert_eq!(c.fuzzy, vec![
                 Fuzzy {
+                    field: Field::Auto,
                     matcher: Sublime::default() .with_query("\"\""),
                     inverted: false,
                 }
@@ -648,6 +2337,501 @@ mod constraint {
 
         }
 
+        #[test]
+        fn notes() {
+            use crate::tagging::annotation::Annotation;
+
+            let c = ConstraintList::parse("report note:\"sent to client\" .pdf !note:draft");
+            assert_eq!(c.notes, vec![
+                Note { query: "sent to client".to_string(), inverted: false, case: CaseMode::Smart },
+                Note { query: "draft".to_string(), inverted: true, case: CaseMode::Smart },
+            ]);
+            assert_eq!(c.exact, vec![]);
+            assert_eq!(c.extensions, vec![
+                Extension { extension: OsString::from("pdf"), inverted: false, case: CaseMode::Smart },
+            ]);
+            assert_eq!(c.fuzzy, vec![
+                Fuzzy {
+                    field: Field::Auto,
+                    matcher: Sublime::default() .with_query("report"),
+                    inverted: false,
+                }
+            ]);
+
+            let path = Path::new("C:/Users/ddxte/Documents/report.pdf");
+            let sent = Annotation::new("Final version, sent to Client");
+            let draft = Annotation::new("Draft, sent to client for review");
+
+            assert!( c.score_annotated(path, &[&sent]).is_some() );
+            assert!( c.score_annotated(path, &[&draft]).is_none() );
+            assert!( c.score_annotated(path, &[]).is_none() );
+            assert!( c.score(path).is_none() );
+
+            // Unquoted
+            let c = ConstraintList::parse("note:client");
+            assert_eq!(c.notes, vec![
+                Note { query: "client".to_string(), inverted: false, case: CaseMode::Smart },
+            ]);
+            assert!(c.fuzzy.is_empty());
+        }
+
+        #[test]
+        fn narrowing() {
+            let narrows = |query: &str, previous: &str| {
+                ConstraintList::parse(query).narrows(&ConstraintList::parse(previous))
+            };
+
+            assert!(narrows("dino", "din"));
+            assert!(narrows("dino", "do"));
+            assert!(narrows("dino .png", "dino"));
+            assert!(narrows("dino -f", "dino"));
+            assert!(narrows("dino \"cool\"", "dino \"co\""));
+            assert!(narrows("!\"co\"", "!\"cool\""));
+            assert!(narrows("report note:client", "report"));
+            assert!(narrows("!dino", "!di"));
+
+            // Searches without constraints only list the entries themselves
+            assert!(!narrows("dino", ""));
+            assert!(!narrows("din", "dino"));
+            assert!(!narrows("dino .png .jpg", "dino .png"));
+            assert!(!narrows("dino pics", "dino"));
+            assert!(!narrows("dino -d", "dino -f"));
+            assert!(!narrows("!\"cool\"", "!\"co\""));
+            assert!(!narrows("dino -a", "dino"));
+
+            // Only with the case mode they are searched with
+            let narrows_with_case = |query: &str, previous: &str, case: CaseMode| {
+                let (mut c, mut p) = (ConstraintList::parse(query), ConstraintList::parse(previous));
+                c.set_case_mode(case);
+                p.set_case_mode(case);
+                c.narrows(&p)
+            };
+            assert!(narrows_with_case("readMe", "readme", CaseMode::Smart));
+            assert!(!narrows_with_case("readMe", "readme", CaseMode::Sensitive));
+        }
+
+        #[test]
+        fn content() {
+            use std::fs;
+            use super::{ Content, ContentMatch, ContentQuery };
+
+            let c = ConstraintList::parse("todo content:\"Fix the\" !content:/^\\s*$/ .md");
+            assert_eq!(c.content.len(), 2);
+            assert_eq!(c.content[0], Content {
+                query: ContentQuery::Text("Fix the".to_string()),
+                inverted: false,
+                case: CaseMode::Smart,
+            });
+            assert!(c.content[1].inverted && matches!(c.content[1].query, ContentQuery::Regex(_)));
+            assert_eq!(c.fuzzy, vec![
+                Fuzzy {
+                    field: Field::Auto,
+                    matcher: Sublime::default() .with_query("todo"),
+                    inverted: false,
+                }
+            ]);
+            assert_eq!(c.extensions.len(), 1);
+
+            // Invalid regexes are errors
+            let c = ConstraintList::parse("content:/(/");
+            assert!(c.content.is_empty());
+            assert_eq!(c.errors.len(), 1);
+            assert_ne!(ConstraintList::parse("content:/a/"), ConstraintList::parse("content:/a/i"));
+
+            let dir = TestDir::new("content");
+            let notes = dir.join("notes.txt");
+            let binary = dir.join("data.bin");
+            fs::write(&notes, "groceries\n  we should fix the sink  \nmore\n").unwrap();
+            fs::write(&binary, b"fix the\0sink").unwrap();
+
+            let c = ConstraintList::parse("content:\"fix the\"");
+            assert_eq!(
+                c.match_annotated(&notes, &[]).and_then(|(_, m)| m),
+                Some(ContentMatch { line_number: 2, line: "we should fix the sink".to_string() })
+            );
+            assert_eq!(c.score(&binary), None);
+            assert_eq!(c.score(&dir), None);
+
+            let c = ConstraintList::parse("content:/FIX THE S.NK/i");
+            assert!(c.score(&notes).is_some());
+            assert!(ConstraintList::parse("!content:sink").score(&notes).is_none());
+            assert!(ConstraintList::parse("!content:boat").score(&notes).is_some());
+
+            // Too big
+            let mut c = ConstraintList::parse("content:sink");
+            c.max_content_size = Some(8);
+            assert_eq!(c.score(&notes), None);
+
+        }
+
+        #[test]
+        fn sizes() {
+            use std::fs;
+            use super::{ parse_size, Size };
+
+            assert_eq!(parse_size("512"), Some(512));
+            assert_eq!(parse_size("4k"), Some(4096));
+            assert_eq!(parse_size("1.5MB"), Some(1572864));
+            assert_eq!(parse_size("2GiB"), Some(2 * 1024 * 1024 * 1024));
+            assert_eq!(parse_size("4 k"), None);
+            assert_eq!(parse_size("lots"), None);
+
+            assert_eq!(Size::parse("size:>10MB"), Some(Size { min: Some(10 * 1024 * 1024 + 1), max: None, inverted: false }));
+            assert_eq!(Size::parse("size:<4k"), Some(Size { min: None, max: Some(4095), inverted: false }));
+            assert_eq!(Size::parse("!size:1M..50M"), Some(Size { min: Some(1024 * 1024), max: Some(50 * 1024 * 1024), inverted: true }));
+            assert_eq!(Size::parse("size:..2k"), Some(Size { min: None, max: Some(2048), inverted: false }));
+            assert_eq!(Size::parse("size:.."), None);
+            assert_eq!(Size::parse("size:<0"), None);
+
+            let c = ConstraintList::parse("report size:>1k");
+            assert_eq!(c.sizes.len(), 1);
+            assert_eq!(c.fuzzy.len(), 1);
+
+            let dir = TestDir::new("sizes");
+            let small = dir.join("small.txt");
+            let big = dir.join("big.txt");
+            fs::write(&small, "tiny").unwrap();
+            fs::write(&big, vec![ b'a'; 2048 ]).unwrap();
+
+            let c = ConstraintList::parse("size:>1k");
+            assert!(c.score(&big).is_some());
+            assert!(c.score(&small).is_none());
+            assert!(c.score(&dir).is_none());
+
+            let c = ConstraintList::parse("!size:>1k");
+            assert!(c.score(&big).is_none());
+            assert!(c.score(&small).is_some());
+
+            assert!(ConstraintList::parse("size:2k..4k").narrows(&ConstraintList::parse("size:>1k")));
+            assert!(!ConstraintList::parse("size:>1k").narrows(&ConstraintList::parse("size:1k..4k")));
+            assert!(ConstraintList::parse("!size:>1k").narrows(&ConstraintList::parse("!size:>2k")));
+
+        }
+
+        #[test]
+        fn dates() {
+            use chrono::{Local, NaiveDate, TimeDelta, TimeZone};
+            use super::{ Date, DateBound, DateField, DateValue };
+
+            assert_eq!(DateValue::parse("7d"), Some(DateValue::Ago(TimeDelta::days(7))));
+            assert_eq!(DateValue::parse("2w"), Some(DateValue::Ago(TimeDelta::days(14))));
+            assert_eq!(DateValue::parse("30h"), Some(DateValue::Ago(TimeDelta::hours(30))));
+            assert_eq!(DateValue::parse("2024"), Some(DateValue::Year(2024)));
+            assert_eq!(DateValue::parse("2024-03"), Some(DateValue::Month(2024, 3)));
+            assert_eq!(DateValue::parse("2024-03-15"), NaiveDate::from_ymd_opt(2024, 3, 15).map(DateValue::Day));
+            assert_eq!(DateValue::parse("2024-13"), None);
+            assert_eq!(DateValue::parse("soon"), None);
+            assert_eq!(DateValue::parse("7x"), None);
+
+            assert_eq!(Date::parse("!created:>=2023-01"), Some(Date {
+                field: DateField::Created,
+                bound: DateBound::Greater(DateValue::Month(2023, 1), true),
+                inverted: true,
+            }));
+            assert_eq!(Date::parse("changed:today"), None);
+
+            let c = ConstraintList::parse("report modified:<7d");
+            assert_eq!(c.dates.len(), 1);
+            assert_eq!(c.fuzzy.len(), 1);
+
+            let now = Local.with_ymd_and_hms(2024, 3, 15, 12, 0, 0).unwrap();
+            let at = |y, m, d| -> std::time::SystemTime {
+                Local.with_ymd_and_hms(y, m, d, 9, 0, 0).unwrap().into()
+            };
+            let a = |query: &str, time, expected: bool| {
+                let date = Date::parse(query).unwrap();
+                assert_eq!(date.matches_time(time, now), expected, "{query}");
+            };
+
+            a("modified:<7d", at(2024, 3, 10), true);
+            a("modified:<7d", at(2024, 3, 1), false);
+            a("modified:>7d", at(2024, 3, 1), true);
+            a("modified:7d", at(2024, 3, 10), true);
+            a("!modified:<7d", at(2024, 3, 10), false);
+            a("modified:2024-03", at(2024, 3, 1), true);
+            a("modified:2024-03", at(2024, 2, 29), false);
+            a("modified:>2024-02", at(2024, 2, 29), false);
+            a("modified:>=2024-02", at(2024, 2, 29), true);
+            a("modified:<2024", at(2023, 12, 31), true);
+            a("modified:<=2024", at(2024, 12, 31), true);
+            a("modified:2023..2024-02", at(2024, 2, 15), true);
+            a("modified:2023..2024-02", at(2024, 3, 1), false);
+            a("modified:2024-03-01..", at(2024, 3, 10), true);
+            a("modified:3d..10d", at(2024, 3, 10), true);
+            a("modified:3d..10d", at(2024, 3, 14), false);
+            a("accessed:today", at(2024, 3, 15), true);
+            a("accessed:yesterday", at(2024, 3, 14), true);
+            a("accessed:yesterday", at(2024, 3, 15), false);
+        }
+
+        #[test]
+        fn tags() {
+            use super::TagFilter;
+            use crate::tagging::id::TagID;
+
+            let c = ConstraintList::parse("cat #pictures !#memes #Music|#my-videos #|#a");
+            assert_eq!(c.tags, vec![
+                TagFilter { any_of: vec![TagID::new("pictures")], inverted: false },
+                TagFilter { any_of: vec![TagID::new("memes")], inverted: true },
+                TagFilter { any_of: vec![TagID::new("music"), TagID::new("my-videos")], inverted: false },
+            ]);
+            // Malformed tags are searched for as text
+            assert_eq!(c.fuzzy.len(), 2);
+
+            // Included tags alone don't need any scoring
+            assert!(ConstraintList::parse("#pictures #music|#videos").is_empty());
+            assert!(!ConstraintList::parse("#pictures !#memes").is_empty());
+
+            // Changing the tags never narrows
+            let previous = ConstraintList::parse("#pictures cat");
+            assert!(ConstraintList::parse("#pictures cats").narrows(&previous));
+            assert!(!ConstraintList::parse("#pictures !#memes cats").narrows(&previous));
+        }
+
+        #[test]
+        fn globs() {
+            use std::path::Path;
+            use super::Glob;
+
+            let c = ConstraintList::parse("backup *.tar.gz .d.ts !*~");
+            assert_eq!(c.globs, vec![
+                Glob { pattern: "*.tar.gz".chars().collect(), inverted: false, case: CaseMode::Smart },
+                Glob { pattern: "*~".chars().collect(), inverted: true, case: CaseMode::Smart },
+            ]);
+            assert_eq!(c.extensions, vec![
+                Extension { extension: OsString::from("d.ts"), inverted: false, case: CaseMode::Smart },
+            ]);
+            assert_eq!(c.fuzzy.len(), 1);
+
+            let c = ConstraintList::parse("*.tar.gz .d.ts");
+            assert!(c.score(Path::new("C:/Backups/Photos.TAR.GZ")).is_some());
+            assert!(c.score(Path::new("C:/Projects/web/index.d.ts")).is_some());
+            assert!(c.score(Path::new("C:/Projects/web/index.ts")).is_none());
+            assert!(c.score(Path::new("C:/Backups/photos.gz")).is_none());
+
+            let c = ConstraintList::parse("!*~ !.tar.gz");
+            assert!(c.score(Path::new("C:/Backups/photos.tar.gz~")).is_none());
+            assert!(c.score(Path::new("C:/Backups/photos.tar.gz")).is_none());
+            assert!(c.score(Path::new("C:/Backups/photos.zip")).is_some());
+
+            let c = ConstraintList::parse("img_????.jpg");
+            assert!(c.score(Path::new("C:/Pictures/IMG_2024.jpg")).is_some());
+            assert!(c.score(Path::new("C:/Pictures/IMG_20245.jpg")).is_none());
+            assert!(c.score(Path::new("C:/Pictures/IMG_2024.jpg.png")).is_none());
+
+            // Multi-part extensions need a stem
+            let c = ConstraintList::parse(".gitignore");
+            assert!(c.score(Path::new("C:/Projects/.gitignore")).is_none());
+            assert!(c.score(Path::new("C:/Projects/nested.gitignore")).is_some());
+
+            let glob = Glob::parse("a*b*c").unwrap();
+            assert!(glob.matches("abc"));
+            assert!(glob.matches("aXbYbZc"));
+            assert!(!glob.matches("acb"));
+        }
+
+        #[test]
+        fn patterns() {
+            use std::path::Path;
+            use super::ParseError;
+
+            let c = ConstraintList::parse(r#"pics /IMG_\d{4}\.(jpe?g|png)$/i !re:thumb"#);
+            assert_eq!(c.patterns.len(), 2);
+            assert!(c.patterns[1].inverted);
+            assert_eq!(c.fuzzy, vec![
+                Fuzzy {
+                    field: Field::Auto,
+                    matcher: Sublime::default() .with_query("pics"),
+                    inverted: false,
+                }
+            ]);
+            assert!(c.errors.is_empty());
+
+            assert!(c.score(Path::new("C:/Users/ddxte/Pictures/img_2024.JPG")).is_some());
+            assert!(c.score(Path::new("C:/Users/ddxte/Pictures/thumbs/IMG_2024.png")).is_none());
+            assert!(c.score(Path::new("C:/Users/ddxte/Pictures/IMG_24.png")).is_none());
+
+            // Spaces and quotes are fine between slashes
+            let c = ConstraintList::parse(r#"/a "b"/"#);
+            assert_eq!(c.patterns.len(), 1);
+            assert!(c.exact.is_empty());
+
+            // Invalid regexes don't become fuzzy text
+            let c = ConstraintList::parse("dino /(unclosed/ re:[");
+            assert_eq!(c.errors.len(), 2);
+            assert!(matches!(&c.errors[0], ParseError::InvalidRegex { pattern, .. } if pattern == "(unclosed"));
+            assert_eq!(c.fuzzy.len(), 1);
+            assert!(c.patterns.is_empty());
+
+            // Flags are part of the regex
+            assert_ne!(ConstraintList::parse("/a/"), ConstraintList::parse("/a/i"));
+        }
+
+        #[test]
+        fn fields() {
+            let catalogue = Path::new("C:/Users/ddxte/Documents/catalogues/report.pdf");
+            let cat = Path::new("C:/Users/ddxte/Pictures/cat.png");
+            let cat_notes = Path::new("C:/Users/ddxte/Documents/my cat/notes.md");
+
+            let c = ConstraintList::parse("!name:cat stem:notes parent:\"my cat\" dir:docs path:users");
+            assert_eq!(c.fuzzy.iter().map(|f| (f.field, f.inverted)).collect::<Vec<_>>(), vec![
+                (Field::Name, true),
+                (Field::Stem, false),
+                (Field::Dir, false),
+                (Field::Path, false),
+            ]);
+            assert_eq!(c.exact, vec![
+                Exact { query: "my cat".to_string(), field: Field::Parent, inverted: false, case: CaseMode::Smart },
+            ]);
+            assert!(c.score(cat_notes).is_some());
+
+            // File names score higher by default
+            let c = ConstraintList::parse("cat");
+            assert!(c.score(cat) > c.score(catalogue));
+
+            let c = ConstraintList::parse("name:cat");
+            assert!(c.score(cat).is_some());
+            assert!(c.score(catalogue).is_none());
+            assert!(c.score(cat_notes).is_none());
+
+            let c = ConstraintList::parse("parent:catalogues");
+            assert!(c.score(catalogue).is_some());
+            assert!(c.score(cat).is_none());
+
+            let c = ConstraintList::parse("stem:png");
+            assert!(c.score(cat).is_none());
+
+            let c = ConstraintList::parse("dir:\"documents/my cat\"");
+            assert!(c.score(cat_notes).is_some());
+            assert!(c.score(catalogue).is_none());
+
+            // Unknown prefixes are searched for as text
+            let c = ConstraintList::parse("wot:cat");
+            assert_eq!(c.fuzzy[0].field, Field::Auto);
+        }
+
+        #[test]
+        fn show_ignored() {
+            let c = ConstraintList::parse("-a .conf");
+            assert!(c.show_ignored);
+            assert!(c.fuzzy.is_empty());
+
+            let c = ConstraintList::parse("--all");
+            assert!(c.show_ignored);
+            assert!(!ConstraintList::parse("all").show_ignored);
+        }
+
+        #[test]
+        fn kinds() {
+            let cat = Path::new("C:/Users/ddxte/Pictures/cat.PNG");
+            let clip = Path::new("C:/Users/ddxte/Videos/cat.mp4");
+            let code = Path::new("C:/Users/ddxte/Projects/cat.rs");
+
+            let c = ConstraintList::parse("kind:IMAGE kind:video");
+            assert_eq!(c.kinds, vec![
+                Kind { kind: "image".to_string(), inverted: false },
+                Kind { kind: "video".to_string(), inverted: false },
+            ]);
+            assert!(c.score(cat).is_some());
+            assert!(c.score(clip).is_some());
+            assert!(c.score(code).is_none());
+
+            let c = ConstraintList::parse("cat !kind:code");
+            assert!(c.score(cat).is_some());
+            assert!(c.score(code).is_none());
+
+            let c = ConstraintList::parse("kind:wot cat");
+            assert!(c.kinds.is_empty());
+            assert!(matches!(&c.errors[..], [ParseError::UnknownKind { kind, span }] if kind == "wot" && *span == (0..8)));
+        }
+
+        #[test]
+        fn sorting() {
+            let c = ConstraintList::parse("cat sort:-size");
+            assert_eq!(c.sort, Some(Sort { key: SortKey::Size, reversed: true }));
+            assert_eq!(c.fuzzy.len(), 1);
+            assert!(ConstraintList::parse("sort:mtime").is_empty());
+            assert_eq!(ConstraintList::parse("sort:ext").sort.map(|s| s.key), Some(SortKey::Extension));
+            // Unknown keys are searched for as text
+            assert_eq!(ConstraintList::parse("sort:wot").sort, None);
+
+            let item = |score: isize, path: &str| Item(score, PathBuf::from(path), None, None);
+            let mut items = vec![
+                item(3, "b.txt"),
+                item(1, "c"),
+                item(5, "a.png"),
+                item(3, "d.txt"),
+            ];
+            let paths = |items: &[Item]| items.iter()
+                .map(|item| item.1.to_string_lossy().to_string())
+                .collect::<Vec<String>>();
+
+            items.sort_by(|a, b| Sort::default().compare(a, b));
+            assert_eq!(paths(&items), vec!["a.png", "b.txt", "d.txt", "c"]);
+
+            let sort = Sort { key: SortKey::Name, reversed: true };
+            items.sort_by(|a, b| sort.compare(a, b));
+            assert_eq!(paths(&items), vec!["d.txt", "c", "b.txt", "a.png"]);
+
+            // Paths without an extension go last either way
+            let sort = Sort { key: SortKey::Extension, reversed: true };
+            items.sort_by(|a, b| sort.compare(a, b));
+            assert_eq!(paths(&items), vec!["d.txt", "b.txt", "a.png", "c"]);
+
+            // Ties keep their order
+            let sort = Sort { key: SortKey::Size, reversed: false };
+            items.sort_by(|a, b| sort.compare(a, b));
+            assert_eq!(paths(&items), vec!["d.txt", "b.txt", "a.png", "c"]);
+        }
+
+        #[test]
+        fn case() {
+            let readme = Path::new("C:/Projects/kfiles/README.md");
+            let lower = Path::new("C:/Projects/kfiles/docs/readme.md");
+
+            // Smart-case by default
+            for query in ["readme", "\"readme\"", "name:readme", "*readme*", ".md"] {
+                let c = ConstraintList::parse(query);
+                assert!(c.score(readme).is_some(), "query = {query}");
+                assert!(c.score(lower).is_some(), "query = {query}");
+            }
+            for query in ["README", "\"README\"", "name:README", "README*"] {
+                let c = ConstraintList::parse(query);
+                assert!(c.score(readme).is_some(), "query = {query}");
+                assert!(c.score(lower).is_none(), "query = {query}");
+            }
+            assert!(ConstraintList::parse(".MD").score(readme).is_none());
+
+            // `case:` applies to the whole query, groups included
+            let c = ConstraintList::parse("case:insensitive (README | \"nothing\")");
+            assert_eq!(c.case_mode, Some(CaseMode::Insensitive));
+            assert!(c.score(lower).is_some());
+            assert!(c.fuzzy.is_empty());
+
+            let c = ConstraintList::parse("\"readme\" case:sensitive");
+            assert_eq!(c.exact[0].case, CaseMode::Sensitive);
+            assert!(c.score(readme).is_none());
+            assert!(c.score(lower).is_some());
+
+            let mut c = ConstraintList::parse("README");
+            assert_eq!(c.case_mode, None);
+            c.set_case_mode(CaseMode::Insensitive);
+            assert!(c.score(lower).is_some());
+
+            // Unknown modes are searched for as text
+            assert_eq!(ConstraintList::parse("case:wot").case_mode, None);
+
+            // A case insensitive query matches more than a sensitive one
+            let narrows = |query: &str, previous: &str| {
+                ConstraintList::parse(query).narrows(&ConstraintList::parse(previous))
+            };
+            assert!(narrows("Dino", "dino"));
+            assert!(narrows("\"Dino\"", "\"dino\""));
+            assert!(!narrows("dino case:insensitive", "dino case:sensitive"));
+            assert!(!narrows("\"dino\" case:insensitive", "\"dino\" case:sensitive"));
+            assert!(!narrows("!\"dino\" case:sensitive", "!\"dino\" case:insensitive"));
+        }
+
         #[test]
         fn test_parse() {
             // let mut str: String = "abc 'bla' --other 'all the rest" .to_string();
@@ -735,3 +2919,83 @@ mod constraint {
 
 
 }
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    use crate::archive;
+    use crate::ignore_rules::IgnoreRules;
+    use crate::tagging::entries::Entries;
+    use crate::TestDir;
+
+    use super::{CancelToken, ConstraintList, Searcher};
+
+    #[test]
+    fn cancelling() {
+        let dir = TestDir::new("cancel");
+        for i in 0..10 {
+            File::create(dir.join(format!("{i}.txt"))).unwrap();
+        }
+
+        let token = CancelToken::new();
+        let mut searcher = Searcher::new(
+            Entries::from(vec![ dir.clone() ]),
+            ConstraintList::parse(".txt"),
+            &IgnoreRules::none()
+        )
+        .with_cancel_token(token.clone());
+
+        assert!(searcher.next().is_some());
+        token.cancel();
+        assert!(searcher.next().is_none());
+
+    }
+
+    #[test]
+    fn result_metadata() {
+        let dir = TestDir::new("result-metadata");
+        fs::write(dir.join("notes.txt"), "hello").unwrap();
+
+        let search = |searcher: Searcher| -> Vec<Option<u64>> {
+            searcher.map(|item| item.3.map(|meta| meta.size)).collect()
+        };
+        let searcher = || Searcher::new(Entries::from(vec![ dir.clone() ]), ConstraintList::parse(".txt"), &IgnoreRules::none());
+        assert_eq!(search(searcher()), vec![ None ]);
+        assert_eq!(search(searcher().with_metadata()), vec![ Some(5) ]);
+
+    }
+
+    #[test]
+    fn archive_member_metadata() {
+        let dir = TestDir::new("member-metadata");
+
+        let mut zip = zip::ZipWriter::new(File::create(dir.join("assets.zip")).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        let old = options.last_modified_time(zip::DateTime::from_date_and_time(2001, 2, 3, 4, 5, 6).unwrap());
+        zip.start_file("old.txt", old).unwrap();
+        zip.start_file("big.txt", options).unwrap();
+        zip.write_all(&[b'a'; 2000]).unwrap();
+        zip.start_file("small.txt", options).unwrap();
+        zip.write_all(b"hello").unwrap();
+        zip.finish().unwrap();
+
+        // Member metadata comes from the archive, since members can't be read from the filesystem
+        let search = |query: &str| -> Vec<PathBuf> {
+            Searcher::new(Entries::from(vec![ dir.clone() ]), ConstraintList::parse(query), &IgnoreRules::none())
+                .with_archives()
+                .map(|item| item.1)
+                .collect()
+        };
+        let member = |name: &str| archive::member_path(&dir.join("assets.zip"), Path::new(name));
+        assert_eq!(search("size:>1k"), vec![ member("big.txt") ]);
+        assert_eq!(search(".txt modified:2001-02"), vec![ member("old.txt") ]);
+        assert_eq!(search(".txt modified:2002"), Vec::<PathBuf>::new());
+
+    }
+}
@@ -0,0 +1,657 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::{self, create_dir_all};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ignore::gitignore::Gitignore;
+use nanoserde::{DeBin, SerBin};
+
+use crate::ignore_rules::IgnoreRules;
+use crate::tagging::entries::EntryOptions;
+use crate::tagging::Tag;
+use crate::{error, get_temp_dir};
+
+
+/// Bump this whenever the on-disk format changes, so that old indexes get rebuilt
+const INDEX_VERSION: u32 = 3;
+
+/// Refreshing only reads files again when their folder changes, which editing a file doesn't do
+/// So indexes get built again from scratch once they are this old
+const MAX_INDEX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Indexes that are loaded in memory, by [`get_key`]
+static LOADED: Mutex<BTreeMap<u64, Arc<RootIndex>>> = Mutex::new(BTreeMap::new());
+
+
+
+/// Get the directory where indexes are stored
+pub fn get_index_dir() -> PathBuf {
+    get_temp_dir().join("index/")
+}
+
+/// Get the key identifying the index of `root` walked with `options` and `rules`
+fn get_key(root: &Path, options: EntryOptions, rules: &IgnoreRules) -> u64 {
+    let mut s = DefaultHasher::new();
+    (root, options, rules).hash(&mut s);
+    s.finish()
+}
+
+fn get_index_path(key: u64) -> PathBuf {
+    get_index_dir().join(format!("{key}.bin"))
+}
+
+/// Get a loaded index of `root` walked with `options`, that can be searched with `rules`
+/// See [`IgnoreRules::extends`]
+/// Returns `None` if there is none, in which case you should walk `root` instead
+/// Indexes get loaded from disk by the [`Indexer`]
+pub fn get(root: &Path, options: EntryOptions, rules: &IgnoreRules) -> Option<Arc<RootIndex>> {
+    if !root.is_dir() {
+        return None;
+    }
+
+    LOADED.lock().ok()?
+        .values()
+        .find(|index| index.root == root && index.options == options && rules.extends(&index.rules))
+        .cloned()
+}
+
+/// Get the index of `root` walked with exactly `options` and `rules`, if it has been built
+/// Indexes get loaded from disk the first time they are needed
+fn get_built(root: &Path, options: EntryOptions, rules: &IgnoreRules) -> Option<Arc<RootIndex>> {
+    let key = get_key(root, options, rules);
+    if let Some(index) = LOADED.lock().ok()?.get(&key) {
+        return Some(index.clone());
+    }
+
+    let index = match RootIndex::load(root, options, rules) {
+        Ok(index) => index?,
+        Err(err) => {
+            error!("[index::get_built()] Failed to load index of {}:\n {}", root.display(), err);
+            return None;
+        }
+    };
+    Some(set_loaded(index))
+}
+
+/// Returns the loaded index
+/// Indexes of the same folder walked with other rules are unloaded, since they don't get
+/// refreshed anymore
+fn set_loaded(index: RootIndex) -> Arc<RootIndex> {
+    let key = index.key();
+    let index = Arc::new(index);
+    if let Ok(mut loaded) = LOADED.lock() {
+        loaded.retain(|_, other| other.root != index.root || other.options != index.options);
+        loaded.insert(key, index.clone());
+    }
+    index
+}
+
+
+
+/// What the index knows about a path
+/// Searches carry it along with each path, so that it doesn't have to be read again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Meta {
+    pub is_dir: bool,
+    /// Size in bytes. Always 0 for folders
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// Not every filesystem keeps track of it
+    pub created: Option<SystemTime>,
+}
+
+impl Meta {
+    /// Read the metadata of `path` from the filesystem
+    /// If `follow_links` is `false`, symbolic links are not traversed
+    fn read(path: &Path, follow_links: bool) -> Option<Meta> {
+        let meta = if follow_links {
+            fs::metadata(path)
+        } else {
+            fs::symlink_metadata(path)
+        }
+        .ok()?;
+
+        Some(Meta::from(&meta))
+    }
+}
+
+impl From<&fs::Metadata> for Meta {
+    fn from(meta: &fs::Metadata) -> Self {
+        Meta {
+            is_dir: meta.is_dir(),
+            size: if meta.is_dir() { 0 } else { meta.len() },
+            modified: meta.modified().ok(),
+            created: meta.created().ok(),
+        }
+    }
+}
+
+
+
+/// Every path under a folder entry, as of the last [`RootIndex::refresh`]
+/// The index stores what a walk through `root` with [`RootIndex::rules`] would find, usually
+/// the global ones, so that the patterns of each tag can be applied at query time
+/// See [`RootIndex::iter_paths`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootIndex {
+    pub root: PathBuf,
+    pub options: EntryOptions,
+    pub rules: IgnoreRules,
+    /// When the index was last built from scratch. See [`MAX_INDEX_AGE`]
+    pub built_at: SystemTime,
+    /// Sorted by path, so that every folder comes right before its contents
+    entries: Vec<(PathBuf, Meta)>,
+}
+
+impl RootIndex {
+    /// Walk through `root` and index everything in it that `rules` don't ignore
+    /// This can take a while, so you probably want to use an [`Indexer`] instead
+    pub fn build(root: &Path, options: EntryOptions, rules: IgnoreRules) -> Self {
+        let mut index = RootIndex {
+            root: root.to_path_buf(),
+            options,
+            rules,
+            built_at: SystemTime::now(),
+            entries: Vec::new(),
+        };
+
+        let mut entries: BTreeMap<PathBuf, Meta> = BTreeMap::new();
+        index.walk_into(&mut entries, root, options.max_depth);
+        index.entries = entries.into_iter().collect();
+        index
+    }
+
+    /// Load the saved index of `root`
+    /// Returns `Ok(None)` if there is none, or it was saved with an older format
+    pub fn load(root: &Path, options: EntryOptions, rules: &IgnoreRules) -> io::Result<Option<Self>> {
+        let bytes = match fs::read(get_index_path(get_key(root, options, rules))) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let ser = SerRootIndex::deserialize_bin(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        if ser.version != INDEX_VERSION || Path::new(&ser.root) != root {
+            return Ok(None);
+        }
+
+        Ok(Some(RootIndex {
+            root: root.to_path_buf(),
+            options,
+            rules: rules.clone(),
+            built_at: UNIX_EPOCH + Duration::from_secs(ser.built_at),
+            entries: ser.entries.into_iter()
+                .map(|e| (root.join(&e.path), e.meta()))
+                .collect(),
+        }))
+    }
+
+    /// Save this index to [`get_index_dir`], creating directories if necessary
+    /// The index is written to a temporary file first, so that a crash never leaves a
+    /// half-written index behind
+    pub fn save(&self) -> io::Result<()> {
+        let dir = get_index_dir();
+        if !dir.exists() {
+            create_dir_all(&dir)?;
+        }
+
+        let path = get_index_path(self.key());
+        let tmp_path = path.with_extension("bin.tmp");
+        fs::write(&tmp_path, SerRootIndex::from(self).serialize_bin())?;
+        fs::rename(&tmp_path, &path)
+    }
+
+    #[inline]
+    fn key(&self) -> u64 {
+        get_key(&self.root, self.options, &self.rules)
+    }
+
+    /// Returns whether the index is older than [`MAX_INDEX_AGE`], and should be built again
+    /// instead of refreshed
+    pub fn is_outdated(&self) -> bool {
+        self.built_at.elapsed().map_or(true, |age| age > MAX_INDEX_AGE)
+    }
+
+    /// Number of indexed paths, including the root
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get what the index knows about `path`
+    pub fn get(&self, path: &Path) -> Option<&Meta> {
+        self.entries.binary_search_by(|(pb, _)| pb.as_path().cmp(path))
+            .ok()
+            .map(|i| &self.entries[i].1)
+    }
+
+    /// Bring this index up to date with the filesystem
+    /// Only folders whose modification time changed get listed again, along with the files
+    /// directly in them, so this is much faster than [`RootIndex::build`]
+    /// Editing a file doesn't change the modification time of its folder, so its [`Meta`] can
+    /// stay outdated until then. See [`RootIndex::is_outdated`]
+    /// Returns whether anything changed
+    pub fn refresh(&mut self) -> bool {
+        let follow_links = self.options.follow_links;
+
+        let changed_dirs: Vec<PathBuf> = self.entries.iter()
+            .filter(|(pb, meta)| meta.is_dir && Meta::read(pb, follow_links).as_ref() != Some(meta))
+            .map(|(pb, _)| pb.clone())
+            .collect();
+        if changed_dirs.is_empty() {
+            return false;
+        }
+
+        let mut entries: BTreeMap<PathBuf, Meta> = std::mem::take(&mut self.entries)
+            .into_iter()
+            .collect();
+
+        for dir in changed_dirs {
+            // Already removed along with its parent
+            if !entries.contains_key(&dir) {
+                continue;
+            }
+
+            let Some(meta) = Meta::read(&dir, follow_links).filter(|m| m.is_dir) else {
+                remove_subtree(&mut entries, &dir);
+                continue;
+            };
+            entries.insert(dir.clone(), meta);
+
+            let depth = self.depth_of(&dir);
+            if self.options.max_depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+
+            let children: BTreeSet<PathBuf> = self.rules
+                .walk(&dir, self.options.with_max_depth(Some(1)))
+                .filter(|pb| pb != &dir)
+                .collect();
+
+            let old_children: Vec<PathBuf> = entries
+                .range::<PathBuf, _>((Bound::Excluded(&dir), Bound::Unbounded))
+                .map(|(pb, _)| pb)
+                .take_while(|pb| pb.starts_with(&dir))
+                .filter(|pb| pb.parent() == Some(dir.as_path()))
+                .cloned()
+                .collect();
+            for pb in old_children {
+                if !children.contains(&pb) {
+                    remove_subtree(&mut entries, &pb);
+                }
+            }
+
+            for child in children {
+                let Some(meta) = Meta::read(&child, follow_links) else {
+                    continue;
+                };
+
+                match entries.get(&child) {
+                    // Changed folders are handled on their own
+                    Some(old) if old.is_dir && meta.is_dir => {},
+                    Some(old) if old.is_dir == meta.is_dir => {
+                        entries.insert(child, meta);
+                    }
+                    // New, or replaced by a different kind of path
+                    _ => {
+                        remove_subtree(&mut entries, &child);
+                        let max_depth = self.options.max_depth.map(|max| max - depth - 1);
+                        self.walk_into(&mut entries, &child, max_depth);
+                    }
+                }
+            }
+        }
+
+        self.entries = entries.into_iter().collect();
+        true
+    }
+
+    /// Iterate through all indexed paths that aren't ignored by `rules`, along with their [`Meta`]
+    /// The root itself is always included
+    pub fn iter_paths(self: Arc<Self>, rules: &IgnoreRules) -> IndexIter {
+        IndexIter {
+            matcher: rules.build_matcher(&self.root),
+            index: self,
+            position: 0,
+            ignored_dir: None,
+        }
+    }
+
+    /// How many levels `path` is below the root
+    fn depth_of(&self, path: &Path) -> usize {
+        path.strip_prefix(&self.root)
+            .map_or(0, |p| p.components().count())
+    }
+
+    /// Walk through `dir` and add everything to `entries`
+    fn walk_into(&self, entries: &mut BTreeMap<PathBuf, Meta>, dir: &Path, max_depth: Option<usize>) {
+        for pb in self.rules.walk(dir, self.options.with_max_depth(max_depth)) {
+            if let Some(meta) = Meta::read(&pb, self.options.follow_links) {
+                entries.insert(pb, meta);
+            }
+        }
+    }
+}
+
+/// Remove `path` and, if it's a folder, everything in it
+fn remove_subtree(entries: &mut BTreeMap<PathBuf, Meta>, path: &Path) {
+    let paths: Vec<PathBuf> = entries
+        .range::<Path, _>((Bound::Included(path), Bound::Unbounded))
+        .map(|(pb, _)| pb)
+        .take_while(|pb| pb.starts_with(path))
+        .cloned()
+        .collect();
+    for pb in paths {
+        entries.remove(&pb);
+    }
+}
+
+
+
+/// Iterator over the paths in a [`RootIndex`] and their [`Meta`]
+/// See [`RootIndex::iter_paths`]
+pub struct IndexIter {
+    index: Arc<RootIndex>,
+    matcher: Gitignore,
+    position: usize,
+    /// The last folder that was ignored, so that its contents can be skipped
+    ignored_dir: Option<PathBuf>,
+}
+
+impl Iterator for IndexIter {
+    type Item = (PathBuf, Meta);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((pb, meta)) = self.index.entries.get(self.position) {
+            self.position += 1;
+
+            if self.ignored_dir.as_ref().is_some_and(|dir| pb.starts_with(dir)) {
+                continue;
+            }
+            if pb != &self.index.root && self.matcher.matched(pb, meta.is_dir).is_ignore() {
+                if meta.is_dir {
+                    self.ignored_dir = Some(pb.clone());
+                }
+                continue;
+            }
+
+            return Some((pb.clone(), *meta));
+        }
+
+        None
+    }
+}
+
+
+
+
+type Worker = JoinHandle<()>;
+
+/// Builds and refreshes [`RootIndex`]es on background threads, one folder per thread, at most
+/// `thread_count` threads at a time
+/// Call [`Indexer::update`] regularly to clean up finished threads and start the queued folders
+#[derive(Debug)]
+pub struct Indexer {
+    workers: HashMap<u64, Worker>,
+    /// Folders waiting for a free thread, by [`get_key`]
+    queue: VecDeque<(u64, PathBuf, EntryOptions, IgnoreRules)>,
+    thread_count: usize,
+}
+
+impl Indexer {
+    /// `thread_count` is usually [`crate::configs::Configs::get_search_thread_count`]
+    pub fn new(thread_count: usize) -> Self {
+        Indexer {
+            workers: HashMap::new(),
+            queue: VecDeque::new(),
+            thread_count: thread_count.max(1),
+        }
+    }
+
+    /// Queue indexing `root` with `rules` on a thread, unless it is already queued or being
+    /// indexed
+    /// If it has been indexed before, the index only gets refreshed
+    /// Returns whether the job was accepted
+    pub fn index(&mut self, root: PathBuf, options: EntryOptions, rules: &IgnoreRules) -> bool {
+        let key = get_key(&root, options, rules);
+        if self.workers.contains_key(&key) || self.queue.iter().any(|(k, ..)| *k == key) {
+            return false;
+        }
+
+        self.queue.push_back((key, root, options, rules.clone()));
+        self.spawn_queued();
+        true
+    }
+
+    /// Start the queued folders while there are free threads
+    fn spawn_queued(&mut self) {
+        while self.workers.len() < self.thread_count {
+            let Some((key, root, options, rules)) = self.queue.pop_front() else {
+                return;
+            };
+
+            let handle = thread::spawn(move || {
+                let (index, changed) = match get_built(&root, options, &rules) {
+                    Some(index) if !index.is_outdated() => {
+                        let mut index = RootIndex::clone(&index);
+                        let changed = index.refresh();
+                        (index, changed)
+                    }
+                    _ => (RootIndex::build(&root, options, rules), true),
+                };
+
+                if !changed {
+                    return;
+                }
+                if let Err(err) = index.save() {
+                    error!("[Indexer::spawn_queued()] Failed to save index of {}:\n {:?}", root.display(), err);
+                }
+                set_loaded(index);
+            });
+            self.workers.insert(key, handle);
+        }
+    }
+
+    /// Index every folder entry of `tags`, including subtags, with `rules`
+    /// `rules` are usually [`IgnoreRules::global`], since each tag's patterns get applied when
+    /// searching
+    pub fn index_tags(&mut self, tags: &[Tag], rules: &IgnoreRules) {
+        for tag in tags {
+            for (path, options) in tag.get_all_entries().into_iter_with_options() {
+                if path.is_dir() {
+                    self.index(path, options, rules);
+                }
+            }
+        }
+    }
+
+    /// Returns whether any folders are still queued or being indexed
+    pub fn is_busy(&self) -> bool {
+        !self.workers.is_empty() || !self.queue.is_empty()
+    }
+
+    /// Joins finished threads, then starts the queued folders
+    pub fn update(&mut self) {
+        let finished: Vec<u64> = self.workers.iter()
+            .filter(|(_, handle)| handle.is_finished())
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in finished {
+            let Some(handle) = self.workers.remove(&key) else {
+                continue;
+            };
+            if let Err(err) = handle.join() {
+                error!("[Indexer::update()] Failed to join index worker:\n {:?}", err);
+            }
+        }
+
+        self.spawn_queued();
+    }
+}
+
+
+
+
+#[derive(Debug, Clone, SerBin, DeBin)]
+struct SerRootIndex {
+    version: u32,
+    root: String,
+    /// Seconds since [`UNIX_EPOCH`]
+    built_at: u64,
+    entries: Vec<SerEntry>,
+}
+
+#[derive(Debug, Clone, SerBin, DeBin)]
+struct SerEntry {
+    /// Relative to the root
+    path: String,
+    is_dir: bool,
+    size: u64,
+    /// Seconds since [`UNIX_EPOCH`]
+    modified_secs: u64,
+    modified_nanos: u32,
+    /// Same as [`SerEntry::modified_secs`]
+    created_secs: u64,
+    created_nanos: u32,
+}
+
+impl SerEntry {
+    fn meta(&self) -> Meta {
+        Meta {
+            is_dir: self.is_dir,
+            size: self.size,
+            modified: from_secs(self.modified_secs, self.modified_nanos),
+            created: from_secs(self.created_secs, self.created_nanos),
+        }
+    }
+}
+
+/// Turn a time since [`UNIX_EPOCH`] back into a [`SystemTime`]
+/// Zero means the time wasn't known
+fn from_secs(secs: u64, nanos: u32) -> Option<SystemTime> {
+    (secs > 0 || nanos > 0).then(|| UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+/// Get the time since [`UNIX_EPOCH`] of `time`, or zero if it isn't known
+fn to_secs(time: Option<SystemTime>) -> Duration {
+    time.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default()
+}
+
+impl From<&RootIndex> for SerRootIndex {
+    fn from(value: &RootIndex) -> Self {
+        SerRootIndex {
+            version: INDEX_VERSION,
+            root: value.root.to_string_lossy().to_string(),
+            built_at: to_secs(Some(value.built_at)).as_secs(),
+            // Non UTF-8 paths get skipped, and will be found by walking again on the next refresh
+            entries: value.entries.iter()
+                .filter_map(|(pb, meta)| {
+                    let path = pb.strip_prefix(&value.root).ok()?.to_str()?.to_string();
+                    let modified = to_secs(meta.modified);
+                    let created = to_secs(meta.created);
+                    Some(SerEntry {
+                        path,
+                        is_dir: meta.is_dir,
+                        size: meta.size,
+                        modified_secs: modified.as_secs(),
+                        modified_nanos: modified.subsec_nanos(),
+                        created_secs: created.as_secs(),
+                        created_nanos: created.subsec_nanos(),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, create_dir_all, File};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use crate::ignore_rules::IgnoreRules;
+    use crate::tagging::entries::EntryOptions;
+    use crate::TestDir;
+
+    use super::RootIndex;
+
+    #[test]
+    fn indexing() {
+        let dir = TestDir::new("index");
+        for d in [ "src/bin", "node_modules/pkg" ] {
+            create_dir_all(dir.join(d)).unwrap();
+        }
+        for f in [ "src/main.rs", "src/bin/tool.rs", "node_modules/pkg/index.js" ] {
+            File::create(dir.join(f)).unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "hello").unwrap();
+
+        let paths = |index: &RootIndex, rules: &IgnoreRules| -> Vec<PathBuf> {
+            let mut v: Vec<PathBuf> = Arc::new(index.clone()).iter_paths(rules)
+                .map(|(pb, _)| pb.strip_prefix(&dir).unwrap().to_path_buf())
+                .collect();
+            v.sort();
+            v
+        };
+
+        let global = IgnoreRules {
+            patterns: vec![ "node_modules/".to_string() ],
+            respect_ignore_files: false,
+        };
+        let mut index = RootIndex::build(&dir, EntryOptions::default(), global.clone());
+        // Skipped by the rules it was built with
+        assert_eq!(index.len(), 6);
+        assert_eq!(index.get(&dir.join("node_modules")), None);
+        assert_eq!(index.get(&dir.join("notes.txt")).map(|m| m.size), Some(5));
+
+        let mut rules = global.clone();
+        rules.extend_patterns([ "*.txt".to_string() ]);
+        assert!(rules.extends(&global));
+        assert!(!IgnoreRules::none().extends(&global));
+        let mut reincluding = global.clone();
+        reincluding.extend_patterns([ "!node_modules/pkg/".to_string() ]);
+        assert!(!reincluding.extends(&global));
+
+        assert_eq!(paths(&index, &rules), vec![
+            PathBuf::from(""),
+            PathBuf::from("src"),
+            PathBuf::from("src/bin"),
+            PathBuf::from("src/bin/tool.rs"),
+            PathBuf::from("src/main.rs"),
+        ]);
+
+        assert!(!index.refresh());
+        assert!(!index.is_outdated());
+
+        fs::remove_dir_all(dir.join("src/bin")).unwrap();
+        create_dir_all(dir.join("docs")).unwrap();
+        File::create(dir.join("docs/readme.md")).unwrap();
+        assert!(index.refresh());
+        assert_eq!(paths(&index, &rules), vec![
+            PathBuf::from(""),
+            PathBuf::from("docs"),
+            PathBuf::from("docs/readme.md"),
+            PathBuf::from("src"),
+            PathBuf::from("src/main.rs"),
+        ]);
+        let built = RootIndex::build(&dir, EntryOptions::default(), global);
+        assert_eq!(index.entries, built.entries);
+    }
+}
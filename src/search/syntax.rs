@@ -0,0 +1,693 @@
+//! Tokenizer and parser for search queries
+//! See [`ConstraintList`] for the syntax
+
+use std::ops::Range;
+
+use crate::kind;
+use crate::strmatch::{CaseMode, Sublime};
+
+use super::constraint::{
+    build_regex, Content, ConstraintList, Date, Exact, Expr, Extension, Field, FileType,
+    Fuzzy, Glob, Kind, Note, ParseError, Pattern, Size, Sort, TagFilter,
+};
+
+
+/// Characters that end a word, unless escaped
+const WORD_BOUNDARIES: [char; 3] = ['|', '(', ')'];
+
+/// Flags that can follow a `/regex/`. See [`build_regex`]
+const REGEX_FLAGS: [char; 4] = ['i', 'm', 's', 'x'];
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    kind: TokenKind,
+    /// Byte range in the query
+    span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Word(Word),
+    /// `|`
+    Or,
+    /// `(`
+    Open,
+    /// `!(`
+    NotOpen,
+    /// `)`
+    Close,
+}
+
+/// A single search term, e.g. `.png`, `!"exact"` or `content:/regex/i`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct Word {
+    /// Text before the quoted or regex part, if any, with escapes resolved
+    text: String,
+    /// Whether the word started with an escaped character, e.g. `\-f`
+    /// Such words are always searched for as text
+    literal: bool,
+    section: Option<Section>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Section {
+    /// `"text"`, where the closing quote is optional
+    Quoted(String),
+    /// `/pattern/flags`
+    Regex {
+        pattern: String,
+        flags: String,
+    },
+}
+
+
+/// Parse `query` into a [`ConstraintList`]
+/// Parts that fail to parse are left out, and reported in [`ConstraintList::errors`]
+pub fn parse(query: &str) -> ConstraintList {
+    let mut parser = Parser {
+        query,
+        tokens: tokenize(query),
+        pos: 0,
+        errors: Vec::new(),
+        show_ignored: false,
+        sort: None,
+        case_mode: None,
+    };
+
+    let mut root = match parser.parse_or().0 {
+        Expr::And(list) => *list,
+        expr => ConstraintList {
+            groups: vec![expr],
+            ..Default::default()
+        },
+    };
+
+    // `parse_or` only stops early at unmatched closing parentheses
+    while let Some(token) = parser.next() {
+        parser.errors.push(ParseError::UnexpectedClose { span: token.span });
+
+        match parser.parse_or() {
+            (Expr::And(mut list), _) => {
+                root.tags.append(&mut list.tags);
+                if *list != ConstraintList::default() {
+                    root.groups.push(Expr::And(list));
+                }
+            },
+            (expr, _) => root.groups.push(expr),
+        }
+    }
+
+    root.show_ignored = parser.show_ignored;
+    root.sort = parser.sort;
+    if let Some(case) = parser.case_mode {
+        root.set_case_mode(case);
+        root.case_mode = Some(case);
+    }
+    root.errors = parser.errors;
+    root
+}
+
+
+struct Parser<'a> {
+    query: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    errors: Vec<ParseError>,
+    /// `--all` applies to the whole query, wherever it is
+    show_ignored: bool,
+    /// `sort:` also applies to the whole query. The last one wins
+    sort: Option<Sort>,
+    /// Same as `sort:`, for `case:`
+    case_mode: Option<CaseMode>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos)?.clone();
+        self.pos += 1;
+        Some(token)
+    }
+
+    /// Parse alternatives separated by `|`
+    /// Also returns the spans of the tags in the result, since they can only be used at the top
+    /// level of a query
+    fn parse_or(&mut self) -> (Expr, Vec<Range<usize>>) {
+        let mut alternatives = vec![self.parse_and()];
+        let mut or_spans: Vec<Range<usize>> = Vec::new();
+        while let Some(Token { kind: TokenKind::Or, span }) = self.peek() {
+            or_spans.push(span.clone());
+            self.pos += 1;
+            alternatives.push(self.parse_and());
+        }
+
+        // Report empty alternatives, e.g. `a | | b`
+        if alternatives.len() > 1 {
+            for (i, (list, _)) in alternatives.iter().enumerate() {
+                if *list == ConstraintList::default() {
+                    let span = or_spans[i.saturating_sub(1)].clone();
+                    self.errors.push(ParseError::ExpectedTerm { span });
+                }
+            }
+            alternatives.retain(|(list, _)| *list != ConstraintList::default());
+        }
+
+        if alternatives.len() <= 1 {
+            let (list, tag_spans) = alternatives.pop().unwrap_or_default();
+            return (Expr::And(Box::new(list)), tag_spans);
+        }
+
+        // `#a | #b` is the same as `#a|#b`
+        if alternatives.iter().all(|(list, _)| is_single_tag(list)) {
+            let (lists, tag_spans): (Vec<ConstraintList>, Vec<Vec<Range<usize>>>) = alternatives.into_iter().unzip();
+            let any_of = lists.into_iter()
+                .flat_map(|list| list.tags)
+                .flat_map(|filter| filter.any_of)
+                .collect();
+            let list = ConstraintList {
+                tags: vec![TagFilter { any_of, inverted: false }],
+                ..Default::default()
+            };
+            return (Expr::And(Box::new(list)), tag_spans.concat());
+        }
+
+        let alternatives = alternatives.into_iter()
+            .map(|(mut list, tag_spans)| {
+                self.report_nested_tags(&mut list, tag_spans);
+                Expr::And(Box::new(list))
+            })
+            .collect();
+        (Expr::Or(alternatives), Vec::new())
+    }
+
+    /// Parse terms and groups until the next `|` or `)`
+    fn parse_and(&mut self) -> (ConstraintList, Vec<Range<usize>>) {
+        let mut list = ConstraintList::default();
+        let mut tag_spans: Vec<Range<usize>> = Vec::new();
+
+        while let Some(token) = self.peek().cloned() {
+            match token.kind {
+                TokenKind::Or | TokenKind::Close => break,
+
+                TokenKind::Word(word) => {
+                    self.pos += 1;
+                    if self.push_word(&mut list, &word, token.span.clone()) {
+                        tag_spans.push(token.span);
+                    }
+                },
+
+                TokenKind::Open | TokenKind::NotOpen => {
+                    self.pos += 1;
+                    let (mut inner, inner_tag_spans) = self.parse_or();
+
+                    match self.peek() {
+                        Some(Token { kind: TokenKind::Close, .. }) => self.pos += 1,
+                        _ => self.errors.push(ParseError::UnclosedGroup { span: token.span.clone() }),
+                    }
+
+                    if let Expr::And(inner_list) = &mut inner {
+                        self.report_nested_tags(inner_list, inner_tag_spans);
+                        if **inner_list == ConstraintList::default() {
+                            self.errors.push(ParseError::ExpectedTerm { span: token.span });
+                            continue;
+                        }
+                    }
+
+                    list.groups.push(match token.kind {
+                        TokenKind::NotOpen => Expr::Not(Box::new(inner)),
+                        _ => inner,
+                    });
+                },
+            }
+        }
+
+        (list, tag_spans)
+    }
+
+    /// Tags decide which folders get searched through, so they can't be inside groups or
+    /// alternatives
+    fn report_nested_tags(&mut self, list: &mut ConstraintList, tag_spans: Vec<Range<usize>>) {
+        list.tags.clear();
+        self.errors.extend(tag_spans.into_iter().map(|span| ParseError::TagInGroup { span }));
+    }
+
+    /// Add the constraint that `word` describes to `list`
+    /// Returns whether it was a tag
+    fn push_word(&mut self, list: &mut ConstraintList, word: &Word, span: Range<usize>) -> bool {
+        let raw: &str = &self.query[span.clone()];
+        let (text, inverted) = match word.text.strip_prefix('!') {
+            Some(text) if !word.literal => (text, true),
+            _ => (word.text.as_str(), false),
+        };
+
+        match &word.section {
+            Some(Section::Quoted(query)) => match text {
+                "" => match Exact::parse(query, inverted) {
+                    Some(c) => list.exact.push(c),
+                    // E.g. `""`
+                    None => list.fuzzy.push(Fuzzy::parse(raw)),
+                },
+                "note:" => match Note::parse(query, inverted) {
+                    Some(c) => list.notes.push(c),
+                    None => list.fuzzy.push(Fuzzy::parse(raw)),
+                },
+                "content:" => match Content::text(query, inverted) {
+                    Some(c) => list.content.push(c),
+                    None => list.fuzzy.push(Fuzzy::parse(raw)),
+                },
+                "re:" => self.push_pattern(list, query, "", inverted, span),
+                // E.g. `name:"my file"`
+                _ => match (Field::parse_prefix(text), Exact::parse(query, inverted)) {
+                    (Some((field, "")), Some(c)) => list.exact.push(c.with_field(field)),
+                    _ => list.fuzzy.push(Fuzzy::parse(raw)),
+                },
+            },
+
+            Some(Section::Regex { pattern, flags }) => match text {
+                "" | "re:" => self.push_pattern(list, pattern, flags, inverted, span),
+                "content:" => match build_regex(pattern, flags, span) {
+                    Ok(regex) => list.content.push(Content::regex(regex, inverted)),
+                    Err(err) => self.errors.push(err),
+                },
+                _ => list.fuzzy.push(Fuzzy::parse(raw)),
+            },
+
+            None if word.literal => list.fuzzy.push(Fuzzy {
+                matcher: Sublime::default() .with_query(&word.text),
+                field: Field::Auto,
+                inverted: false,
+            }),
+
+            None => return self.push_plain(list, &word.text, raw, span),
+        }
+
+        false
+    }
+
+    /// Add the constraint that a word without quoted or regex parts describes to `list`
+    /// Returns whether it was a tag
+    fn push_plain(&mut self, list: &mut ConstraintList, arg: &str, raw: &str, span: Range<usize>) -> bool {
+        let (text, inverted) = arg.strip_prefix('!')
+            .map_or((arg, false), |s| (s, true));
+
+        // Show ignored flag
+        if arg == "--all" || arg == "-a" {
+            self.show_ignored = true;
+            return false;
+        }
+
+        // File type constraint
+        if let Some(c) = FileType::parse(arg) {
+            list.filetype = Some(c);
+            return false;
+        }
+
+        // Sort order
+        if let Some(sort) = Sort::parse(arg) {
+            self.sort = Some(sort);
+            return false;
+        }
+
+        // Case sensitivity
+        if let Some(case) = arg.strip_prefix("case:").and_then(CaseMode::parse) {
+            self.case_mode = Some(case);
+            return false;
+        }
+
+        // Other flags
+        if arg.starts_with('-') {
+            self.errors.push(ParseError::UnknownFlag { flag: arg.to_string(), span });
+            return false;
+        }
+
+        // Note constraint
+        if let Some(c) = text.strip_prefix("note:").and_then(|query| Note::parse(query, inverted)) {
+            list.notes.push(c);
+            return false;
+        }
+
+        // Content constraint
+        if let Some(c) = text.strip_prefix("content:").and_then(|query| Content::text(query, inverted)) {
+            list.content.push(c);
+            return false;
+        }
+
+        // Regex constraint
+        // Escapes mean something else in regexes, so the word is taken as is
+        let raw_pattern = raw.strip_prefix('!').unwrap_or(raw).strip_prefix("re:");
+        if let Some(pattern) = raw_pattern.filter(|pattern| !pattern.is_empty()) {
+            self.push_pattern(list, pattern, "", inverted, span);
+            return false;
+        }
+
+        // Tag constraint
+        if let Some(c) = TagFilter::parse(arg) {
+            list.tags.push(c);
+            return true;
+        }
+
+        // Kind constraint
+        if let Some(c) = Kind::parse(arg) {
+            if kind::table().contains_kind(&c.kind) {
+                list.kinds.push(c);
+            } else {
+                self.errors.push(ParseError::UnknownKind { kind: c.kind, span });
+            }
+            return false;
+        }
+
+        // Size constraint
+        if let Some(c) = Size::parse(arg) {
+            list.sizes.push(c);
+            return false;
+        }
+
+        // Date constraint
+        if let Some(c) = Date::parse(arg) {
+            list.dates.push(c);
+            return false;
+        }
+
+        // Glob constraint
+        // Must come before extensions, since `.*.swp` is a glob
+        if let Some(c) = Glob::parse(arg) {
+            list.globs.push(c);
+            return false;
+        }
+
+        // File extension constraint
+        if let Some(c) = Extension::parse(arg) {
+            list.extensions.push(c);
+            return false;
+        }
+
+        // Everything else -> Score constraint (aka fuzzy search)
+        list.fuzzy.push(Fuzzy::parse(arg));
+        false
+    }
+
+    fn push_pattern(&mut self, list: &mut ConstraintList, pattern: &str, flags: &str, inverted: bool, span: Range<usize>) {
+        match build_regex(pattern, flags, span) {
+            Ok(regex) => list.patterns.push(Pattern { regex, inverted }),
+            Err(err) => self.errors.push(err),
+        }
+    }
+}
+
+/// Whether `list` has nothing but one tag to search through
+fn is_single_tag(list: &ConstraintList) -> bool {
+    match list.tags.as_slice() {
+        [filter] if !filter.inverted => {
+            let mut rest = list.clone();
+            rest.tags.clear();
+            rest == ConstraintList::default()
+        },
+        _ => false,
+    }
+}
+
+
+/// Split `query` into [`Token`]s
+fn tokenize(query: &str) -> Vec<Token> {
+    let chars: Vec<(usize, char)> = query.char_indices().collect();
+    let byte_index = |i: usize| chars.get(i).map_or(query.len(), |(index, _)| *index);
+
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i: usize = 0;
+    while let Some(&(start, ch)) = chars.get(i) {
+        let (kind, next) = match ch {
+            ch if ch.is_whitespace() => {
+                i += 1;
+                continue;
+            },
+            '|' => (TokenKind::Or, i + 1),
+            '(' => (TokenKind::Open, i + 1),
+            ')' => (TokenKind::Close, i + 1),
+            '!' if matches!(chars.get(i + 1), Some((_, '('))) => (TokenKind::NotOpen, i + 2),
+            _ => {
+                let (word, next) = read_word(&chars, i);
+                (TokenKind::Word(word), next)
+            },
+        };
+
+        tokens.push(Token { kind, span: start..byte_index(next) });
+        i = next;
+    }
+
+    tokens
+}
+
+/// Read the word starting at `chars[i]`
+/// Returns it along with the index right after it
+fn read_word(chars: &[(usize, char)], mut i: usize) -> (Word, usize) {
+    let mut word = Word::default();
+
+    while let Some(&(_, ch)) = chars.get(i) {
+        // Quotes and regexes can come at the start, or after a prefix like `note:`
+        let can_start_section: bool = !word.literal && {
+            let text = word.text.strip_prefix('!').unwrap_or(&word.text);
+            text.is_empty() || text.ends_with(':')
+        };
+
+        // Bare `re:` patterns are read as they are up to the next space, like `/(regex)/`, so
+        // `|`, `( )` and escapes are part of the regex
+        let is_bare_regex: bool = !word.literal
+            && word.text.strip_prefix('!').unwrap_or(&word.text).starts_with("re:")
+            && !(can_start_section && matches!(ch, '"' | '/'));
+        if is_bare_regex {
+            if ch.is_whitespace() {
+                break;
+            }
+            word.text.push(ch);
+            i += 1;
+            continue;
+        }
+
+        match ch {
+            '\\' => match chars.get(i + 1) {
+                Some(&(_, escaped)) if !escaped.is_alphanumeric() => {
+                    word.literal |= word.text.is_empty();
+                    word.text.push(escaped);
+                    i += 2;
+                },
+                // Keep paths like `C:\Users` as they are
+                _ => {
+                    word.text.push('\\');
+                    i += 1;
+                },
+            },
+
+            '"' if can_start_section => {
+                let (query, next) = read_quoted(chars, i + 1);
+                word.section = Some(Section::Quoted(query));
+                return (word, next);
+            },
+
+            '/' if can_start_section => match read_regex(chars, i + 1) {
+                Some((section, next)) => {
+                    word.section = Some(section);
+                    return (word, next);
+                },
+                None => {
+                    word.text.push('/');
+                    i += 1;
+                },
+            },
+
+            // Tag lists like `#a|#b` are a single word
+            '|' if is_tag_list(&word.text) && matches!(chars.get(i + 1), Some((_, '#'))) => {
+                word.text.push('|');
+                i += 1;
+            },
+
+            ch if ch.is_whitespace() || WORD_BOUNDARIES.contains(&ch) => break,
+
+            ch => {
+                word.text.push(ch);
+                i += 1;
+            },
+        }
+    }
+
+    (word, i)
+}
+
+fn is_tag_list(text: &str) -> bool {
+    text.strip_prefix('!').unwrap_or(text).starts_with('#')
+}
+
+/// Read a quoted section, starting right after the opening quote at `chars[i - 1]`
+/// Without a closing quote, the rest of the query is included
+/// Returns the unescaped text, along with the index right after the closing quote
+fn read_quoted(chars: &[(usize, char)], mut i: usize) -> (String, usize) {
+    let mut text = String::new();
+
+    while let Some(&(_, ch)) = chars.get(i) {
+        match (ch, chars.get(i + 1).map(|(_, ch)| *ch)) {
+            ('"', _) => return (text, i + 1),
+            ('\\', Some(escaped @ ('"' | '\\'))) => {
+                text.push(escaped);
+                i += 2;
+            },
+            (ch, _) => {
+                text.push(ch);
+                i += 1;
+            },
+        }
+    }
+
+    (text, i)
+}
+
+/// Read a regex section, starting right after the opening slash at `chars[i - 1]`
+/// Returns `None` if this isn't a regex, e.g. in `/home/user`, where the closing slash isn't
+/// followed by flags and the end of the word
+/// Otherwise, returns the section and the index right after it
+fn read_regex(chars: &[(usize, char)], mut i: usize) -> Option<(Section, usize)> {
+    let mut pattern = String::new();
+
+    loop {
+        let &(_, ch) = chars.get(i)?;
+        match ch {
+            '/' => break,
+            // Escapes are part of the regex
+            '\\' => {
+                pattern.push('\\');
+                if let Some(&(_, escaped)) = chars.get(i + 1) {
+                    pattern.push(escaped);
+                }
+                i += 2;
+            },
+            ch => {
+                pattern.push(ch);
+                i += 1;
+            },
+        }
+    }
+
+    if pattern.is_empty() {
+        return None;
+    }
+
+    let mut flags = String::new();
+    i += 1;
+    while let Some(&(_, ch)) = chars.get(i).filter(|(_, ch)| REGEX_FLAGS.contains(ch)) {
+        flags.push(ch);
+        i += 1;
+    }
+
+    match chars.get(i) {
+        Some(&(_, ch)) if !ch.is_whitespace() && !WORD_BOUNDARIES.contains(&ch) => None,
+        _ => Some((Section::Regex { pattern, flags }, i)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, tokenize, Section, TokenKind};
+    use crate::search::constraint::{Expr, ParseError};
+
+    #[test]
+    fn tokenizing() {
+        let kinds: Vec<TokenKind> = tokenize(r#"a|(b) !(c \"d\" content:"e f" /g h/i "#)
+            .into_iter()
+            .map(|token| token.kind)
+            .collect();
+        assert!(matches!(kinds.as_slice(), [
+            TokenKind::Word(_), TokenKind::Or, TokenKind::Open, TokenKind::Word(_), TokenKind::Close,
+            TokenKind::NotOpen, TokenKind::Word(_), TokenKind::Word(_), TokenKind::Word(_), TokenKind::Word(_),
+        ]));
+
+        let TokenKind::Word(word) = &kinds[7] else { unreachable!() };
+        assert_eq!(word.text, "\"d\"");
+        assert!(word.literal && word.section.is_none());
+
+        let TokenKind::Word(word) = &kinds[8] else { unreachable!() };
+        assert_eq!(word.text, "content:");
+        assert_eq!(word.section, Some(Section::Quoted("e f".to_string())));
+
+        let TokenKind::Word(word) = &kinds[9] else { unreachable!() };
+        assert_eq!(word.section, Some(Section::Regex { pattern: "g h".to_string(), flags: "i".to_string() }));
+
+        // Not regexes
+        let tokens = tokenize("/home/user C:\\Users");
+        assert_eq!(tokens.len(), 2);
+        assert!(matches!(&tokens[0].kind, TokenKind::Word(w) if w.text == "/home/user"));
+        assert!(matches!(&tokens[1].kind, TokenKind::Word(w) if w.text == "C:\\Users"));
+        assert_eq!(tokens[1].span, 11..19);
+    }
+
+    #[test]
+    fn grouping() {
+        let c = parse("dino (.png | .jpg) !(art -d)");
+        assert_eq!(c.fuzzy.len(), 1);
+        assert_eq!(c.groups.len(), 2);
+        assert!(matches!(&c.groups[0], Expr::Or(alternatives) if alternatives.len() == 2));
+        assert!(matches!(&c.groups[1], Expr::Not(_)));
+        assert!(c.errors.is_empty());
+
+        // AND binds tighter than OR
+        let c = parse("a b | c");
+        assert!(c.fuzzy.is_empty());
+        assert!(matches!(&c.groups[..], [Expr::Or(alternatives)]
+            if matches!(&alternatives[0], Expr::And(list) if list.fuzzy.len() == 2)
+        ));
+
+        // Tags
+        let c = parse("#a | #b cat");
+        assert_eq!(c.errors, vec![
+            ParseError::TagInGroup { span: 0..2 },
+            ParseError::TagInGroup { span: 5..7 },
+        ]);
+        let c = parse("#a | #b");
+        assert_eq!(c.tags.len(), 1);
+        assert_eq!(c.tags[0].any_of.len(), 2);
+    }
+
+    #[test]
+    fn escaping() {
+        let c = parse(r#"\-f \"quote \#tag \(x\)"#);
+        assert!(c.errors.is_empty());
+        assert!(c.filetype.is_none() && c.exact.is_empty() && c.tags.is_empty() && c.groups.is_empty());
+        assert_eq!(c.fuzzy.len(), 4);
+
+        let c = parse(r#""say \"hi\"" "C:\Users"#);
+        assert_eq!(c.exact.len(), 2);
+        assert_eq!(c.exact[0].query, "say \"hi\"");
+        assert_eq!(c.exact[1].query, "C:\\Users");
+    }
+
+    #[test]
+    fn bare_regexes() {
+        // `|` and `( )` are part of bare `re:` patterns, which end at the next space
+        for (query, regex) in [ ("re:(a|b)c", "(a|b)c"), ("re:a|b", "a|b"), ("!re:\\.tmp$ x", "\\.tmp$") ] {
+            let c = parse(query);
+            assert!(c.errors.is_empty() && c.groups.is_empty(), "{query}");
+            assert_eq!(c.patterns.len(), 1, "{query}");
+            assert_eq!(c.patterns[0].regex.as_str(), regex, "{query}");
+        }
+        assert_eq!(parse("!re:\\.tmp$ x").fuzzy.len(), 1);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("a (b").errors, vec![ParseError::UnclosedGroup { span: 2..3 }]);
+        assert_eq!(parse("a) b").errors, vec![ParseError::UnexpectedClose { span: 1..2 }]);
+        assert_eq!(parse("a | | b").errors, vec![ParseError::ExpectedTerm { span: 2..3 }]);
+        assert_eq!(parse("a ()").errors, vec![ParseError::ExpectedTerm { span: 2..3 }]);
+        assert_eq!(parse("a --wot").errors, vec![
+            ParseError::UnknownFlag { flag: "--wot".to_string(), span: 2..7 },
+        ]);
+        assert!(matches!(&parse("x re:[").errors[..], [ParseError::InvalidRegex { span, .. }] if *span == (2..6)));
+
+        // The rest still parses
+        let c = parse("a) b");
+        assert_eq!(c.fuzzy.len(), 1);
+        assert_eq!(c.groups.len(), 1);
+    }
+}
@@ -1,16 +1,90 @@
 use std::collections::HashMap;
+use std::fmt::Display;
+
+use nanoserde::{DeJson, SerJson};
 
 
 fn is_char_sep(c: char) -> bool {
     !c.is_alphanumeric()
 }
 
+/// Whether `a` and `b` are the same char, ignoring case unless `case_sensitive`
+#[inline]
+pub fn chars_match(a: char, b: char, case_sensitive: bool) -> bool {
+    a == b || !case_sensitive && a.to_lowercase().eq(b.to_lowercase())
+}
+
+
+
+/// How a query decides whether letter case matters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, SerJson, DeJson)]
+pub enum CaseMode {
+    /// Case sensitive only if the query has uppercase letters
+    #[default]
+    #[nserde(rename = "smart")]
+    Smart,
+    #[nserde(rename = "sensitive")]
+    Sensitive,
+    #[nserde(rename = "insensitive")]
+    Insensitive,
+}
+
+impl CaseMode {
+    pub const ALL: [CaseMode; 3] = [CaseMode::Smart, CaseMode::Sensitive, CaseMode::Insensitive];
+
+    /// Parses `smart`, `sensitive` or `insensitive`
+    pub fn parse(str: &str) -> Option<CaseMode> {
+        match str {
+            "smart" => Some(CaseMode::Smart),
+            "sensitive" => Some(CaseMode::Sensitive),
+            "insensitive" => Some(CaseMode::Insensitive),
+            _ => None,
+        }
+    }
+
+    /// Whether matching `query` should be case sensitive
+    #[inline]
+    pub fn is_sensitive(self, query: &str) -> bool {
+        self.is_sensitive_chars(query.chars())
+    }
+
+    /// Same as [`CaseMode::is_sensitive`], for queries stored as chars
+    pub fn is_sensitive_chars<I: IntoIterator<Item = char>>(self, query: I) -> bool {
+        match self {
+            CaseMode::Smart => query.into_iter().any(char::is_uppercase),
+            CaseMode::Sensitive => true,
+            CaseMode::Insensitive => false,
+        }
+    }
+
+    /// Whether `target` contains `query`, following this mode
+    pub fn contains(self, target: &str, query: &str) -> bool {
+        if self.is_sensitive(query) {
+            target.contains(query)
+        } else {
+            target.to_lowercase() .contains(&query.to_lowercase())
+        }
+    }
+}
+
+impl Display for CaseMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CaseMode::Smart => "smart",
+            CaseMode::Sensitive => "sensitive",
+            CaseMode::Insensitive => "insensitive",
+        })
+    }
+}
+
 
 
 
 pub trait StringMatcher {
     fn set_query(&mut self, q: &str);
 
+    fn set_case(&mut self, case: CaseMode);
+
     fn score<S: AsRef<str>>(&self, target: &S) -> Option<isize>;
 
     fn matches<S: AsRef<str>>(&self, target: &S) -> bool {
@@ -26,7 +100,7 @@ pub trait StringMatcher {
 #[derive(Debug, Clone)]
 pub struct Contains {
     pub query: String,
-    case_insensitive: bool,
+    case: CaseMode,
 }
 
 impl Contains {
@@ -35,14 +109,18 @@ impl Contains {
     pub fn new(query: String) -> Self {
         Contains {
             query,
-            case_insensitive: false,
+            case: CaseMode::default(),
         }
     }
 
-    pub fn case_insensitive(mut self) -> Self {
-        self.case_insensitive = true;
+    pub fn with_case(mut self, case: CaseMode) -> Self {
+        self.case = case;
         self
     }
+
+    pub fn case_insensitive(self) -> Self {
+        self.with_case(CaseMode::Insensitive)
+    }
 }
 
 impl StringMatcher for Contains {
@@ -50,6 +128,10 @@ impl StringMatcher for Contains {
         self.query = q.to_string();
     }
 
+    fn set_case(&mut self, case: CaseMode) {
+        self.case = case;
+    }
+
     fn score<S: AsRef<str>>(&self, target: &S) -> Option<isize> {
         if self.matches(target) {
             return Some(Contains::DEFAULT_SCORE);
@@ -58,11 +140,7 @@ impl StringMatcher for Contains {
     }
 
     fn matches<S: AsRef<str>>(&self, target: &S) -> bool {
-        if self.case_insensitive {
-            target.as_ref().to_lowercase() .contains(&self.query.to_lowercase())
-        } else {
-            target.as_ref() .contains(&self.query)
-        }
+        self.case.contains(target.as_ref(), &self.query)
     }
 }
 
@@ -74,21 +152,25 @@ impl StringMatcher for Contains {
 #[derive(Debug, Clone)]
 pub struct Simple {
     pub query: String,
-    case_insensitive: bool,
+    case: CaseMode,
 }
 
 impl Simple {
     pub fn new(query: String) -> Self {
         Simple {
             query,
-            case_insensitive: false,
+            case: CaseMode::default(),
         }
     }
 
-    pub fn case_insensitive(mut self) -> Self {
-        self.case_insensitive = true;
+    pub fn with_case(mut self, case: CaseMode) -> Self {
+        self.case = case;
         self
     }
+
+    pub fn case_insensitive(self) -> Self {
+        self.with_case(CaseMode::Insensitive)
+    }
 }
 
 impl StringMatcher for Simple {
@@ -96,47 +178,27 @@ impl StringMatcher for Simple {
         self.query = q.to_string();
     }
 
+    fn set_case(&mut self, case: CaseMode) {
+        self.case = case;
+    }
+
     fn score<S: AsRef<str>>(&self, target: &S) -> Option<isize> {
+        let case_sensitive: bool = self.case.is_sensitive(&self.query);
         let mut tchars = target.as_ref().chars();
 
-        if self.case_insensitive {
-            let mut score: isize = 0;
-            for qch in self.query.chars() {
-                let qch = qch.to_lowercase().to_string();
-                let p = tchars.position(|tch| tch.to_lowercase().to_string() == qch)?;
-                score -= p as isize;
-            }
-            return Some(score);
-        }
-
         let mut score: isize = 0;
         for qch in self.query.chars() {
-            let p = tchars.position(|tch| tch == qch)?;
+            let p = tchars.position(|tch| chars_match(qch, tch, case_sensitive))?;
             score -= p as isize;
         }
         Some(score)
     }
 
     fn matches<S: AsRef<str>>(&self, target: &S) -> bool {
+        let case_sensitive: bool = self.case.is_sensitive(&self.query);
         let mut tchars = target.as_ref().chars();
-
-        // bruh
-        if self.case_insensitive {
-            for qch in self.query.chars() {
-                let qch = qch.to_lowercase().to_string();
-                if !tchars.any(|tch| tch.to_lowercase().to_string() == qch) {
-                    return false;
-                }
-            }
-            return true;
-        }
-
-        for qch in self.query.chars() {
-            if !tchars.any(|tch| tch == qch) {
-                return false;
-            }
-        }
-        true
+        self.query.chars()
+            .all(|qch| tchars.any(|tch| chars_match(qch, tch, case_sensitive)))
     }
 }
 
@@ -156,6 +218,7 @@ pub struct Sublime {
     pub bonus_match_case: isize,
     pub penalty_distance: isize,
     query: Vec<char>,
+    case: CaseMode,
 }
 
 impl Sublime {
@@ -170,6 +233,7 @@ impl Sublime {
             bonus_match_case:8,
             penalty_distance: 8,
             query: Vec::new(),
+            case: CaseMode::default(),
         }
     }
 
@@ -178,11 +242,32 @@ impl Sublime {
         self
     }
 
+    pub fn with_case(mut self, case: CaseMode) -> Self {
+        self.case = case;
+        self
+    }
+
+    #[inline]
+    pub fn get_query(&self) -> &[char] {
+        &self.query
+    }
+
+    #[inline]
+    pub fn get_case(&self) -> CaseMode {
+        self.case
+    }
+
+    /// Whether the query is matched case sensitively, see [`CaseMode::is_sensitive`]
+    pub fn is_case_sensitive(&self) -> bool {
+        self.case.is_sensitive_chars(self.query.iter().copied())
+    }
+
     fn score_recursive(
         &self,
         query_index: usize,
         target: &[char],
         target_index: usize,
+        case_sensitive: bool,
         cache: &mut HashMap<(usize, usize), Option<(isize, usize)>>
     ) -> Option<Match>
     {
@@ -195,11 +280,9 @@ impl Sublime {
             });
         };
 
-        let qch_lower = qch.to_lowercase().to_string();
-
         // Get all occurences of qch
         let occurences = target[target_index..].iter().enumerate()
-            .filter(|(_, ch)| ch.to_lowercase().to_string() == qch_lower)
+            .filter(|(_, ch)| chars_match(*qch, **ch, case_sensitive))
             .map(|(i, ch)| (i + target_index, ch));
 
         let mut best: Option<Match> = None;
@@ -237,7 +320,7 @@ impl Sublime {
                 index: next_i,
                 score: next_score,
                 consecutive_count: next_consecutive
-            }) = self.score_recursive(query_index + 1,target,i + 1,cache) else {
+            }) = self.score_recursive(query_index + 1, target, i + 1, case_sensitive, cache) else {
                 // No match found in this branch
                 continue;
             };
@@ -294,6 +377,10 @@ impl StringMatcher for Sublime {
         self.query = q.chars().collect();
     }
 
+    fn set_case(&mut self, case: CaseMode) {
+        self.case = case;
+    }
+
     fn score<S: AsRef<str>>(&self, target: &S) -> Option<isize> {
         if self.query.is_empty() {
             return Some(0);
@@ -301,16 +388,16 @@ impl StringMatcher for Sublime {
 
         let mut cache = HashMap::new();
         let t: Vec<char> = target.as_ref().chars().collect();
-        self.score_recursive(0, &t, 0, &mut cache)
+        self.score_recursive(0, &t, 0, self.is_case_sensitive(), &mut cache)
             .map(|Match { score, .. }| score)
     }
 
     // Just check if chars in query appear sequentially within target
     fn matches<S: AsRef<str>>(&self, target: &S) -> bool {
+        let case_sensitive: bool = self.is_case_sensitive();
         let mut tchars = target.as_ref().chars();
         for qch in self.query.iter() {
-            let qch = qch.to_lowercase().to_string();
-            if !tchars.any(|tch| tch.to_lowercase().to_string() == qch) {
+            if !tchars.any(|tch| chars_match(*qch, tch, case_sensitive)) {
                 return false;
             }
         }
@@ -327,6 +414,7 @@ impl Default for Sublime {
             bonus_match_case: 8,
             penalty_distance: 4,
             query: Vec::new(),
+            case: CaseMode::default(),
         }
     }
 }
@@ -404,5 +492,36 @@ mod tests {
         }
 
     }
+
+    #[test]
+    fn case() {
+        assert!(!CaseMode::Smart.is_sensitive("readme"));
+        assert!(CaseMode::Smart.is_sensitive("ReadMe"));
+        assert!(CaseMode::Sensitive.is_sensitive("readme"));
+        assert!(!CaseMode::Insensitive.is_sensitive("README"));
+
+        fn count_matches<M: StringMatcher>(matcher: &M) -> (usize, usize) {
+            let targets = ["README.md", "readme.md"];
+            (
+                targets.iter().filter(|t| matcher.matches(t)).count(),
+                targets.iter().filter(|t| matcher.score(t).is_some()).count(),
+            )
+        }
+
+        for (query, case, expected) in [
+            ("readme", CaseMode::Smart, 2),
+            ("README", CaseMode::Smart, 1),
+            ("readme", CaseMode::Sensitive, 1),
+            ("README", CaseMode::Insensitive, 2),
+        ] {
+            let contains = Contains::new(query.to_string()).with_case(case);
+            let simple = Simple::new(query.to_string()).with_case(case);
+            let sublime = Sublime::default().with_query(query).with_case(case);
+
+            assert_eq!(count_matches(&contains), (expected, expected), "{query} {case}");
+            assert_eq!(count_matches(&simple), (expected, expected), "{query} {case}");
+            assert_eq!(count_matches(&sublime), (expected, expected), "{query} {case}");
+        }
+    }
 }
 
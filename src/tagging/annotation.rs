@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use nanoserde::{DeJson, SerJson};
+
+use super::Tag;
+
+
+/// A short note and optional rating attached to a path under a [`Tag`]
+/// E.g. `"final version sent to client"` on a pdf file
+#[derive(Debug, Clone, Default, PartialEq, Eq, SerJson, DeJson)]
+pub struct Annotation {
+    pub note: String,
+    /// From 1 to [`Annotation::MAX_RATING`]
+    pub rating: Option<u8>,
+}
+
+impl Annotation {
+    pub const MAX_RATING: u8 = 5;
+
+    pub fn new(note: &str) -> Self {
+        Annotation {
+            note: note.to_string(),
+            rating: None,
+        }
+    }
+
+    pub fn with_rating(mut self, rating: Option<u8>) -> Self {
+        self.set_rating(rating);
+        self
+    }
+
+    /// Sets the rating, clamping it between 1 and [`Annotation::MAX_RATING`]
+    /// `Some(0)` is the same as `None`
+    pub fn set_rating(&mut self, rating: Option<u8>) {
+        self.rating = rating
+            .filter(|r| *r > 0)
+            .map(|r| r.min(Annotation::MAX_RATING));
+    }
+
+    /// Returns whether this annotation has neither a note nor a rating
+    pub fn is_empty(&self) -> bool {
+        self.note.trim().is_empty() && self.rating.is_none()
+    }
+}
+
+
+
+/// All annotations from multiple tags, for looking up while searching
+/// A path may be annotated in more than one tag, hence the [`Vec`]
+#[derive(Debug, Clone, Default)]
+pub struct AnnotationIndex(HashMap<PathBuf, Vec<Annotation>>);
+
+impl AnnotationIndex {
+    /// Collect the annotations of all `tags`, INCLUDING their subtags
+    pub fn from_tags(tags: &[Tag]) -> Self {
+        let mut index = AnnotationIndex::default();
+
+        for tag in tags.iter() {
+            index.extend_from(tag);
+            for subtag in tag.iter_all_subtags() {
+                index.extend_from(&subtag);
+            }
+        }
+
+        index
+    }
+
+    fn extend_from(&mut self, tag: &Tag) {
+        for (path, annotation) in tag.iter_annotations() {
+            let v = self.0.entry(path.clone()).or_default();
+            if !v.contains(annotation) {
+                v.push(annotation.clone());
+            }
+        }
+    }
+
+    /// Get all annotations that apply to `path`
+    /// Annotations on a folder also apply to everything inside it
+    pub fn get(&self, path: &Path) -> Vec<&Annotation> {
+        if self.0.is_empty() {
+            return Vec::new();
+        }
+
+        path.ancestors()
+            .filter_map(|p| self.0.get(p))
+            .flatten()
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hasher};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+use crate::error;
+use crate::ignore_rules::IgnoreRules;
+use crate::search::{self, CancelToken};
+
+use super::entries::Entries;
+use super::tag::SaveError;
+use super::Tag;
+
+
+/// How many bytes at the start of each file are hashed, before hashing whole files
+const PARTIAL_HASH_LEN: u64 = 16 * 1024;
+
+/// How many files each hashing thread takes at a time
+const HASH_BATCH_SIZE: usize = 16;
+
+
+
+/// Files with identical contents
+/// See [`find_duplicates`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    /// Size of each file, in bytes
+    pub size: u64,
+    pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+    /// Bytes that would be freed by keeping only one copy
+    pub fn wasted_size(&self) -> u64 {
+        self.size * (self.paths.len() as u64).saturating_sub(1)
+    }
+}
+
+
+
+/// List every file under `sources` once, skipping ignored paths
+/// See [`search::iter_entries`]
+pub fn list_files(sources: Vec<(Entries, IgnoreRules)>) -> Vec<PathBuf> {
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    sources.into_iter()
+        .flat_map(|(entries, rules)| search::iter_entries(entries, &rules))
+        .filter(|path| path.is_file() && seen.insert(path.clone()))
+        .collect()
+}
+
+/// Group `paths` by identical contents
+/// Files are compared by size first, then by a hash of their first [`PARTIAL_HASH_LEN`]
+/// bytes, and only then by a hash of their whole contents, so most files are never fully read
+/// Empty files and files that can't be read are left out
+/// Groups are sorted by [`DuplicateGroup::wasted_size`], biggest first
+/// Returns an empty list if `token` gets cancelled
+pub fn find_duplicates(paths: Vec<PathBuf>, thread_count: usize, token: &CancelToken) -> Vec<DuplicateGroup> {
+    // 1. Size
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for path in paths {
+        let Ok(meta) = fs::metadata(&path) else {
+            continue;
+        };
+        if meta.is_file() && meta.len() > 0 {
+            by_size.entry(meta.len()).or_default().push(path);
+        }
+    }
+    let candidates = into_candidates(by_size);
+
+    // 2. Partial hash
+    let by_partial_hash = hash_grouped(candidates, Some(PARTIAL_HASH_LEN), thread_count, token);
+
+    // 3. Full hash
+    // Small files were already hashed whole
+    let (small, big): (HashMap<_, _>, HashMap<_, _>) = by_partial_hash.into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .partition(|((size, _), _)| *size <= PARTIAL_HASH_LEN);
+    let candidates = into_candidates(big.into_iter()
+        .map(|((size, _), paths)| (size, paths))
+    );
+    let mut by_hash = hash_grouped(candidates, None, thread_count, token);
+    by_hash.extend(small);
+
+    if token.is_cancelled() {
+        return Vec::new();
+    }
+
+    let mut groups: Vec<DuplicateGroup> = by_hash.into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|((size, _), mut paths)| {
+            paths.sort();
+            DuplicateGroup { size, paths }
+        })
+        .collect();
+    groups.sort_by(|a, b| b.wasted_size().cmp(&a.wasted_size())
+        .then_with(|| a.paths.cmp(&b.paths))
+    );
+    groups
+}
+
+/// Flatten groups of files of the same size into `(size, path)` pairs, leaving out files that
+/// have no possible duplicates
+fn into_candidates<I>(groups: I) -> Vec<(u64, PathBuf)>
+where
+    I: IntoIterator<Item = (u64, Vec<PathBuf>)>,
+{
+    groups.into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .flat_map(|(size, paths)| paths.into_iter().map(move |path| (size, path)))
+        .collect()
+}
+
+/// Hash the first `len` bytes (or all bytes if `None`) of each file on `thread_count` threads,
+/// and group them by `(size, hash)`
+fn hash_grouped(
+    files: Vec<(u64, PathBuf)>,
+    len: Option<u64>,
+    thread_count: usize,
+    token: &CancelToken,
+) -> HashMap<(u64, u64), Vec<PathBuf>> {
+    let files = Mutex::new(files.into_iter());
+    let groups: Mutex<HashMap<(u64, u64), Vec<PathBuf>>> = Mutex::new(HashMap::new());
+
+    thread::scope(|s| {
+        for _ in 0..thread_count.max(1) {
+            s.spawn(|| loop {
+                if token.is_cancelled() {
+                    return;
+                }
+                let batch: Vec<(u64, PathBuf)> = match files.lock() {
+                    Ok(mut files) => files.by_ref().take(HASH_BATCH_SIZE).collect(),
+                    Err(_) => return,
+                };
+                if batch.is_empty() {
+                    return;
+                }
+
+                let hashed: Vec<((u64, u64), PathBuf)> = batch.into_iter()
+                    .filter_map(|(size, path)| match hash_file(&path, len) {
+                        Ok(hash) => Some(((size, hash), path)),
+                        Err(err) => {
+                            error!("[duplicates::hash_grouped()] Failed to read \"{}\":\n {}", path.display(), err);
+                            None
+                        }
+                    })
+                    .collect();
+                let Ok(mut groups) = groups.lock() else {
+                    return;
+                };
+                for (key, path) in hashed {
+                    groups.entry(key).or_default().push(path);
+                }
+            });
+        }
+    });
+
+    groups.into_inner().unwrap_or_default()
+}
+
+fn hash_file(path: &Path, len: Option<u64>) -> io::Result<u64> {
+    let file = File::open(path)?;
+    let mut reader: Box<dyn Read> = match len {
+        Some(len) => Box::new(file.take(len)),
+        None => Box::new(file),
+    };
+
+    let mut hasher = DefaultHasher::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buf[..read]);
+    }
+    Ok(hasher.finish())
+}
+
+
+
+/// Remove `path` from every tag in `tags` that contains it, EXCLUDING subtags
+/// Paths that are only tagged through a folder get their full path added to that tag's ignore
+/// patterns instead, which skips only them. See [`IgnoreRules::build_matcher`]
+/// Returns the indexes of the tags that were changed, which still need to be saved
+pub fn untag_path(path: &Path, tags: &mut [Tag]) -> Vec<usize> {
+    let mut changed: Vec<usize> = Vec::new();
+    for (i, tag) in tags.iter_mut().enumerate() {
+        if !tag.contains(path) {
+            continue;
+        }
+
+        if !tag.remove_entry(&path) {
+            if !tag.entries.as_ref().iter().any(|dir| path.starts_with(dir)) {
+                continue;
+            }
+            let pattern = path.to_string_lossy().to_string();
+            if !tag.ignore_patterns.contains(&pattern) {
+                tag.ignore_patterns.push(pattern);
+            }
+        }
+        changed.push(i);
+    }
+    changed
+}
+
+/// Untag every path in `paths` (see [`untag_path`]) and save the changed tags
+/// Returns how many tags were changed
+pub fn untag_paths(paths: &[PathBuf], tags: &mut [Tag]) -> Result<usize, SaveError> {
+    let changed: HashSet<usize> = paths.iter()
+        .flat_map(|path| untag_path(path, tags))
+        .collect();
+
+    for &i in changed.iter() {
+        tags[i].save()?;
+    }
+    Ok(changed.len())
+}
+
+/// Remove `path` from the entries of every tag in `tags`, e.g. once it got moved to the trash,
+/// and save the changed tags
+/// Tags that only contain it through a folder are left as is, since it's gone from there anyway
+/// Returns how many tags were changed
+pub fn remove_entry_from_all(path: &Path, tags: &mut [Tag]) -> Result<usize, SaveError> {
+    let mut changed_count: usize = 0;
+    for tag in tags.iter_mut() {
+        if tag.remove_entry(&path) {
+            tag.save()?;
+            changed_count += 1;
+        }
+    }
+    Ok(changed_count)
+}
+
+
+
+/// Looks for duplicates on a thread
+/// See [`find_duplicates`]
+#[derive(Debug, Default)]
+pub struct DuplicateFinder {
+    handle: Option<JoinHandle<Vec<DuplicateGroup>>>,
+    cancel_token: CancelToken,
+}
+
+impl DuplicateFinder {
+    pub fn new() -> Self {
+        DuplicateFinder::default()
+    }
+
+    /// Start looking for duplicates among the files under `tags`, INCLUDING subtags
+    /// This cancels the previous search, if any
+    pub fn find(&mut self, tags: &[Tag], thread_count: usize) {
+        self.cancel();
+        self.cancel_token = CancelToken::new();
+        let token = self.cancel_token.clone();
+
+        let sources: Vec<(Entries, IgnoreRules)> = tags.iter()
+            .map(|tag| (tag.get_all_entries(), IgnoreRules::for_tag(tag)))
+            .collect();
+        self.handle = Some(thread::spawn(move ||
+            find_duplicates(list_files(sources), thread_count, &token)
+        ));
+    }
+
+    /// Stop the current search, if any
+    /// This never blocks: the thread is left to finish on its own
+    pub fn cancel(&mut self) {
+        self.cancel_token.cancel();
+        self.handle = None;
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// Returns the results if the search just finished
+    #[must_use]
+    pub fn update(&mut self) -> Option<Vec<DuplicateGroup>> {
+        if !self.handle.as_ref()?.is_finished() {
+            return None;
+        }
+
+        match self.handle.take()?.join() {
+            Ok(groups) => Some(groups),
+            Err(err) => {
+                error!("[DuplicateFinder::update()] Duplicate search thread panicked:\n {:?}", err);
+                Some(Vec::new())
+            }
+        }
+    }
+}
+
+impl Drop for DuplicateFinder {
+    fn drop(&mut self) {
+        self.cancel_token.cancel();
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, create_dir_all};
+    use std::path::{Path, PathBuf};
+
+    use crate::search::CancelToken;
+    use crate::tagging::{entries::Entries, Tag};
+    use crate::TestDir;
+
+    use crate::ignore_rules::IgnoreRules;
+    use crate::search;
+
+    use super::{find_duplicates, untag_path, DuplicateGroup, PARTIAL_HASH_LEN};
+
+    #[test]
+    fn finding() {
+        let dir = TestDir::new("duplicates");
+        create_dir_all(dir.join("sub")).unwrap();
+
+        // Same start, different end
+        let mut big = vec![7u8; PARTIAL_HASH_LEN as usize + 10];
+        fs::write(dir.join("big1.bin"), &big).unwrap();
+        fs::write(dir.join("sub/big2.bin"), &big).unwrap();
+        *big.last_mut().unwrap() = 8;
+        fs::write(dir.join("big3.bin"), &big).unwrap();
+
+        fs::write(dir.join("a.txt"), "hello").unwrap();
+        fs::write(dir.join("b.txt"), "hello").unwrap();
+        fs::write(dir.join("c.txt"), "world").unwrap();
+        fs::write(dir.join("empty1"), "").unwrap();
+        fs::write(dir.join("empty2"), "").unwrap();
+
+        let paths: Vec<PathBuf> = ["big1.bin", "sub/big2.bin", "big3.bin", "a.txt", "b.txt", "c.txt", "empty1", "empty2"]
+            .iter()
+            .map(|p| dir.join(p))
+            .collect();
+        let groups = find_duplicates(paths.clone(), 2, &CancelToken::new());
+        assert_eq!(groups, vec![
+            DuplicateGroup { size: big.len() as u64, paths: vec![ dir.join("big1.bin"), dir.join("sub/big2.bin") ] },
+            DuplicateGroup { size: 5, paths: vec![ dir.join("a.txt"), dir.join("b.txt") ] },
+        ]);
+        assert_eq!(groups[0].wasted_size(), big.len() as u64);
+
+        let token = CancelToken::new();
+        token.cancel();
+        assert!(find_duplicates(paths, 2, &token).is_empty());
+    }
+
+    #[test]
+    fn untagging() {
+        let mut tags = vec![
+            Tag::create("pictures").with_entries(Entries::from(vec![
+                PathBuf::from("C:/Pictures/"),
+                PathBuf::from("C:/Downloads/cat.png"),
+            ])),
+            Tag::create("memes").with_entries(Entries::from(vec![
+                PathBuf::from("C:/Downloads/"),
+            ])),
+            Tag::create("music").with_entries(Entries::from(vec![
+                PathBuf::from("C:/Music/"),
+            ])),
+        ];
+
+        let changed = untag_path(Path::new("C:/Downloads/cat.png"), &mut tags);
+        assert_eq!(changed, vec![0, 1]);
+        assert!(!tags[0].entries.as_ref().contains(&PathBuf::from("C:/Downloads/cat.png")));
+        assert_eq!(tags[1].ignore_patterns, vec![ "C:/Downloads/cat.png".to_string() ]);
+
+        assert!(untag_path(Path::new("C:/Videos/cat.mp4"), &mut tags).is_empty());
+    }
+
+    #[test]
+    fn untagging_shared_relative_path() {
+        let dir = TestDir::new("untag-shared");
+        for d in [ "Pictures", "Downloads" ] {
+            create_dir_all(dir.join(d)).unwrap();
+            fs::write(dir.join(d).join("cat.png"), "meow").unwrap();
+        }
+
+        let mut tags = vec![
+            Tag::create("pictures").with_entries(Entries::from(vec![
+                dir.join("Pictures"),
+                dir.join("Downloads"),
+            ])),
+        ];
+        assert_eq!(untag_path(&dir.join("Downloads/cat.png"), &mut tags), vec![0]);
+
+        let rules = IgnoreRules::none().with_tag_patterns(&tags[0]);
+        let files: Vec<PathBuf> = search::iter_entries(tags[0].entries.clone(), &rules)
+            .filter(|pb| pb.is_file())
+            .collect();
+        assert_eq!(files, vec![ dir.join("Pictures/cat.png") ]);
+    }
+}
@@ -1,7 +1,8 @@
 use std::collections::{HashMap, HashSet};
-use std::ops::{Deref, DerefMut};
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
 
+use crate::ignore_rules::IgnoreRules;
 use crate::{search, ToPrettyString};
 
 
@@ -11,11 +12,107 @@ pub struct NonexistentPath;
 
 
 
+/// Options on how to walk through a folder entry
+/// See [`Entries::set_options`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct EntryOptions {
+    /// Whether to follow symbolic links
+    /// Links that point back to one of their parents are skipped to avoid infinite loops
+    pub follow_links: bool,
+    /// How many levels deep to go inside the folder
+    /// `Some(0)` only includes the folder itself, `Some(1)` its direct children, and `None`
+    /// means no limit
+    pub max_depth: Option<usize>,
+}
+
+impl EntryOptions {
+    /// Options to only include the direct children of a folder
+    pub fn direct_children_only() -> Self {
+        EntryOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_follow_links(mut self, follow_links: bool) -> Self {
+        self.follow_links = follow_links;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    #[inline]
+    pub fn is_default(&self) -> bool {
+        *self == EntryOptions::default()
+    }
+
+    /// Options as seen from a path `offset` levels deeper
+    /// Returns `None` if that path is out of reach
+    fn narrowed(self, offset: usize) -> Option<Self> {
+        let max_depth = match self.max_depth {
+            Some(d) if offset > d => return None,
+            Some(d) => Some(d - offset),
+            None => None,
+        };
+        Some(self.with_max_depth(max_depth))
+    }
+
+    /// Options that reach only what both `self` and `other` reach
+    fn intersect(self, other: Self) -> Self {
+        EntryOptions {
+            follow_links: self.follow_links && other.follow_links,
+            max_depth: match (self.max_depth, other.max_depth) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+
+    /// Options that reach everything `self` or `other` reach
+    fn union(self, other: Self) -> Self {
+        EntryOptions {
+            follow_links: self.follow_links || other.follow_links,
+            max_depth: self.max_depth.zip(other.max_depth)
+                .map(|(a, b)| a.max(b)),
+        }
+    }
+
+    /// Returns whether these options reach at least everything `other` does
+    fn reaches(&self, other: &Self) -> bool {
+        let depth_ok = match (self.max_depth, other.max_depth) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(a), Some(b)) => a >= b,
+        };
+        depth_ok && (self.follow_links || !other.follow_links)
+    }
+}
+
+
+/// Get how many levels deeper `path` is from `ancestor`, if it is inside it
+fn depth_from(ancestor: &Path, path: &Path) -> Option<usize> {
+    path.strip_prefix(ancestor).ok()
+        .map(|p| p.components().count())
+}
+
+/// Returns whether entry `a` covers everything entry `b` does
+fn covers(a: (&Path, EntryOptions), b: (&Path, EntryOptions)) -> bool {
+    depth_from(a.0, b.0)
+        .and_then(|offset| a.1.narrowed(offset))
+        .is_some_and(|a| a.reaches(&b.1))
+}
+
+
+
 /// List of paths which a [`Tag`] contains
 /// All contained paths are guaranteed to exist
 /// Duplicate entries are not allowed
-#[derive(Debug, Clone, Default)]
-pub struct Entries(pub(super) Vec<PathBuf>);
+/// Each entry can have [`EntryOptions`]. Those with default options are not stored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Entries(pub(super) Vec<PathBuf>, pub(super) HashMap<PathBuf, EntryOptions>);
 
 impl Entries {
     #[inline]
@@ -40,19 +137,140 @@ impl Entries {
         Ok(true)
     }
 
-    /// Returns whether the given `path` is contained in this [`Entries`] list
+    /// Adds an entry with the given [`EntryOptions`]
+    /// If the entry is already contained, its options get replaced
+    /// See [`Entries::push`]
+    pub fn push_with_options(&mut self, path: PathBuf, options: EntryOptions) -> Result<bool, NonexistentPath> {
+        let added = self.push(path.clone())?;
+        self.set_options(&path, options);
+        Ok(added)
+    }
+
+    /// Remove the entry `path`, along with its [`EntryOptions`]
+    /// Returns whether it was an entry
+    pub fn remove(&mut self, path: &Path) -> bool {
+        let len = self.0.len();
+        self.0.retain(|pb| pb != path);
+        self.1.remove(path);
+        self.0.len() != len
+    }
+
+    /// Keep only the entries for which `f` returns `true`, dropping the options of the others
+    pub fn retain<F>(&mut self, mut f: F)
+    where F: FnMut(&PathBuf) -> bool,
+    {
+        self.0.retain(|pb| f(pb));
+        let Entries(paths, options) = self;
+        options.retain(|pb, _| paths.contains(pb));
+    }
+
+    /// Get the [`EntryOptions`] of the entry `path`
+    /// Returns the default options if `path` has none or isn't an entry
+    pub fn get_options(&self, path: &Path) -> EntryOptions {
+        self.1.get(path).copied().unwrap_or_default()
+    }
+
+    /// Set the [`EntryOptions`] of the entry `path`
+    /// Returns whether `path` is an entry in this list. If not, nothing happens
+    pub fn set_options(&mut self, path: &Path, options: EntryOptions) -> bool {
+        if !self.0.iter().any(|pb| pb == path) {
+            return false;
+        }
+
+        if options.is_default() {
+            self.1.remove(path);
+        } else {
+            self.1.insert(path.to_path_buf(), options);
+        }
+        true
+    }
+
+    /// Iterates over the paths defining this [`Entries`], along with their [`EntryOptions`]
+    pub fn iter_with_options(&self) -> impl Iterator<Item = (&PathBuf, EntryOptions)> {
+        self.0.iter()
+            .map(|pb| (pb, self.get_options(pb)))
+    }
+
+    /// Same as [`Entries::iter_with_options`], but consumes `self`
+    pub fn into_iter_with_options(self) -> impl Iterator<Item = (PathBuf, EntryOptions)> {
+        let Entries(paths, options) = self;
+        paths.into_iter()
+            .map(move |pb| {
+                let o = options.get(&pb).copied().unwrap_or_default();
+                (pb, o)
+            })
+    }
+
+    /// Append all entries in `other`
+    /// If an entry is in both, the options that reach the furthest are kept
+    pub fn extend_entries(&mut self, other: Entries) {
+        for (path, options) in other.into_iter_with_options() {
+            let options = match self.1.get(&path) {
+                Some(o) => o.union(options),
+                None if self.0.contains(&path) => EntryOptions::default().union(options),
+                None => options,
+            };
+            if !options.is_default() {
+                self.1.insert(path.clone(), options);
+            }
+            self.0.push(path);
+        }
+    }
+
+    /// Copy over the options of entries in `other` that are also in `self`, but have none
+    /// Useful when rebuilding an [`Entries`] from paths only, e.g. [`Entries::from_string_list`]
+    pub fn inherit_options(&mut self, other: &Entries) {
+        for (pb, options) in other.1.iter() {
+            if self.0.contains(pb) && !self.1.contains_key(pb) {
+                self.1.insert(pb.clone(), *options);
+            }
+        }
+    }
+
+    /// Returns whether the given `path` is contained in this [`Entries`] list, taking
+    /// [`EntryOptions::max_depth`] into account
     /// To get whether a path is an entry in this list, please use `entries.as_ref().contains()`
     pub fn contains(&self, path: &Path) -> bool {
-        self.0.iter().any(|p| path.starts_with(p))
+        self.iter_with_options()
+            .any(|(p, options)| depth_from(p, path)
+                .is_some_and(|d| options.max_depth.is_none_or(|max| d <= max))
+            )
     }
 
-    /// Iterates through all the paths contained
+    /// Returns whether any entry in this list covers everything `path` with `options` does
+    fn covers(&self, path: &Path, options: EntryOptions) -> bool {
+        self.iter_with_options()
+            .any(|(p, o)| covers((p, o), (path, options)))
+    }
+
+    /// Add `path` unless it is already covered, removing any entries it covers
+    fn push_covering(&mut self, path: PathBuf, options: EntryOptions) {
+        if self.covers(&path, options) {
+            return;
+        }
+
+        let covered: Vec<PathBuf> = self.iter_with_options()
+            .filter(|(p, o)| covers((&path, options), (p, *o)))
+            .map(|(p, _)| p.clone())
+            .collect();
+        self.0.retain(|pb| !covered.contains(pb));
+        for pb in covered.iter() {
+            self.1.remove(pb);
+        }
+
+        if !options.is_default() {
+            self.1.insert(path.clone(), options);
+        }
+        self.0.push(path);
+    }
+
+    /// Iterates through all the paths contained, skipping those ignored by `rules`
     /// Same as [`search::iter_entries`]
     /// If you want to simply iterate over the paths defining this [`Entries`], please do
     /// `entries.as_ref().iter()`
     #[inline]
-    pub fn iter(self) -> Box<dyn Iterator<Item = PathBuf>> {
-        search::iter_entries(self)
+    pub fn iter(self, rules: &IgnoreRules) -> Box<dyn Iterator<Item = PathBuf>> {
+        search::iter_entries(self, rules)
     }
 
     /// Remove and return any duplicate entries
@@ -102,17 +320,12 @@ impl Entries {
     /// Creates a new [`Entries`] that's the minimum of all paths in `self`
     /// Removes:
     /// - Any duplicate entries
-    /// - Entries that are sub-paths of other entries
+    /// - Entries that are sub-paths of other entries, and reached by their [`EntryOptions`]
     pub fn trim(self) -> Entries {
         let mut new_entries = Entries::new();
 
-        for path in self.0.into_iter() {
-            if new_entries.contains(&path) {
-                continue;
-            }
-
-            new_entries.0.retain(|pb| !pb.starts_with(&path));
-            new_entries.0.push(path);
+        for (path, options) in self.into_iter_with_options() {
+            new_entries.push_covering(path, options);
         }
 
         new_entries
@@ -126,13 +339,8 @@ impl Entries {
     {
         let mut new_entries = Entries::new();
 
-        for path in entries.into_iter().flatten() {
-            if new_entries.contains(&path) {
-                continue;
-            }
-
-            new_entries.0.retain(|pb| !pb.starts_with(&path));
-            new_entries.0.push(path);
+        for (path, options) in entries.into_iter().flat_map(Entries::into_iter_with_options) {
+            new_entries.push_covering(path, options);
         }
 
         new_entries
@@ -149,16 +357,26 @@ impl Entries {
         let mut new_entries = it.next().unwrap_or_default();
 
         for e in it {
-            let (mut e, f) = e.into_iter()
-                .partition::<Vec<PathBuf>, _>(|bp| new_entries.0.iter()
-                    .any(|ap| bp.starts_with(ap) && ap != bp)
-                );
-
-            new_entries.retain(|ap|
-                e.iter().chain(&f)
-                    .any(|bp| ap.starts_with(bp) || ap == bp)
-            );
-            new_entries.append(&mut e);
+            let mut shared = Entries::new();
+
+            for (ap, a) in new_entries.iter_with_options() {
+                for (bp, b) in e.iter_with_options() {
+                    // The deeper path, with the options that both entries reach it with
+                    let entry = if let Some(offset) = depth_from(ap, bp) {
+                        a.narrowed(offset).map(|a| (bp, a.intersect(b)))
+                    } else if let Some(offset) = depth_from(bp, ap) {
+                        b.narrowed(offset).map(|b| (ap, b.intersect(a)))
+                    } else {
+                        None
+                    };
+
+                    if let Some((path, options)) = entry {
+                        shared.push_covering(path.clone(), options);
+                    }
+                }
+            }
+
+            new_entries = shared;
         }
 
         new_entries.trim()
@@ -171,12 +389,6 @@ impl AsRef<Vec<PathBuf>> for Entries {
     }
 }
 
-impl AsMut<Vec<PathBuf>> for Entries {
-    fn as_mut(&mut self) -> &mut Vec<PathBuf> {
-        &mut self.0
-    }
-}
-
 impl Deref for Entries {
     type Target = Vec<PathBuf>;
 
@@ -185,12 +397,6 @@ impl Deref for Entries {
     }
 }
 
-impl DerefMut for Entries {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
-    }
-}
-
 impl IntoIterator for Entries {
     type Item = PathBuf;
     type IntoIter = <Vec<PathBuf> as IntoIterator>::IntoIter;
@@ -202,7 +408,7 @@ impl IntoIterator for Entries {
 
 impl From<Vec<PathBuf>> for Entries {
     fn from(value: Vec<PathBuf>) -> Self {
-        Entries(value)
+        Entries(value, HashMap::new())
     }
 }
 
@@ -214,7 +420,7 @@ impl From<Entries> for Vec<PathBuf> {
 
 impl FromIterator<PathBuf> for Entries {
     fn from_iter<T: IntoIterator<Item = PathBuf>>(iter: T) -> Self {
-        Entries(Vec::from_iter(iter))
+        Entries(Vec::from_iter(iter), HashMap::new())
     }
 }
 
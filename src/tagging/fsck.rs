@@ -0,0 +1,493 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::{self, create_dir_all};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::ToPrettyString;
+
+use super::id::TagID;
+use super::order::TagOrder;
+use super::usage::TagUsage;
+use super::{get_all_tags, get_save_dir, LoadError, SaveError, Tag};
+
+
+#[derive(Debug, Error)]
+pub enum FixError {
+    #[error("failed to load tag: {0}")]
+    Load(#[from] LoadError),
+    #[error("failed to save tag: {0}")]
+    Save(#[from] SaveError),
+    #[error("tag {0} already exists")]
+    AlreadyExists(TagID),
+    #[error(transparent)]
+    IO(#[from] io::Error),
+}
+
+
+
+/// Categories of [`Problem`]s, each with its own fix
+/// See [`fix`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProblemKind {
+    NonexistentEntry,
+    DanglingSubtag,
+    SubtagCycle,
+    DuplicateEntry,
+    RedundantEntry,
+    Unparsable,
+    NonCanonicalName,
+}
+
+impl ProblemKind {
+    pub const ALL: [ProblemKind; 7] = [
+        ProblemKind::NonexistentEntry,
+        ProblemKind::DanglingSubtag,
+        ProblemKind::SubtagCycle,
+        ProblemKind::DuplicateEntry,
+        ProblemKind::RedundantEntry,
+        ProblemKind::Unparsable,
+        ProblemKind::NonCanonicalName,
+    ];
+
+    /// What [`fix`] does for this kind of problem
+    pub fn fix_description(&self) -> &'static str {
+        match self {
+            ProblemKind::NonexistentEntry => "Remove the entries",
+            ProblemKind::DanglingSubtag => "Remove the subtags",
+            ProblemKind::SubtagCycle => "Remove the subtag closing each cycle",
+            ProblemKind::DuplicateEntry => "Remove the duplicates",
+            ProblemKind::RedundantEntry => "Remove the redundant entries",
+            ProblemKind::Unparsable => "Move the files to the quarantine folder",
+            ProblemKind::NonCanonicalName => "Rename the files and update subtags referring to them",
+        }
+    }
+}
+
+impl Display for ProblemKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProblemKind::NonexistentEntry => write!(f, "Nonexistent entries"),
+            ProblemKind::DanglingSubtag => write!(f, "Dangling subtags"),
+            ProblemKind::SubtagCycle => write!(f, "Subtag cycles"),
+            ProblemKind::DuplicateEntry => write!(f, "Duplicate entries"),
+            ProblemKind::RedundantEntry => write!(f, "Redundant entries"),
+            ProblemKind::Unparsable => write!(f, "Unparsable files"),
+            ProblemKind::NonCanonicalName => write!(f, "Invalid file names"),
+        }
+    }
+}
+
+
+
+/// A single problem found in the tag store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// An entry pointing to a path that doesn't exist
+    NonexistentEntry { tag: TagID, path: PathBuf },
+    /// A subtag referring to a tag that doesn't exist
+    DanglingSubtag { tag: TagID, subtag: TagID },
+    /// Each tag has the next one as subtag, and the last one has the first
+    SubtagCycle(Vec<TagID>),
+    /// An entry that appears more than once in the same tag
+    DuplicateEntry { tag: TagID, path: PathBuf },
+    /// An entry already covered by another entry of the same tag
+    /// See [`super::entries::Entries::trim`]
+    RedundantEntry { tag: TagID, path: PathBuf },
+    /// A tag file that fails to load
+    Unparsable { path: PathBuf, error: String },
+    /// A tag file whose name doesn't round-trip through [`TagID::parse`]
+    NonCanonicalName { path: PathBuf, expected: TagID },
+}
+
+impl Problem {
+    pub fn kind(&self) -> ProblemKind {
+        match self {
+            Problem::NonexistentEntry { .. } => ProblemKind::NonexistentEntry,
+            Problem::DanglingSubtag { .. } => ProblemKind::DanglingSubtag,
+            Problem::SubtagCycle(_) => ProblemKind::SubtagCycle,
+            Problem::DuplicateEntry { .. } => ProblemKind::DuplicateEntry,
+            Problem::RedundantEntry { .. } => ProblemKind::RedundantEntry,
+            Problem::Unparsable { .. } => ProblemKind::Unparsable,
+            Problem::NonCanonicalName { .. } => ProblemKind::NonCanonicalName,
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::NonexistentEntry { tag, path } =>
+                write!(f, "{tag}: \"{}\" doesn't exist", path.to_pretty_string()),
+            Problem::DanglingSubtag { tag, subtag } =>
+                write!(f, "{tag}: subtag {subtag} doesn't exist"),
+            Problem::SubtagCycle(cycle) => {
+                for id in cycle.iter() {
+                    write!(f, "{id} > ")?;
+                }
+                match cycle.first() {
+                    Some(id) => write!(f, "{id}"),
+                    None => Ok(()),
+                }
+            }
+            Problem::DuplicateEntry { tag, path } =>
+                write!(f, "{tag}: \"{}\" appears more than once", path.to_pretty_string()),
+            Problem::RedundantEntry { tag, path } =>
+                write!(f, "{tag}: \"{}\" is already covered by another entry", path.to_pretty_string()),
+            Problem::Unparsable { path, error } =>
+                write!(f, "\"{}\": {error}", path.to_pretty_string()),
+            Problem::NonCanonicalName { path, expected } =>
+                write!(f, "\"{}\" should be named {expected}", path.to_pretty_string()),
+        }
+    }
+}
+
+
+
+/// Result of [`fsck`]
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Iterate over all problems of the given `kind`
+    pub fn iter_kind(&self, kind: ProblemKind) -> impl Iterator<Item = &Problem> {
+        self.problems.iter()
+            .filter(move |p| p.kind() == kind)
+    }
+
+    /// Get the kinds of problems found, in the order of [`ProblemKind::ALL`]
+    pub fn kinds(&self) -> Vec<ProblemKind> {
+        ProblemKind::ALL.into_iter()
+            .filter(|kind| self.problems.iter().any(|p| p.kind() == *kind))
+            .collect()
+    }
+}
+
+
+
+/// Scan the whole tag store for problems
+/// Unlike [`super::load_tags`], this looks at the tag files as they are, without silently
+/// dropping anything
+pub fn fsck() -> io::Result<Report> {
+    let mut problems: Vec<Problem> = Vec::new();
+    let mut tags: Vec<Tag> = Vec::new();
+    let mut known_ids: HashSet<TagID> = HashSet::new();
+
+    for path in get_all_tags()?.into_iter() {
+        if let Ok(id) = TagID::try_from(path.as_path()) {
+            known_ids.insert(id);
+        }
+
+        if let Some(expected) = expected_id(&path) {
+            problems.push(Problem::NonCanonicalName { path: path.clone(), expected });
+        }
+
+        match Tag::load_from_path_unchecked(&path) {
+            Ok(tag) => tags.push(tag),
+            Err(err) => problems.push(Problem::Unparsable { path, error: err.to_string() }),
+        }
+    }
+
+    problems.extend(check_tags(&tags, &known_ids));
+    Ok(Report { problems })
+}
+
+/// Get the id the tag file at `path` should have, if its name doesn't round-trip through
+/// [`TagID::parse`]
+fn expected_id(path: &Path) -> Option<TagID> {
+    let stem = path.file_stem()?.to_str()?;
+    let expected = TagID::parse(stem);
+    (expected.as_str() != stem).then_some(expected)
+}
+
+/// Check already loaded `tags` for problems
+/// `known_ids` are the ids of all tag files, including those that failed to load
+fn check_tags(tags: &[Tag], known_ids: &HashSet<TagID>) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = Vec::new();
+
+    for tag in tags.iter() {
+        for path in tag.entries.as_ref().iter() {
+            if !path.exists() {
+                problems.push(Problem::NonexistentEntry { tag: tag.id.clone(), path: path.clone() });
+            }
+        }
+
+        for subtag in tag.subtags.iter() {
+            if !known_ids.contains(subtag) {
+                problems.push(Problem::DanglingSubtag { tag: tag.id.clone(), subtag: subtag.clone() });
+            }
+        }
+
+        let mut entries = tag.entries.clone();
+        let mut duplicates = entries.remove_duplicates();
+        duplicates.sort();
+        problems.extend(duplicates.into_iter()
+            .map(|path| Problem::DuplicateEntry { tag: tag.id.clone(), path })
+        );
+
+        let trimmed = entries.clone().trim();
+        problems.extend(entries.into_iter()
+            .filter(|pb| !trimmed.as_ref().contains(pb))
+            .map(|path| Problem::RedundantEntry { tag: tag.id.clone(), path })
+        );
+    }
+
+    problems.extend(find_cycles(tags).into_iter().map(Problem::SubtagCycle));
+    problems
+}
+
+/// Find subtag cycles between `tags`
+/// Each cycle is only reported once
+fn find_cycles(tags: &[Tag]) -> Vec<Vec<TagID>> {
+    let graph: HashMap<&TagID, &Vec<TagID>> = tags.iter()
+        .map(|t| (&t.id, &t.subtags))
+        .collect();
+
+    let mut cycles: Vec<Vec<TagID>> = Vec::new();
+    let mut seen_cycles: HashSet<Vec<TagID>> = HashSet::new();
+    let mut done: HashSet<&TagID> = HashSet::new();
+
+    for tag in tags.iter() {
+        let mut stack: Vec<&TagID> = Vec::new();
+        visit(&tag.id, &graph, &mut stack, &mut done, &mut |cycle| {
+            // Same cycle, found from another tag
+            let mut key = cycle.clone();
+            key.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+            if seen_cycles.insert(key) {
+                cycles.push(cycle);
+            }
+        });
+    }
+
+    cycles
+}
+
+fn visit<'a>(
+    id: &'a TagID,
+    graph: &HashMap<&'a TagID, &'a Vec<TagID>>,
+    stack: &mut Vec<&'a TagID>,
+    done: &mut HashSet<&'a TagID>,
+    on_cycle: &mut dyn FnMut(Vec<TagID>),
+) {
+    if done.contains(id) {
+        return;
+    }
+    if let Some(start) = stack.iter().position(|t| *t == id) {
+        on_cycle(stack[start..].iter().map(|t| (*t).clone()).collect());
+        return;
+    }
+
+    let Some(subtags) = graph.get(id) else {
+        return;
+    };
+
+    stack.push(id);
+    for subtag in subtags.iter() {
+        visit(subtag, graph, stack, done, on_cycle);
+    }
+    stack.pop();
+    done.insert(id);
+}
+
+
+
+/// Fix all problems of the given `kind` in `report`
+/// Returns how many problems were fixed
+/// Stops at the first error. Running [`fsck`] again shows what's left
+pub fn fix(report: &Report, kind: ProblemKind) -> Result<usize, FixError> {
+    let mut count: usize = 0;
+
+    for problem in report.iter_kind(kind) {
+        match problem {
+            Problem::NonexistentEntry { tag, path } => {
+                let mut tag = load(tag)?;
+                tag.remove_entry(path);
+                tag.save()?;
+            }
+
+            Problem::DanglingSubtag { tag, subtag } => {
+                let mut tag = load(tag)?;
+                tag.remove_subtag(subtag);
+                tag.save()?;
+            }
+
+            Problem::SubtagCycle(cycle) => {
+                let (Some(first), Some(last)) = (cycle.first(), cycle.last()) else {
+                    continue;
+                };
+                let mut tag = load(last)?;
+                tag.remove_subtag(first);
+                tag.save()?;
+            }
+
+            Problem::DuplicateEntry { tag, .. } => {
+                let mut tag = load(tag)?;
+                let _ = tag.entries.remove_duplicates();
+                tag.save()?;
+            }
+
+            Problem::RedundantEntry { tag, .. } => {
+                let mut tag = load(tag)?;
+                tag.entries = tag.entries.trim();
+                tag.save()?;
+            }
+
+            Problem::Unparsable { path, .. } => {
+                quarantine(path)?;
+            }
+
+            Problem::NonCanonicalName { path, expected } => {
+                rename_file(path, expected)?;
+            }
+        }
+
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[inline]
+fn load(id: &TagID) -> Result<Tag, LoadError> {
+    Tag::load_from_path_unchecked(&id.get_path())
+}
+
+/// Rename the tag file at `path` to `new_id`, updating all subtags referring to it
+fn rename_file(path: &Path, new_id: &TagID) -> Result<(), FixError> {
+    let new_path = new_id.get_path();
+    if new_path.exists() {
+        return Err(FixError::AlreadyExists(new_id.clone()));
+    }
+    let old_id = TagID::try_from(path)
+        .map_err(|_| LoadError::InvalidName)?;
+
+    fs::rename(path, &new_path)?;
+
+    let mut order = TagOrder::load();
+    if order.rename(&old_id, new_id) {
+        order.save()?;
+    }
+    let mut usage = TagUsage::load();
+    if usage.rename(&old_id, new_id) {
+        usage.save()?;
+    }
+
+    for path in get_all_tags()?.iter() {
+        let Ok(mut tag) = Tag::load_from_path_unchecked(path) else {
+            continue;
+        };
+        if tag.remove_subtag(&old_id) {
+            let _ = tag.add_subtag(new_id);
+            tag.save()?;
+        }
+    }
+
+    Ok(())
+}
+
+
+
+/// Gets the folder where broken tag files are moved to
+/// See [`quarantine`]
+pub fn get_quarantine_dir() -> PathBuf {
+    get_save_dir().join("quarantine/")
+}
+
+/// Move the tag file at `path` to [`get_quarantine_dir`], so that it stops being loaded but
+/// can still be recovered
+/// Returns the new path of the file
+pub fn quarantine(path: &Path) -> io::Result<PathBuf> {
+    let dir = get_quarantine_dir();
+    create_dir_all(&dir)?;
+
+    let file_name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut dest = dir.join(file_name);
+    let mut count: u32 = 0;
+    while dest.exists() {
+        count += 1;
+        dest = dir.join(format!("{}.{}", file_name.to_string_lossy(), count));
+    }
+
+    fs::rename(path, &dest)?;
+    Ok(dest)
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::fs::create_dir_all;
+    use std::path::Path;
+
+    use crate::tagging::entries::EntryOptions;
+    use crate::tagging::id::TagID;
+    use crate::tagging::Tag;
+    use crate::TestDir;
+
+    use super::{check_tags, expected_id, Problem, ProblemKind};
+
+    #[test]
+    fn checking() {
+        let dir = TestDir::new("fsck");
+        create_dir_all(dir.join("a/b")).unwrap();
+
+        let mut music = Tag::create(TagID::new("music"));
+        music.entries.0.extend([
+            dir.join("a"),
+            dir.join("a/b"),
+            dir.join("a"),
+            dir.join("nope"),
+        ]);
+        music.add_subtag(&TagID::new("songs")).unwrap();
+        music.add_subtag(&TagID::new("ghost")).unwrap();
+
+        let mut songs = Tag::create(TagID::new("songs"));
+        songs.entries.push_with_options(dir.join("a"), EntryOptions::direct_children_only()).unwrap();
+        songs.entries.push(dir.join("a/b")).unwrap();
+        songs.add_subtag(&TagID::new("music")).unwrap();
+
+        let known: HashSet<TagID> = [ "music", "songs" ].into_iter().map(TagID::new).collect();
+        let problems = check_tags(&[ music, songs ], &known);
+        let of_kind = |kind: ProblemKind| -> Vec<&Problem> {
+            problems.iter().filter(|p| p.kind() == kind).collect()
+        };
+
+        assert_eq!(of_kind(ProblemKind::NonexistentEntry), vec![
+            &Problem::NonexistentEntry { tag: TagID::new("music"), path: dir.join("nope") },
+        ]);
+        assert_eq!(of_kind(ProblemKind::DanglingSubtag), vec![
+            &Problem::DanglingSubtag { tag: TagID::new("music"), subtag: TagID::new("ghost") },
+        ]);
+        assert_eq!(of_kind(ProblemKind::DuplicateEntry), vec![
+            &Problem::DuplicateEntry { tag: TagID::new("music"), path: dir.join("a") },
+        ]);
+        // `songs` only goes 1 level deep into `a`, which does reach `a/b` but not its contents
+        assert_eq!(of_kind(ProblemKind::RedundantEntry), vec![
+            &Problem::RedundantEntry { tag: TagID::new("music"), path: dir.join("a/b") },
+        ]);
+        assert_eq!(of_kind(ProblemKind::SubtagCycle), vec![
+            &Problem::SubtagCycle(vec![ TagID::new("music"), TagID::new("songs") ]),
+        ]);
+
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(expected_id(Path::new("tags/my-tag.json")), None);
+        assert_eq!(expected_id(Path::new("tags/new-tag-1.json")), None);
+        assert_eq!(expected_id(Path::new("tags/My Tag.json")), Some(TagID::new("my-tag")));
+        assert_eq!(expected_id(Path::new("tags/my_tag.json")), Some(TagID::new("my-tag")));
+    }
+}
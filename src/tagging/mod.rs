@@ -1,22 +1,29 @@
 use std::collections::HashMap;
 use std::fmt::Write;
-use std::fs::{create_dir_all, read_dir};
+use std::fs::{self, create_dir_all, read_dir};
 use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::{RwLock, RwLockReadGuard};
 
+pub mod annotation;
 pub mod entries;
+pub mod fsck;
 pub mod id;
+pub mod order;
+pub mod usage;
 pub mod tag;
+pub mod stats;
+pub mod duplicates;
 
 use iced::Command;
 use id::TagID;
+use order::TagOrder;
 use crate::log::notification::Notification;
 use crate::log::Level as LogLevel;
 use crate::{error, send_message, trace};
 use crate::app::Message as AppMessage;
 
-pub use tag::{ LoadError, RenameError, SaveError };
+pub use tag::{ LoadError, NotContained, RenameError, SaveError };
 pub use tag::Tag;
 
 
@@ -85,6 +92,41 @@ pub fn get_save_dir() -> PathBuf {
     PathBuf::from("C:/Users/ddxte/Documents/Projects/kfiles new/tests/tags/")
 }
 
+/// Returns the dir where the last working version of each tag file is kept
+/// See [`restore_backup`]
+pub fn get_backup_dir() -> PathBuf {
+    get_save_dir().join("backups/")
+}
+
+/// Get the path of the backup of the tag file at `path`
+pub fn get_backup_path(path: &Path) -> Option<PathBuf> {
+    path.file_name()
+        .map(|name| get_backup_dir().join(name))
+}
+
+/// Back up the tag file at `path` before it gets overwritten
+/// Files that fail to load are not backed up, so that the backup always stays usable
+pub fn back_up(path: &Path) -> io::Result<()> {
+    if !path.exists() || Tag::load_from_path_unchecked(path).is_err() {
+        return Ok(());
+    }
+
+    let backup_path = get_backup_path(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    create_dir_all(get_backup_dir())?;
+    fs::copy(path, backup_path)?;
+    Ok(())
+}
+
+/// Replace the tag file at `path` with its backup
+pub fn restore_backup(path: &Path) -> io::Result<()> {
+    let backup_path = get_backup_path(path)
+        .filter(|pb| pb.is_file())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no backup found"))?;
+    fs::copy(backup_path, path)?;
+    Ok(())
+}
+
 /// Get all existing tags as paths
 pub fn get_all_tags() -> io::Result<Vec<PathBuf>> {
     Ok(read_dir(get_save_dir_or_create()?)?
@@ -107,8 +149,6 @@ pub fn get_all_tag_ids() -> io::Result<Vec<TagID>> {
 /// TODO documentation
 /// TODO clean up
 pub fn load_tags() -> TagLoadResult {
-    use crate::{ send_message, error };
-
     trace!("[tagging::load_tags()] Loading tags...");
 
     let paths = match get_all_tags() {
@@ -132,6 +172,8 @@ pub fn load_tags() -> TagLoadResult {
         }
     }
 
+    TagOrder::load().sort(&mut tags, |t| &t.id);
+
     TagLoadResult::Ok(tags, errors)
 }
 
@@ -188,7 +230,54 @@ impl TagLoadResult {
     pub fn log_errors_verbose<V>(&self) -> Option<V>
     where V: TagLoadResultLog + Default
     {
-        todo!()
+        let mut v = V::default();
+
+        match self {
+            TagLoadResult::IO(err) => {
+                let content = format!("Failed to load tags:\n {}", err);
+                v.push(content);
+            }
+
+            TagLoadResult::Ok(_, errs) if errs.is_empty() => {
+                return None;
+            }
+
+            TagLoadResult::Ok(_, errs) => {
+                for (path, err) in errs.iter() {
+                    let content = match err {
+                        LoadError::ParseError(err) => format!(
+                            "Failed to load tag at \"{}\":\n {} (line {}, column {})",
+                            path.display(), err.msg, err.line + 1, err.col + 1
+                        ),
+                        err => format!("Failed to load tag at \"{}\":\n {}", path.display(), err),
+                    };
+                    v.push(content);
+                }
+            }
+        }
+
+        Some(v)
+    }
+
+    /// Get a [`Command`] reporting any errors
+    /// Failed tags open the diagnostics screen, which only happens once per set of failed paths
+    pub fn report_errors(&self) -> Command<AppMessage> {
+        match self {
+            TagLoadResult::IO(err) => {
+                let notif = Notification::new(
+                    LogLevel::Error,
+                    format!("Failed to load tags:\n {}", err),
+                );
+                send_message!(notif = notif)
+            }
+
+            TagLoadResult::Ok(_, errs) if errs.is_empty() => Command::none(),
+
+            TagLoadResult::Ok(_, errs) => {
+                let paths: Vec<PathBuf> = errs.keys().cloned().collect();
+                send_message!(AppMessage::TagsFailedToLoad(paths))
+            }
+        }
     }
 
     /* pub fn notify_on_error(self, commands: &mut Vec<Command<AppMessage>>) -> Self {
@@ -278,7 +367,7 @@ impl TagLoadResultLog for String {
 mod tests {
     use std::{collections::HashSet, path::{Path, PathBuf}};
 
-    use crate::tagging::{entries::Entries, id::TagID, tag::{SelfReferringSubtag, Tag}};
+    use crate::tagging::{entries::{Entries, EntryOptions}, id::TagID, tag::{SelfReferringSubtag, Tag}};
 
     #[test]
     fn serde() {
@@ -420,7 +509,7 @@ mod tests {
         let union_cba = Entries::union_of(vec![ a.clone(), b.clone(), c.clone() ]);
         assert_eq!(HashSet::from_iter(union_cba), expected );
 
-        c.as_mut().push(PathBuf::from("C:/Users/ddxte/"));
+        c.0.push(PathBuf::from("C:/Users/ddxte/"));
         let expected: HashSet<PathBuf> = HashSet::from_iter(vec![
             PathBuf::from("C:/Users/ddxte/")
         ]);
@@ -478,6 +567,58 @@ mod tests {
         assert_eq!(HashSet::from_iter(intersection_cba), expected );
     }
 
+    #[test]
+    fn entries_options() {
+        let mut a = Entries::from(vec![
+            PathBuf::from("/home/user/build/"),
+            PathBuf::from("/home/user/Music/"),
+        ]);
+        assert!(a.set_options(Path::new("/home/user/build/"), EntryOptions::direct_children_only()));
+        assert!(!a.set_options(Path::new("/home/user/"), EntryOptions::direct_children_only()));
+
+        assert!(a.contains(Path::new("/home/user/build/")));
+        assert!(a.contains(Path::new("/home/user/build/debug")));
+        assert!(!a.contains(Path::new("/home/user/build/debug/deps")));
+        assert!(a.contains(Path::new("/home/user/Music/a/b/c.mp3")));
+
+        // Entries out of reach of depth-limited entries don't get trimmed
+        let mut b = a.clone();
+        b.0.push(PathBuf::from("/home/user/build/debug/"));
+        assert_eq!(b.trim().as_ref().len(), 3);
+
+        // Entries within reach do
+        let mut b = a.clone();
+        b.0.push(PathBuf::from("/home/user/Music/albums/"));
+        assert_eq!(b.trim().as_ref().len(), 2);
+
+        // Intersection narrows options down
+        let c = Entries::from(vec![ PathBuf::from("/home/user/") ]);
+        let mut c2 = c.clone();
+        c2.set_options(Path::new("/home/user/"), EntryOptions::default().with_max_depth(Some(2)));
+
+        let intersection = Entries::intersection_of(vec![ a.clone(), c2 ]);
+        assert_eq!(
+            HashSet::<PathBuf>::from_iter(intersection.clone()),
+            HashSet::from_iter(a.clone()),
+        );
+        assert_eq!(intersection.get_options(Path::new("/home/user/build/")), EntryOptions::default().with_max_depth(Some(1)));
+        assert_eq!(intersection.get_options(Path::new("/home/user/Music/")), EntryOptions::default().with_max_depth(Some(1)));
+
+        // Union keeps the options that reach the furthest
+        let union = Entries::union_of(vec![ a.clone(), c ]);
+        assert_eq!(union.as_ref(), &[ PathBuf::from("/home/user/") ]);
+        assert!(union.get_options(Path::new("/home/user/")).is_default());
+
+        // Removed entries don't leave their options behind
+        let mut b = a.clone();
+        assert!(b.remove(Path::new("/home/user/build/")));
+        assert!(!b.remove(Path::new("/home/user/build/")));
+        assert_eq!(b, Entries::from(vec![ PathBuf::from("/home/user/Music/") ]));
+        let mut b = a.clone();
+        b.retain(|pb| pb.ends_with("Music"));
+        assert_eq!(b, Entries::from(vec![ PathBuf::from("/home/user/Music/") ]));
+    }
+
     #[test]
     fn entries_string_list() {
         let entries = Entries::from(vec![
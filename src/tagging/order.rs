@@ -0,0 +1,224 @@
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use nanoserde::{DeJson, SerJson};
+
+use crate::{configs, error};
+
+use super::id::TagID;
+
+
+/// Gets the path of the file storing the [`TagOrder`]
+/// It lives outside the tags dir so that it doesn't get loaded as a tag
+#[inline]
+pub fn get_save_path() -> io::Result<PathBuf> {
+    configs::get_config_file_path("tag_order.json")
+}
+
+
+
+/// Which tags are pinned, and the order tags are shown in
+/// Pinned tags always come first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagOrder {
+    /// Pinned tags, in the order they were pinned
+    pinned: Vec<TagID>,
+    /// Manual order of tags. Tags not in here come last, in their original order
+    order: Vec<TagID>,
+}
+
+impl TagOrder {
+    /// Load the saved order
+    /// Returns the default order if there is none or it fails to load
+    pub fn load() -> Self {
+        let contents = match get_save_path().and_then(fs::read_to_string) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return TagOrder::default(),
+            Err(err) => {
+                error!("[TagOrder::load()] Failed to read tag order:\n {}", err);
+                return TagOrder::default();
+            }
+        };
+
+        match SerTagOrder::deserialize_json(&contents) {
+            Ok(ser) => ser.into(),
+            Err(err) => {
+                error!("[TagOrder::load()] Failed to parse tag order:\n {}", err);
+                TagOrder::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = get_save_path()?;
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+
+        let string = SerTagOrder::from(self).serialize_json();
+        File::create(path)?
+            .write_all(string.as_bytes())?;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn is_pinned(&self, id: &TagID) -> bool {
+        self.pinned.contains(id)
+    }
+
+    /// Get the pinned tags, in order
+    #[inline]
+    pub fn get_pinned(&self) -> &Vec<TagID> {
+        &self.pinned
+    }
+
+    /// Pin or unpin a tag
+    /// Returns whether anything changed
+    pub fn set_pinned(&mut self, id: &TagID, pinned: bool) -> bool {
+        match (pinned, self.pinned.iter().position(|t| t == id)) {
+            (true, None) => self.pinned.push(id.clone()),
+            (false, Some(index)) => { self.pinned.remove(index); },
+            _ => return false,
+        }
+        true
+    }
+
+    /// Sort `items` in place by this order
+    /// The sort is stable, so items not in the manual order keep their relative order
+    pub fn sort<T, F>(&self, items: &mut [T], get_id: F)
+    where F: Fn(&T) -> &TagID,
+    {
+        items.sort_by_cached_key(|item| {
+            let id = get_id(item);
+            (
+                self.pinned.iter().position(|t| t == id).unwrap_or(usize::MAX),
+                self.order.iter().position(|t| t == id).unwrap_or(usize::MAX),
+            )
+        });
+    }
+
+    /// Move pinned tags to the front of `items`, keeping everything else in place
+    pub fn pinned_first<T, F>(&self, items: &mut [T], get_id: F)
+    where F: Fn(&T) -> &TagID,
+    {
+        items.sort_by_cached_key(|item| {
+            self.pinned.iter().position(|t| t == get_id(item)).unwrap_or(usize::MAX)
+        });
+    }
+
+    /// Move the tag `id` by `offset` places, given the currently shown order `ids`
+    /// Pinned tags only move among pinned tags, and the rest among the rest
+    /// Returns whether anything changed
+    pub fn move_by(&mut self, ids: &[TagID], id: &TagID, offset: isize) -> bool {
+        let is_pinned = self.is_pinned(id);
+        let mut group: Vec<TagID> = ids.iter()
+            .filter(|t| self.is_pinned(t) == is_pinned)
+            .cloned()
+            .collect();
+
+        let Some(index) = group.iter().position(|t| t == id) else {
+            return false;
+        };
+        let new_index = index.saturating_add_signed(offset)
+            .min(group.len() - 1);
+        if new_index == index {
+            return false;
+        }
+
+        let id = group.remove(index);
+        group.insert(new_index, id);
+
+        if is_pinned {
+            self.pinned = group;
+        } else {
+            self.order = group;
+        }
+        true
+    }
+
+    /// Replace `old` by `new`, e.g. after renaming a tag
+    /// Returns whether `old` was in this order
+    pub fn rename(&mut self, old: &TagID, new: &TagID) -> bool {
+        let mut found = false;
+        for id in self.pinned.iter_mut().chain(self.order.iter_mut()) {
+            if id == old {
+                id.clone_from(new);
+                found = true;
+            }
+        }
+        found
+    }
+}
+
+
+
+#[derive(Debug, Clone, SerJson, DeJson)]
+struct SerTagOrder {
+    pinned: Vec<String>,
+    order: Vec<String>,
+}
+
+impl From<&TagOrder> for SerTagOrder {
+    fn from(value: &TagOrder) -> Self {
+        SerTagOrder {
+            pinned: value.pinned.iter().map(|id| id.0.clone()).collect(),
+            order: value.order.iter().map(|id| id.0.clone()).collect(),
+        }
+    }
+}
+
+impl From<SerTagOrder> for TagOrder {
+    fn from(value: SerTagOrder) -> Self {
+        TagOrder {
+            pinned: value.pinned.into_iter().map(TagID).collect(),
+            order: value.order.into_iter().map(TagID).collect(),
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::tagging::id::TagID;
+
+    use super::TagOrder;
+
+    fn ids(names: &[&str]) -> Vec<TagID> {
+        names.iter().map(|s| TagID::new(s)).collect()
+    }
+
+    #[test]
+    fn ordering() {
+        let mut order = TagOrder::default();
+        let mut tags = ids(&[ "a", "b", "c", "d" ]);
+
+        order.set_pinned(&TagID::new("c"), true);
+        order.sort(&mut tags, |t| t);
+        assert_eq!(tags, ids(&[ "c", "a", "b", "d" ]));
+
+        // Unpinned tags only move among themselves
+        assert!(!order.move_by(&tags, &TagID::new("a"), -1));
+        assert!(order.move_by(&tags, &TagID::new("d"), -2));
+        order.sort(&mut tags, |t| t);
+        assert_eq!(tags, ids(&[ "c", "d", "a", "b" ]));
+
+        // New tags come last
+        tags.push(TagID::new("e"));
+        tags.reverse();
+        order.sort(&mut tags, |t| t);
+        assert_eq!(tags, ids(&[ "c", "d", "a", "b", "e" ]));
+
+        order.set_pinned(&TagID::new("b"), true);
+        order.sort(&mut tags, |t| t);
+        assert_eq!(tags, ids(&[ "c", "b", "d", "a", "e" ]));
+        assert!(order.move_by(&tags, &TagID::new("b"), -1));
+        order.sort(&mut tags, |t| t);
+        assert_eq!(tags, ids(&[ "b", "c", "d", "a", "e" ]));
+
+        order.rename(&TagID::new("b"), &TagID::new("bee"));
+        assert_eq!(order.get_pinned(), &ids(&[ "bee", "c" ]));
+    }
+}
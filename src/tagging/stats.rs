@@ -0,0 +1,347 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, create_dir_all, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nanoserde::{DeJson, SerJson};
+
+use crate::ignore_rules::IgnoreRules;
+use crate::search::{self, CancelToken};
+use crate::{error, get_temp_dir};
+
+use super::entries::{Entries, EntryOptions};
+use super::id::TagID;
+use super::Tag;
+
+
+/// How long cached stats stay valid, even if the tag's entries haven't changed
+/// Files inside tagged folders can change at any time, so we can't trust the cache forever
+const MAX_CACHE_AGE: Duration = Duration::from_secs(60 * 60);
+
+
+
+/// Statistics about all the paths under a [`Tag`], INCLUDING subtags
+/// See [`TagStats::compute`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagStats {
+    pub file_count: u64,
+    pub folder_count: u64,
+    /// Total size of all files, in bytes
+    pub total_size: u64,
+    /// Most recent modification time out of all paths
+    pub newest_modified: Option<SystemTime>,
+    /// Number of entries that don't exist anymore
+    pub broken_entries: u64,
+}
+
+impl TagStats {
+    /// Walk through all entries of `tag` (see [`Tag::get_all_entries`]) and compute its stats
+    /// This can take a while, so you probably want to use a [`StatsBuilder`] instead
+    /// Returns `None` if `token` got cancelled before it was done
+    pub fn compute(tag: &Tag, token: &CancelToken) -> Option<TagStats> {
+        TagStats::compute_entries(tag.get_all_entries(), &IgnoreRules::for_tag(tag), token)
+    }
+
+    /// Walk through all paths in `entries` that aren't ignored by `rules`, and compute their stats
+    /// Returns `None` if `token` got cancelled before it was done
+    pub fn compute_entries(entries: Entries, rules: &IgnoreRules, token: &CancelToken) -> Option<TagStats> {
+        let mut stats = TagStats {
+            broken_entries: entries.as_ref().iter()
+                .filter(|pb| !pb.exists())
+                .count() as u64,
+            ..Default::default()
+        };
+
+        for path in search::iter_entries(entries, rules) {
+            if token.is_cancelled() {
+                return None;
+            }
+            let Ok(meta) = fs::metadata(&path) else {
+                continue;
+            };
+
+            if meta.is_dir() {
+                stats.folder_count += 1;
+            } else {
+                stats.file_count += 1;
+                stats.total_size += meta.len();
+            }
+
+            if let Ok(modified) = meta.modified() {
+                if stats.newest_modified.is_none_or(|t| modified > t) {
+                    stats.newest_modified = Some(modified);
+                }
+            }
+        }
+
+        Some(stats)
+    }
+
+    /// Load the cached stats for `tag`, if they are still valid
+    /// Cached stats are invalidated when the tag's entries change, or when they get too old
+    pub fn load_cached(tag: &Tag) -> Option<TagStats> {
+        let mut contents = String::new();
+        File::open(get_cache_path(&tag.id)).ok()?
+            .read_to_string(&mut contents).ok()?;
+        let cached = SerTagStats::deserialize_json(&contents).ok()?;
+
+        if cached.entries_hash != hash_entries(&tag.get_all_entries()) {
+            return None;
+        }
+
+        let computed_at = UNIX_EPOCH + Duration::from_secs(cached.computed_at);
+        if computed_at.elapsed().map_or(true, |age| age > MAX_CACHE_AGE) {
+            return None;
+        }
+
+        Some(cached.into())
+    }
+
+    /// Save these stats to the cache for `tag`, creating directories if necessary
+    pub fn save_cached(&self, tag: &Tag) -> io::Result<()> {
+        let dir = get_cache_dir();
+        if !dir.exists() {
+            create_dir_all(&dir)?;
+        }
+
+        let mut ser = SerTagStats::from(self);
+        ser.entries_hash = hash_entries(&tag.get_all_entries());
+        ser.computed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        File::create(get_cache_path(&tag.id))?
+            .write_all(ser.serialize_json().as_bytes())?;
+        Ok(())
+    }
+
+    /// Short human readable summary of these stats
+    /// E.g. `"12 files, 3 folders, 1.5 MB"`
+    pub fn summary(&self) -> String {
+        format!(
+            "{} files, {} folders, {}",
+            self.file_count,
+            self.folder_count,
+            format_size(self.total_size),
+        )
+    }
+
+    /// Human readable [`TagStats::newest_modified`], in local time
+    pub fn newest_modified_string(&self) -> Option<String> {
+        let datetime: chrono::DateTime<chrono::Local> = self.newest_modified?.into();
+        Some(datetime.format("%Y-%m-%d %H:%M").to_string())
+    }
+}
+
+
+
+#[derive(Debug, Clone, SerJson, DeJson)]
+struct SerTagStats {
+    file_count: u64,
+    folder_count: u64,
+    total_size: u64,
+    /// Seconds since [`UNIX_EPOCH`], or 0 if none
+    newest_modified: u64,
+    broken_entries: u64,
+    /// Hash of the entries the stats were computed with
+    entries_hash: u64,
+    /// Seconds since [`UNIX_EPOCH`]
+    computed_at: u64,
+}
+
+impl From<&TagStats> for SerTagStats {
+    fn from(value: &TagStats) -> Self {
+        SerTagStats {
+            file_count: value.file_count,
+            folder_count: value.folder_count,
+            total_size: value.total_size,
+            newest_modified: value.newest_modified
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs()),
+            broken_entries: value.broken_entries,
+            entries_hash: 0,
+            computed_at: 0,
+        }
+    }
+}
+
+impl From<SerTagStats> for TagStats {
+    fn from(value: SerTagStats) -> Self {
+        TagStats {
+            file_count: value.file_count,
+            folder_count: value.folder_count,
+            total_size: value.total_size,
+            newest_modified: (value.newest_modified > 0)
+                .then(|| UNIX_EPOCH + Duration::from_secs(value.newest_modified)),
+            broken_entries: value.broken_entries,
+        }
+    }
+}
+
+
+
+pub fn get_cache_dir() -> PathBuf {
+    get_temp_dir().join("tag_stats/")
+}
+
+/// Get the file where the cached stats for `tag_id` are stored
+pub fn get_cache_path(tag_id: &TagID) -> PathBuf {
+    get_cache_dir().join(format!("{}.json", tag_id.as_ref()))
+}
+
+/// Get the hash of all paths in `entries` and their options, regardless of order
+fn hash_entries(entries: &Entries) -> u64 {
+    let mut paths: Vec<(&PathBuf, EntryOptions)> = entries.iter_with_options().collect();
+    paths.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut s = DefaultHasher::new();
+    paths.hash(&mut s);
+    s.finish()
+}
+
+
+/// Format a size in bytes into a human readable string
+/// E.g. `1536` => `"1.5 KB"`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KB", "MB", "GB", "TB", "PB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for u in UNITS.iter().skip(1) {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = u;
+    }
+
+    format!("{size:.1} {unit}")
+}
+
+
+
+
+type Worker = JoinHandle<Option<TagStats>>;
+
+/// Computes [`TagStats`] on background threads, one tag per thread, at most `thread_count`
+/// threads at a time
+/// Call [`StatsBuilder::update`] regularly to collect the results and start the queued tags
+/// Dropping it cancels the running computations
+#[derive(Debug)]
+pub struct StatsBuilder {
+    workers: HashMap<TagID, Worker>,
+    /// Tags waiting for a free thread
+    queue: VecDeque<Tag>,
+    thread_count: usize,
+    cancel_token: CancelToken,
+}
+
+impl StatsBuilder {
+    /// `thread_count` is usually [`crate::configs::Configs::get_search_thread_count`]
+    pub fn new(thread_count: usize) -> Self {
+        StatsBuilder {
+            workers: HashMap::new(),
+            queue: VecDeque::new(),
+            thread_count: thread_count.max(1),
+            cancel_token: CancelToken::new(),
+        }
+    }
+
+    /// Queue computing the stats for `tag` on a thread, unless it is already queued or being
+    /// computed
+    /// Returns whether the job was accepted
+    pub fn build(&mut self, tag: &Tag) -> bool {
+        if self.is_building(&tag.id) {
+            return false;
+        }
+
+        self.queue.push_back(tag.clone());
+        self.spawn_queued();
+        true
+    }
+
+    /// Start the queued tags while there are free threads
+    fn spawn_queued(&mut self) {
+        while self.workers.len() < self.thread_count {
+            let Some(tag) = self.queue.pop_front() else {
+                return;
+            };
+
+            let tag_id = tag.id.clone();
+            let token = self.cancel_token.clone();
+            let handle = thread::spawn(move || {
+                let stats = TagStats::compute(&tag, &token)?;
+                if let Err(err) = stats.save_cached(&tag) {
+                    error!("[StatsBuilder::spawn_queued()] Failed to cache stats for tag {}:\n {:?}", tag.id, err);
+                }
+                Some(stats)
+            });
+            self.workers.insert(tag_id, handle);
+        }
+    }
+
+    /// Returns whether any stats are still queued or being computed
+    pub fn is_busy(&self) -> bool {
+        !self.workers.is_empty() || !self.queue.is_empty()
+    }
+
+    /// Returns whether the stats for `tag_id` are currently queued or being computed
+    pub fn is_building(&self, tag_id: &TagID) -> bool {
+        self.workers.contains_key(tag_id) || self.queue.iter().any(|tag| &tag.id == tag_id)
+    }
+
+    /// Joins finished threads and returns their results, then starts the queued tags
+    #[must_use]
+    pub fn update(&mut self) -> Vec<(TagID, TagStats)> {
+        let finished: Vec<TagID> = self.workers.iter()
+            .filter(|(_, handle)| handle.is_finished())
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        let results = finished.into_iter()
+            .filter_map(|id| {
+                let handle = self.workers.remove(&id)?;
+                match handle.join() {
+                    Ok(stats) => Some((id, stats?)),
+                    Err(err) => {
+                        error!("[StatsBuilder::update()] Failed to join stats worker for tag {}:\n {:?}", id, err);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        self.spawn_queued();
+        results
+    }
+}
+
+impl Drop for StatsBuilder {
+    fn drop(&mut self) {
+        self.cancel_token.cancel();
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::format_size;
+
+    #[test]
+    fn size_formatting() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+}
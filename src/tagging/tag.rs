@@ -1,4 +1,4 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Display;
 use std::fs::{create_dir_all, remove_file, File};
 use std::io::{self, Read, Write};
@@ -8,8 +8,10 @@ use thiserror::Error;
 use nanoserde::{DeJson, DeJsonErr, SerJson};
 
 use crate::app::main_screen::Item;
+use crate::warn;
 
-use super::entries::{NonexistentPath, Entries};
+use super::annotation::Annotation;
+use super::entries::{EntryOptions, NonexistentPath, Entries};
 use super::id::TagID;
 
 
@@ -39,6 +41,16 @@ pub enum SaveError {
     IO(#[from] io::Error),
 }
 
+/// Path not contained in tag error
+#[derive(Debug)]
+pub struct NotContained;
+
+impl Display for NotContained {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "path is not contained in tag")
+    }
+}
+
 /// Self-referring subtag error
 #[derive(Debug)]
 pub struct SelfReferringSubtag;
@@ -59,8 +71,8 @@ pub struct Tag {
     pub id: TagID,
 
     /// Paths that this tag contains
-    /// Automatically goes inside folders during search, and ignores those that
-    /// start with "." (e.g. ".git/")
+    /// Automatically goes inside folders during search, skipping those matched by
+    /// [`crate::ignore_rules::IgnoreRules`]
     pub entries: Entries,
 
     /// All tags that are tagged with this tag
@@ -68,6 +80,15 @@ pub struct Tag {
     /// Then, searching for tag `"pictures"` would reveal entries from all 3 tags,
     /// but `"animals"` and `"memes"` would only reveal entries from themselves
     pub(super) subtags: Vec<TagID>,
+
+    /// Notes and ratings attached to paths under this tag
+    /// See [`Tag::set_annotation`]
+    pub(super) annotations: HashMap<PathBuf, Annotation>,
+
+    /// Gitignore-style patterns for paths to skip inside this tag's folders, on top of the
+    /// global ignore file
+    /// See [`crate::ignore_rules::IgnoreRules::for_tag`]
+    pub ignore_patterns: Vec<String>,
 }
 
 impl Tag {
@@ -78,8 +99,10 @@ impl Tag {
     {
         Tag {
             id: id.into(),
-            entries: Entries(Vec::new()),
+            entries: Entries::new(),
             subtags: Vec::new(),
+            annotations: HashMap::new(),
+            ignore_patterns: Vec::new(),
         }
     }
 
@@ -113,10 +136,9 @@ impl Tag {
     where
         P: PartialEq<PathBuf>,
     {
-        if let Some(index) = self.entries.as_ref()
-            .iter().position(|p| path == p)
-        {
-            self.entries.remove(index);
+        if let Some(found) = self.entries.as_ref().iter().find(|&p| path == p).cloned() {
+            self.entries.remove(&found);
+            self.retain_contained_annotations();
             return true;
         }
         false
@@ -172,8 +194,8 @@ impl Tag {
         let mut entries = self.entries.clone();
 
         // Merge subtags' entries into this one
-        for mut tag in self.iter_all_subtags() {
-            entries.as_mut().append(&mut tag.entries);
+        for tag in self.iter_all_subtags() {
+            entries.extend_entries(tag.entries);
         }
 
         entries.filter_duplicates()
@@ -188,6 +210,8 @@ impl Tag {
         if !path.exists() {
             let dir = path.parent().expect("could not get parent dir");
             create_dir_all(dir)?;
+        } else if let Err(err) = super::back_up(&path) {
+            warn!("[Tag::save()] Failed to back up \"{}\":\n {}", path.display(), err);
         }
 
         let string = SerTag::from(self).serialize_json();
@@ -225,6 +249,14 @@ impl Tag {
     }
 
     pub fn load_from_path(path: &Path) -> Result<Tag, LoadError> {
+        let mut tag = Tag::load_from_path_unchecked(path)?;
+        tag.subtags.retain(|tag_id| tag_id.exists());
+        Ok(tag)
+    }
+
+    /// Same as [`Tag::load_from_path`], but keeps subtags that don't exist
+    /// Useful for inspecting the tag file as is, e.g. in [`super::fsck`]
+    pub(super) fn load_from_path_unchecked(path: &Path) -> Result<Tag, LoadError> {
         let mut contents = String::new();
         File::open(path)?
             .read_to_string(&mut contents)?;
@@ -236,11 +268,52 @@ impl Tag {
             .ok_or(LoadError::InvalidName)?;
         tag.id = TagID(file_name.to_string());
 
-        tag.subtags.retain(|tag_id| tag_id.exists());
-
         Ok(tag)
     }
 
+    /// Get the [`Annotation`] attached to `path`, if any
+    pub fn get_annotation<P>(&self, path: P) -> Option<&Annotation>
+    where P: AsRef<Path>,
+    {
+        self.annotations.get(path.as_ref())
+    }
+
+    /// Attach an [`Annotation`] to `path`, replacing any previous one
+    /// Setting an empty annotation removes it
+    /// Returns `Err(NotContained)` if `path` is not tagged with this [`Tag`]
+    pub fn set_annotation(&mut self, path: PathBuf, annotation: Annotation) -> Result<(), NotContained> {
+        if !self.contains(&path) {
+            return Err(NotContained);
+        }
+
+        if annotation.is_empty() {
+            self.annotations.remove(&path);
+        } else {
+            self.annotations.insert(path, annotation);
+        }
+        Ok(())
+    }
+
+    /// Iterate over all annotated paths under this [`Tag`], EXCLUDING subtags
+    pub fn iter_annotations(&self) -> impl Iterator<Item = (&PathBuf, &Annotation)> {
+        self.annotations.iter()
+    }
+
+    /// Remove annotations on paths that are no longer contained in this [`Tag`]
+    /// Returns the paths whose annotations were removed
+    pub fn retain_contained_annotations(&mut self) -> Vec<PathBuf> {
+        let mut removed: Vec<PathBuf> = Vec::new();
+        let entries = &self.entries;
+        self.annotations.retain(|pb, _| {
+            let keep = entries.contains(pb);
+            if !keep {
+                removed.push(pb.clone());
+            }
+            keep
+        });
+        removed
+    }
+
     /// Get all directories under this [`Tag`], including all subtags
     pub fn get_dirs(&self) -> Box<dyn Iterator<Item = Item>> {
         unimplemented!()
@@ -304,6 +377,28 @@ impl PartialEq<Tag> for Tag {
 struct SerTag {
     entries: Vec<String>,
     subtags: Vec<String>,
+    /// Optional so that older tag files still load
+    #[nserde(default)]
+    annotations: Vec<SerAnnotation>,
+    #[nserde(default)]
+    ignore_patterns: Vec<String>,
+    /// Only entries with non-default options are stored
+    #[nserde(default)]
+    entry_options: Vec<SerEntryOptions>,
+}
+
+#[derive(Debug, Clone, SerJson, DeJson)]
+struct SerEntryOptions {
+    path: String,
+    follow_links: bool,
+    max_depth: Option<usize>,
+}
+
+#[derive(Debug, Clone, SerJson, DeJson)]
+struct SerAnnotation {
+    path: String,
+    note: String,
+    rating: Option<u8>,
 }
 
 impl From<&Tag> for SerTag {
@@ -316,21 +411,52 @@ impl From<&Tag> for SerTag {
             subtags: value.subtags.iter()
                 .map(|id| id.0.clone())
                 .collect(),
+            annotations: value.annotations.iter()
+                .filter_map(|(pb, a)| pb.to_str().map(|p| SerAnnotation {
+                    path: p.to_string(),
+                    note: a.note.clone(),
+                    rating: a.rating,
+                }))
+                .collect(),
+            ignore_patterns: value.ignore_patterns.clone(),
+            entry_options: value.entries.iter_with_options()
+                .filter(|(_, o)| !o.is_default())
+                .filter_map(|(pb, o)| pb.to_str().map(|p| SerEntryOptions {
+                    path: p.to_string(),
+                    follow_links: o.follow_links,
+                    max_depth: o.max_depth,
+                }))
+                .collect(),
         }
     }
 }
 
 impl From<SerTag> for Tag {
     fn from(value: SerTag) -> Self {
+        let mut entries: Entries = value.entries.into_iter()
+            .map(PathBuf::from)
+            .collect::<Vec<PathBuf>>()
+            .into();
+        for o in value.entry_options {
+            let options = EntryOptions::default()
+                .with_follow_links(o.follow_links)
+                .with_max_depth(o.max_depth);
+            entries.set_options(Path::new(&o.path), options);
+        }
+
         Tag {
             id: TagID( String::new() ),
-            entries: value.entries.into_iter()
-                .map(PathBuf::from)
-                .collect::<Vec<PathBuf>>()
-                .into(),
+            entries,
             subtags: value.subtags.into_iter()
                 .map(TagID)
                 .collect(),
+            annotations: value.annotations.into_iter()
+                .map(|a| (
+                    PathBuf::from(a.path),
+                    Annotation::new(&a.note) .with_rating(a.rating),
+                ))
+                .collect(),
+            ignore_patterns: value.ignore_patterns,
         }
     }
 }
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nanoserde::{DeJson, SerJson};
+
+use crate::{configs, error};
+
+use super::id::TagID;
+
+
+/// How much a tag's usage weighs against its fuzzy match score, at most
+/// For reference, matching the start of a word in [`crate::strmatch::Sublime`] is worth 72
+pub const MAX_USAGE_BONUS: isize = 48;
+
+/// Uses after which a tag counts as used as often as it gets
+const FREQUENT_USE_COUNT: f32 = 30.0;
+
+/// A tag's recency weight halves every this many days since it was last used
+const RECENCY_HALF_LIFE_DAYS: f32 = 7.0;
+
+const SECONDS_PER_DAY: f32 = 60.0 * 60.0 * 24.0;
+
+
+/// Gets the path of the file storing the [`TagUsage`], which is rewritten every time a tag
+/// gets used
+#[inline]
+pub fn get_save_path() -> io::Result<PathBuf> {
+    configs::get_config_file_path("tag_usage.json")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Record {
+    count: u32,
+    /// Unix timestamp, in seconds
+    last_used: u64,
+}
+
+
+
+/// How often and how recently each tag was used in queries and file actions
+/// Used to rank tag suggestions. See [`TagUsage::bonus`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagUsage(HashMap<TagID, Record>);
+
+impl TagUsage {
+    /// Load the saved usage history
+    /// Returns an empty history if there is none or it fails to load
+    pub fn load() -> Self {
+        let contents = match get_save_path().and_then(fs::read_to_string) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return TagUsage::default(),
+            Err(err) => {
+                error!("[TagUsage::load()] Failed to read tag usage:\n {}", err);
+                return TagUsage::default();
+            }
+        };
+
+        match SerTagUsage::deserialize_json(&contents) {
+            Ok(ser) => ser.into(),
+            Err(err) => {
+                error!("[TagUsage::load()] Failed to parse tag usage:\n {}", err);
+                TagUsage::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = get_save_path()?;
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+
+        let string = SerTagUsage::from(self).serialize_json();
+        File::create(path)?
+            .write_all(string.as_bytes())?;
+        Ok(())
+    }
+
+    /// Record a use of `id` right now
+    #[inline]
+    pub fn record(&mut self, id: &TagID) {
+        self.record_at(id, now());
+    }
+
+    fn record_at(&mut self, id: &TagID, time: u64) {
+        let record = self.0.entry(id.clone()).or_default();
+        record.count = record.count.saturating_add(1);
+        record.last_used = record.last_used.max(time);
+    }
+
+    /// Load the history, record a use of each of `ids`, and save it back
+    pub fn record_and_save<'a, I>(ids: I) -> io::Result<()>
+    where I: IntoIterator<Item = &'a TagID>,
+    {
+        let mut usage = TagUsage::load();
+        for id in ids {
+            usage.record(id);
+        }
+        usage.save()
+    }
+
+    /// Get how much `id` is used, from `0.0` (never) to `1.0` (often and recently)
+    #[inline]
+    pub fn frecency(&self, id: &TagID) -> f32 {
+        self.frecency_at(id, now())
+    }
+
+    fn frecency_at(&self, id: &TagID, time: u64) -> f32 {
+        let Some(record) = self.0.get(id) else {
+            return 0.0;
+        };
+
+        let frequency = ((record.count as f32).ln_1p() / FREQUENT_USE_COUNT.ln_1p())
+            .min(1.0);
+        let days = time.saturating_sub(record.last_used) as f32 / SECONDS_PER_DAY;
+        let recency = 0.5f32.powf(days / RECENCY_HALF_LIFE_DAYS);
+        frequency * recency
+    }
+
+    /// Get the bonus to add to the match score of `id`, up to [`MAX_USAGE_BONUS`]
+    #[inline]
+    pub fn bonus(&self, id: &TagID) -> isize {
+        (self.frecency(id) * MAX_USAGE_BONUS as f32).round() as isize
+    }
+
+    /// Sort `items` in place, most used first
+    /// The sort is stable, so unused items keep their relative order
+    pub fn sort<T, F>(&self, items: &mut [T], get_id: F)
+    where F: Fn(&T) -> &TagID,
+    {
+        let time = now();
+        items.sort_by_cached_key(|item| {
+            std::cmp::Reverse((self.frecency_at(get_id(item), time) * 1000.0) as u32)
+        });
+    }
+
+    /// Move the history of `old` to `new`, e.g. after renaming a tag
+    /// Returns whether `old` had any history
+    pub fn rename(&mut self, old: &TagID, new: &TagID) -> bool {
+        let Some(record) = self.0.remove(old) else {
+            return false;
+        };
+        self.0.insert(new.clone(), record);
+        true
+    }
+}
+
+
+
+#[derive(Debug, Clone, SerJson, DeJson)]
+struct SerTagUsage {
+    records: Vec<SerRecord>,
+}
+
+#[derive(Debug, Clone, SerJson, DeJson)]
+struct SerRecord {
+    id: String,
+    count: u32,
+    last_used: u64,
+}
+
+impl From<&TagUsage> for SerTagUsage {
+    fn from(value: &TagUsage) -> Self {
+        SerTagUsage {
+            records: value.0.iter()
+                .map(|(id, r)| SerRecord {
+                    id: id.0.clone(),
+                    count: r.count,
+                    last_used: r.last_used,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<SerTagUsage> for TagUsage {
+    fn from(value: SerTagUsage) -> Self {
+        TagUsage(value.records.into_iter()
+            .map(|r| (TagID(r.id), Record { count: r.count, last_used: r.last_used }))
+            .collect())
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::tagging::id::TagID;
+
+    use super::{TagUsage, SECONDS_PER_DAY};
+
+    #[test]
+    fn ranking() {
+        let day = SECONDS_PER_DAY as u64;
+        let now = 1000 * day;
+        let projects = TagID::new("projects");
+        let pets = TagID::new("pets");
+        let old = TagID::new("old");
+
+        let mut usage = TagUsage::default();
+        for i in 0..20 {
+            usage.record_at(&projects, now - i * day);
+        }
+        usage.record_at(&pets, now - day);
+        for _ in 0..20 {
+            usage.record_at(&old, now - 90 * day);
+        }
+
+        let projects_score = usage.frecency_at(&projects, now);
+        let pets_score = usage.frecency_at(&pets, now);
+        let old_score = usage.frecency_at(&old, now);
+        assert!(projects_score > pets_score);
+        assert!(pets_score > old_score);
+        assert!(projects_score <= 1.0);
+        assert_eq!(usage.frecency_at(&TagID::new("never"), now), 0.0);
+
+        assert!(usage.rename(&pets, &TagID::new("animals")));
+        assert_eq!(usage.frecency_at(&pets, now), 0.0);
+    }
+}
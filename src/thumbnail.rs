@@ -290,6 +290,8 @@ pub mod icons {
 
     use iced::widget::image::Handle;
 
+    use crate::kind;
+
     use super::is_file_supported;
 
     pub const ICONS_PATH: &str = "assets/icons/";
@@ -300,13 +302,20 @@ pub mod icons {
     // TODO optimize this shit
     static EXTENSIONS_CACHE: OnceLock<HashMap<OsString, PathBuf>> = OnceLock::new();
 
+    /// Icon for `path`, looked up in this order:
+    /// 1. The icon for its extension
+    /// 2. The icon for another extension of the same kind (see [`get_icon_for_kind`])
+    /// 3. The generic file or folder icon
     pub fn load_icon_for_path(path: &Path) -> Handle {
         if path.is_dir() {
             return Handle::from_path(FOLDER_ICON_PATH);
         }
 
         path.extension()
-            .and_then(|osstr| get_icon_for_extension(osstr))
+            .and_then(|osstr| get_icon_for_extension(osstr)
+                // Files aren't sniffed here, since this runs on every redraw
+                .or_else(|| kind::table().kind_of_extension(&osstr.to_string_lossy()).and_then(get_icon_for_kind))
+            )
             .map_or_else(
                 || Handle::from_path(FILE_ICON_PATH),
                 |p| Handle::from_path(p)
@@ -317,6 +326,13 @@ pub mod icons {
         get_cache().get( &extension.to_ascii_lowercase() )
     }
 
+    /// Icon for files of `kind`: that of the first of its extensions with an icon
+    /// There are no icons for whole kinds, so e.g. `.webp` files get the `.png` icon
+    pub fn get_icon_for_kind(kind: &str) -> Option<&'static PathBuf> {
+        kind::table().extensions_of(kind).iter()
+            .find_map(|ext| get_icon_for_extension(OsStr::new(ext)))
+    }
+
     fn get_cache() -> &'static HashMap<OsString, PathBuf> {
         // EXTENSIONS_CACHE.get_or_init(|| HashMap::new())
 
@@ -0,0 +1,210 @@
+//! Moving files to the system's trash (or recycle bin) instead of deleting them for good
+
+use std::io;
+use std::path::Path;
+
+
+
+/// Move `path` to the trash, so it can still be restored from the file explorer
+pub fn move_to_trash(path: &Path) -> io::Result<()> {
+    let path = path.canonicalize()?;
+    platform::move_to_trash(&path)
+}
+
+
+
+#[cfg(windows)]
+mod platform {
+    use std::io;
+    use std::path::Path;
+    use std::process::Command;
+
+    /// Windows has no command for this, so this goes through PowerShell
+    pub fn move_to_trash(path: &Path) -> io::Result<()> {
+        let method = if path.is_dir() { "DeleteDirectory" } else { "DeleteFile" };
+        // Single quotes are escaped by doubling them
+        let pathstr = path.to_string_lossy().replace('\'', "''");
+        let script = format!(
+            "Add-Type -AssemblyName Microsoft.VisualBasic; \
+            [Microsoft.VisualBasic.FileIO.FileSystem]::{method}('{pathstr}', 'OnlyErrorDialogs', 'SendToRecycleBin')"
+        );
+
+        let output = Command::new("powershell")
+            .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+            .output()?;
+        super::check_output(output)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::io;
+    use std::path::Path;
+    use std::process::Command;
+
+    pub fn move_to_trash(path: &Path) -> io::Result<()> {
+        let pathstr = path.to_string_lossy()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        let script = format!("tell application \"Finder\" to delete POSIX file \"{pathstr}\"");
+
+        let output = Command::new("osascript")
+            .args(["-e", &script])
+            .output()?;
+        super::check_output(output)
+    }
+}
+
+/// Follows the freedesktop.org trash specification
+/// Files on other drives than the home folder go to the trash at the top of their drive, since
+/// they can't be moved across filesystems
+/// See <https://specifications.freedesktop.org/trash-spec/latest/>
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use std::fs::{self, create_dir_all, DirBuilder, OpenOptions};
+    use std::io::{self, Write};
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+    use std::path::{Path, PathBuf};
+
+    use directories::BaseDirs;
+
+    pub fn move_to_trash(path: &Path) -> io::Result<()> {
+        let home_trash_dir = get_trash_dir()?;
+        // Paths in the trash of a drive are relative to the top of the drive
+        let (trash_dir, top_dir) = if is_same_device(path, &home_trash_dir)? {
+            (home_trash_dir, None)
+        } else {
+            let top_dir = get_top_dir(path)?;
+            let trash_dir = get_top_trash_dir(&top_dir).map_err(|err| io::Error::new(
+                err.kind(),
+                format!("can't move to the trash across filesystems, and {} has no trash of its own: {err}", top_dir.display()),
+            ))?;
+            (trash_dir, Some(top_dir))
+        };
+        let files_dir = trash_dir.join("files");
+        let info_dir = trash_dir.join("info");
+        create_dir_all(&files_dir)?;
+        create_dir_all(&info_dir)?;
+
+        let name = path.file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?
+            .to_string_lossy()
+            .to_string();
+
+        // The info file is created first, so that the name is reserved
+        let (trashed_name, mut info_file) = (0..)
+            .map(|i| if i == 0 { name.clone() } else { format!("{name}.{i}") })
+            .find_map(|trashed_name| {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(info_dir.join(format!("{trashed_name}.trashinfo")))
+                    .ok()?;
+                Some((trashed_name, file))
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::AlreadyExists, "no free name in trash"))?;
+        let info_path = info_dir.join(format!("{trashed_name}.trashinfo"));
+
+        let deletion_date = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S");
+        let res = write!(
+            info_file,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode_path(top_dir.as_ref().and_then(|dir| path.strip_prefix(dir).ok()).unwrap_or(path)),
+            deletion_date
+        )
+        .and_then(|_| fs::rename(path, files_dir.join(&trashed_name)));
+
+        if res.is_err() {
+            let _ = fs::remove_file(info_path);
+        }
+        res
+    }
+
+    fn get_trash_dir() -> io::Result<PathBuf> {
+        if let Some(data_home) = std::env::var_os("XDG_DATA_HOME").filter(|s| !s.is_empty()) {
+            return Ok(PathBuf::from(data_home).join("Trash"));
+        }
+        BaseDirs::new()
+            .map(|dirs| dirs.data_dir().join("Trash"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "home directory not found"))
+    }
+
+    /// Returns whether `path` is on the same filesystem as `trash_dir`, which may not exist yet
+    fn is_same_device(path: &Path, trash_dir: &Path) -> io::Result<bool> {
+        let dev = fs::symlink_metadata(path)?.dev();
+        let trash_dev = trash_dir.ancestors()
+            .find_map(|dir| fs::metadata(dir).ok())
+            .map(|meta| meta.dev());
+        Ok(trash_dev == Some(dev))
+    }
+
+    /// Get the top of the filesystem `path` is on, by going up until the device changes
+    fn get_top_dir(path: &Path) -> io::Result<PathBuf> {
+        let dev = fs::symlink_metadata(path)?.dev();
+        let mut top_dir = path;
+        while let Some(parent) = top_dir.parent() {
+            if fs::metadata(parent)?.dev() != dev {
+                break;
+            }
+            top_dir = parent;
+        }
+        Ok(top_dir.to_path_buf())
+    }
+
+    /// Get the trash at the top of a drive: `$topdir/.Trash/$uid` if the drive has a shared
+    /// `.Trash` folder, or `$topdir/.Trash-$uid` otherwise
+    fn get_top_trash_dir(top_dir: &Path) -> io::Result<PathBuf> {
+        let uid = get_uid()?;
+
+        // Shared trashes must have the sticky bit set and not be links, otherwise they're skipped
+        let shared_dir = top_dir.join(".Trash");
+        let is_shared_valid = fs::symlink_metadata(&shared_dir)
+            .is_ok_and(|meta| meta.is_dir() && meta.mode() & 0o1000 != 0);
+        if is_shared_valid {
+            let trash_dir = shared_dir.join(uid.to_string());
+            if create_dir_all(&trash_dir).is_ok() {
+                return Ok(trash_dir);
+            }
+        }
+
+        let trash_dir = top_dir.join(format!(".Trash-{uid}"));
+        match fs::symlink_metadata(&trash_dir) {
+            Ok(meta) if meta.is_dir() && meta.uid() == uid => {},
+            Ok(_) => return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is not a folder of the current user", trash_dir.display()),
+            )),
+            Err(_) => DirBuilder::new().mode(0o700).create(&trash_dir)?,
+        }
+        Ok(trash_dir)
+    }
+
+    /// The home folder belongs to the current user, so its owner is the user ID
+    fn get_uid() -> io::Result<u32> {
+        let home_dir = BaseDirs::new()
+            .map(|dirs| dirs.home_dir().to_path_buf())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "home directory not found"))?;
+        Ok(fs::metadata(home_dir)?.uid())
+    }
+
+    /// Percent-encode `path` for the `Path=` key of `.trashinfo` files
+    fn encode_path(path: &Path) -> String {
+        use std::os::unix::ffi::OsStrExt;
+
+        path.as_os_str().as_bytes().iter()
+            .map(|&b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+                _ => format!("%{b:02X}"),
+            })
+            .collect()
+    }
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+fn check_output(output: std::process::Output) -> io::Result<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(io::Error::new(io::ErrorKind::Other, stderr.trim().to_string()))
+}
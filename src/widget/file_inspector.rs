@@ -1,40 +1,58 @@
 use std::borrow::Cow;
-use std::marker::PhantomData;
 use std::path::PathBuf;
 
-use iced::{widget, Element, Length};
-use iced::widget::{column, component, container, horizontal_rule, scrollable, text, Component, Container};
-use iced_aw::{grid, grid_row};
+use iced::{widget, Alignment, Element, Length};
+use iced::widget::{column, component, container, horizontal_rule, horizontal_space, row, scrollable, text, text_input, Column, Component, Container};
+use iced_aw::{grid, grid_row, Bootstrap};
 use iced_aw::widgets::Grid;
 
+use crate::tagging::annotation::Annotation;
 use crate::tagging::id::TagID;
 use crate::tagging::tag::Tag;
-use crate::ToPrettyString;
+use crate::widget::rating::rating_stars;
+use crate::{simple_button, ToPrettyString};
+
+/// Callback for when a file's annotation in a tag is edited
+type OnAnnotationChanged<'a, Message> = Box<dyn Fn(TagID, PathBuf, Annotation) -> Message + 'a>;
 
 
 
 #[derive(Debug, Clone)]
-pub enum Event {}
+pub enum Event {
+    NoteInput(usize, String),
+    NoteSubmit,
+    RatingChanged(usize, Option<u8>),
+    Close,
+}
 
 #[derive(Debug, Default)]
-pub struct State {}
+pub struct State {
+    /// Note being edited: the inspected path, index of the tag, and the edited note
+    editing_note: Option<(PathBuf, usize, String)>,
+}
 
 
 
-pub struct FileInspector<Message: Clone> {
+/// Displays info about a path, along with its [`Annotation`]s in every [`Tag`] containing it
+pub struct FileInspector<'a, Message: Clone> {
     path: PathBuf,
     width: Length,
     height: Length,
-    tags: Vec<TagID>,
-    s: PhantomData<Message>,
+    /// Tags containing [`FileInspector::path`], and its annotation in each
+    tags: Vec<(TagID, Annotation)>,
+    on_annotation_changed: Option<OnAnnotationChanged<'a, Message>>,
+    on_close: Option<Message>,
 }
 
 
-impl<Message: Clone> FileInspector<Message> {
+impl<'a, Message: Clone> FileInspector<'a, Message> {
     pub fn new(path: PathBuf, all_tags: &[Tag]) -> Self {
-        let tags: Vec<TagID> = all_tags.iter()
+        let tags: Vec<(TagID, Annotation)> = all_tags.iter()
             .filter(|t| t.contains(&path))
-            .map(|t| t.id.clone())
+            .map(|t| (
+                t.id.clone(),
+                t.get_annotation(&path).cloned().unwrap_or_default(),
+            ))
             .collect();
 
         FileInspector {
@@ -42,10 +60,29 @@ impl<Message: Clone> FileInspector<Message> {
             width: Length::Shrink,
             height: Length::Fill,
             tags,
-            s: PhantomData,
+            on_annotation_changed: None,
+            on_close: None,
         }
     }
 
+    pub fn width(mut self, width: impl Into<Length>) -> Self {
+        self.width = width.into();
+        self
+    }
+
+    /// Makes annotations editable, calling `f` with the tag, path, and new annotation on change
+    pub fn on_annotation_changed<F>(mut self, f: F) -> Self
+    where F: 'a + Fn(TagID, PathBuf, Annotation) -> Message,
+    {
+        self.on_annotation_changed = Some(Box::new(f));
+        self
+    }
+
+    pub fn on_close(mut self, message: Message) -> Self {
+        self.on_close = Some(message);
+        self
+    }
+
     fn view_info(&self) -> Container<Event> {
         container(
             grid![
@@ -53,10 +90,52 @@ impl<Message: Clone> FileInspector<Message> {
             ]
         )
     }
+
+    fn view_annotations(&self, state: &State) -> Column<Event> {
+        let is_editable = self.on_annotation_changed.is_some();
+
+        column(self.tags.iter().enumerate().map(|(i, (tag_id, annotation))| {
+            let note: &str = match &state.editing_note {
+                Some((path, index, note)) if *index == i && path == &self.path => note,
+                _ => &annotation.note,
+            };
+
+            let note_widget: Element<Event> = if is_editable {
+                text_input("Note...", note)
+                    .on_input(move |str| Event::NoteInput(i, str))
+                    .on_submit(Event::NoteSubmit)
+                    .into()
+            } else {
+                text(note).into()
+            };
+
+            column![
+                row![
+                    text(tag_id),
+                    rating_stars(
+                        annotation.rating,
+                        is_editable.then_some(move |rating| Event::RatingChanged(i, rating))
+                    ),
+                ]
+                .spacing(8)
+                .align_items(Alignment::Center),
+                note_widget,
+            ]
+            .spacing(4)
+            .into()
+        }))
+        .spacing(12)
+    }
+
+    fn annotation_changed(&self, index: usize, annotation: Annotation) -> Option<Message> {
+        let callback = self.on_annotation_changed.as_ref()?;
+        let (tag_id, _) = self.tags.get(index)?;
+        Some(callback(tag_id.clone(), self.path.clone(), annotation))
+    }
 }
 
 
-impl<Message: Clone> Component<Message> for FileInspector<Message> {
+impl<'a, Message: Clone> Component<Message> for FileInspector<'a, Message> {
     type State = State;
     type Event = Event;
 
@@ -65,7 +144,30 @@ impl<Message: Clone> Component<Message> for FileInspector<Message> {
         state: &mut Self::State,
         event: Self::Event,
     ) -> Option<Message> {
-        None
+        match event {
+            Event::NoteInput(index, str) => {
+                state.editing_note = Some((self.path.clone(), index, str));
+                None
+            }
+
+            Event::NoteSubmit => {
+                let (path, index, note) = state.editing_note.take()?;
+                if path != self.path {
+                    return None;
+                }
+                let mut annotation = self.tags.get(index)?.1.clone();
+                annotation.note = note;
+                self.annotation_changed(index, annotation)
+            }
+
+            Event::RatingChanged(index, rating) => {
+                let mut annotation = self.tags.get(index)?.1.clone();
+                annotation.set_rating(rating);
+                self.annotation_changed(index, annotation)
+            }
+
+            Event::Close => self.on_close.clone(),
+        }
     }
 
     fn view(
@@ -83,11 +185,22 @@ impl<Message: Clone> Component<Message> for FileInspector<Message> {
         container(
             scrollable(
                 column![
-                    text( file_name ),
+                    row![ text( file_name ), horizontal_space() ]
+                        .push_maybe(self.on_close.is_some().then(||
+                            simple_button!(icon = Bootstrap::X) .on_press(Event::Close)
+                        ))
+                        .align_items(Alignment::Center),
                     horizontal_rule(2),
 
-                    self.view_info()
+                    self.view_info(),
+                ]
+                .push_maybe((!self.tags.is_empty()).then(|| column![
+                    horizontal_rule(2),
+                    text("Notes") .size(18),
+                    self.view_annotations(state),
                 ]
+                .spacing(8)))
+                .spacing(8)
             )
             .direction(scrollable::Direction::Both { vertical: scroll_properties, horizontal: scroll_properties })
         )
@@ -99,11 +212,11 @@ impl<Message: Clone> Component<Message> for FileInspector<Message> {
 
 
 
-impl<'a, Message> From<FileInspector<Message>> for Element<'a, Message>
-where 
+impl<'a, Message> From<FileInspector<'a, Message>> for Element<'a, Message>
+where
     Message: 'a + Clone,
 {
-    fn from(value: FileInspector<Message>) -> Self {
+    fn from(value: FileInspector<'a, Message>) -> Self {
         component(value)
     }
 }
@@ -17,7 +17,10 @@ use iced::widget::{
     text_input, TextInput,
 };
 
-use crate::strmatch::{self, StringMatcher};
+use crate::strmatch::{self, CaseMode, StringMatcher};
+
+/// Extra score added to an option's match score
+type ScoreBonus<'a, T> = Box<dyn Fn(&T) -> isize + 'a>;
 
 
 /// [`TextInput`] extension widget that can fuzzy search from a list of options
@@ -59,6 +62,8 @@ where
     options: &'a [T],
     on_selected: Box<dyn Fn(T) -> Message + 'a>,
     on_hovered: Option<Box<dyn Fn(T) -> Message + 'a>>,
+    /// Extra score added to each option's match score
+    score_bonus: Option<ScoreBonus<'a, T>>,
     query: String,
     overlay_style: <Theme as menu::StyleSheet>::Style,
     /// Whether to show options if text input is empty
@@ -90,6 +95,7 @@ where
             options,
             on_selected: Box::new(on_selected),
             on_hovered: None,
+            score_bonus: None,
             query: text.to_string(),
             overlay_style: <Theme as menu::StyleSheet>::Style::default(),
             hide_on_empty: false,
@@ -112,6 +118,16 @@ where
         self
     }
 
+    /// Add `f(option)` to the match score of each option when filtering
+    /// Useful to rank options that are used more often higher
+    pub fn score_bonus<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&T) -> isize + 'a
+    {
+        self.score_bonus = Some(Box::new(f));
+        self
+    }
+
     pub fn style(
         mut self,
         style: impl Into<<Theme as menu::StyleSheet>::Style>,
@@ -135,10 +151,14 @@ where
             return None;
         }
 
-        let matcher = strmatch::Sublime::default() .with_query(query);
+        // Options like tag IDs are lowercase, so typing `Music` should still find `music`
+        let matcher = strmatch::Sublime::default()
+            .with_query(query)
+            .with_case(CaseMode::Insensitive);
         let mut matches: Vec<(&T, isize)> = self.options.iter()
             .filter_map(|opt| {
-                matcher.score( &opt.to_string() ) .map(|score| (opt, score))
+                let bonus = self.score_bonus.as_ref().map_or(0, |f| f(opt));
+                matcher.score( &opt.to_string() ) .map(|score| (opt, score + bonus))
             })
             .collect();
         matches.sort_by_key(|(_opt, score)| Reverse(*score));
@@ -5,6 +5,7 @@ pub mod context_menu;
 pub mod notification_card;
 pub mod file_inspector;
 pub mod file_list;
+pub mod rating;
 
 
 /// Create a [`iced::widget::Text`] widget with the given [`Bootstrap`] icon
@@ -0,0 +1,35 @@
+use iced::widget::{button, row, Row};
+use iced_aw::Bootstrap;
+
+use crate::app::theme;
+use crate::icon;
+use crate::tagging::annotation::Annotation;
+
+
+/// Create a row of stars displaying an [`Annotation`] rating
+/// If `on_change` is set, the stars can be clicked to change the rating
+/// Clicking the current rating clears it
+pub fn rating_stars<'a, Message, F>(rating: Option<u8>, on_change: Option<F>) -> Row<'a, Message>
+where
+    Message: Clone + 'a,
+    F: Fn(Option<u8>) -> Message,
+{
+    let current: u8 = rating.unwrap_or(0);
+
+    row((1..=Annotation::MAX_RATING).map(|i| {
+        let star = if i <= current {
+            icon!(Bootstrap::StarFill, theme::WARNING_COLOR)
+        } else {
+            icon!(Bootstrap::Star, light)
+        };
+
+        button(star)
+            .on_press_maybe(on_change.as_ref().map(|f|
+                f( (i != current).then_some(i) )
+            ))
+            .style(iced::theme::Button::Text)
+            .padding(0)
+            .into()
+    }))
+    .spacing(2)
+}
@@ -6,6 +6,7 @@ use iced_aw::Bootstrap;
 
 use crate::app::theme;
 use crate::tagging::id::TagID;
+use crate::tagging::stats::TagStats;
 use crate::tagging::Tag;
 use crate::ToPrettyString;
 use crate::{ icon, simple_button };
@@ -40,6 +41,8 @@ pub enum Event {
     ToggleExpand,
     EditPressed,
     SubtagPressed(usize),
+    PinToggled,
+    Move(isize),
 }
 
 #[derive(Debug, Default)]
@@ -53,9 +56,15 @@ pub struct State {
 /// Displays surface level info about a [`Tag`]:
 /// - Its name
 /// - Entries under it along with an icon for any errors with them
+/// - Optionally, its [`TagStats`]
 /// - Optionally, an edit button
+/// - Optionally, pin and move buttons
 pub struct TagEntry<'a, Message: Clone> {
     tag: &'a Tag,
+    stats: Option<&'a TagStats>,
+    is_pinned: bool,
+    on_pin_toggled: Option<Message>,
+    on_move: Option< Box<dyn Fn(isize) -> Message + 'a > >,
     on_edit_pressed: Option<Message>,
     on_subtag_pressed: Option< Box<dyn Fn(TagID) -> Message + 'a > >,
     /// Cache containing the indices of entries that don't exist
@@ -66,6 +75,10 @@ impl<'a, Message: Clone> TagEntry<'a, Message> {
     pub fn new(tag: &'a Tag) -> Self {
         TagEntry {
             tag,
+            stats: None,
+            is_pinned: false,
+            on_pin_toggled: None,
+            on_move: None,
             on_edit_pressed: None,
             on_subtag_pressed: None,
             erroneous_entries: tag.entries.as_ref().iter().enumerate()
@@ -75,6 +88,27 @@ impl<'a, Message: Clone> TagEntry<'a, Message> {
         }
     }
 
+    /// Show the given [`TagStats`], if any
+    pub fn stats(mut self, stats: Option<&'a TagStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Show a pin button, highlighted if `is_pinned`
+    pub fn pinned(mut self, is_pinned: bool, on_toggled: Message) -> Self {
+        self.is_pinned = is_pinned;
+        self.on_pin_toggled = Some(on_toggled);
+        self
+    }
+
+    /// Show buttons to move this tag up (`-1`) or down (`1`)
+    pub fn on_move<F>(mut self, f: F) -> Self
+    where F: 'a + Fn(isize) -> Message,
+    {
+        self.on_move = Some(Box::new(f));
+        self
+    }
+
     pub fn on_edit_pressed(mut self, message: Message) -> Self {
         self.on_edit_pressed = Some(message);
         self
@@ -88,6 +122,22 @@ impl<'a, Message: Clone> TagEntry<'a, Message> {
     }
 
     fn view_contents(&self, _state: &State) -> Column<Event> {
+        Column::new()
+            .push_maybe(self.stats.and_then(|stats| {
+                let modified = stats.newest_modified_string()?;
+                Some(text(format!("Last modified: {modified}")) .size(12) .style(ENTRY_COLOR))
+            }))
+            .push_maybe(self.stats.filter(|stats| stats.broken_entries > 0).map(|stats|
+                text(format!("{} broken entries (including subtags)", stats.broken_entries))
+                    .size(12)
+                    .style(theme::WARNING_COLOR)
+            ))
+            .push(self.view_entries())
+            .spacing(8.0)
+            .padding([0, 24])
+    }
+
+    fn view_entries(&self) -> Column<Event> {
         column(
             self.tag.entries.as_ref().iter().enumerate()
                 .map(|(i, pb)| Row::new()
@@ -101,7 +151,6 @@ impl<'a, Message: Clone> TagEntry<'a, Message> {
                 )
         )
         .spacing(8.0)
-        .padding([0, 24])
     }
 
     fn view_top_bar(&self, state: &State) -> Container<Event> {
@@ -122,12 +171,27 @@ impl<'a, Message: Clone> TagEntry<'a, Message> {
                 icon!(Bootstrap::ExclamationCircleFill, theme::WARNING_COLOR)
             ))
 
+            // Stats, if any
+            .push_maybe(self.stats.map(|stats|
+                text(stats.summary()) .size(12) .style(ENTRY_COLOR)
+            ))
+
             // Subtags, if enabled
             .push_maybe(self.on_subtag_pressed.is_some()
                 .then(|| self.view_subtags_list(state))
             )
 
             .push(horizontal_space())
+            // Move buttons
+            .push_maybe(self.on_move.is_some().then(|| row![
+                simple_button!(icon = Bootstrap::ArrowUp) .on_press(Event::Move(-1)),
+                simple_button!(icon = Bootstrap::ArrowDown) .on_press(Event::Move(1)),
+            ]))
+            // Pin button
+            .push_maybe(self.on_pin_toggled.is_some().then(||
+                simple_button!(icon = if self.is_pinned { Bootstrap::PinFill } else { Bootstrap::Pin })
+                    .on_press(Event::PinToggled)
+            ))
             // Edit button
             .push_maybe(self.on_edit_pressed.is_some().then(||
                 simple_button!(icon = Bootstrap::PencilSquare)
@@ -191,6 +255,15 @@ impl<'a, Message: Clone> Component<Message> for TagEntry<'a, Message> {
                 self.on_edit_pressed.clone()
             }
 
+            Event::PinToggled => {
+                self.on_pin_toggled.clone()
+            }
+
+            Event::Move(offset) => {
+                let callback = self.on_move.as_ref()?;
+                Some(callback(offset))
+            }
+
             Event::SubtagPressed(index) => {
                 let callback = self.on_subtag_pressed.as_ref()?;
                 let tag_id = self.tag.get_subtags().get(index)?